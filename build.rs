@@ -0,0 +1,37 @@
+use std::path::Path;
+use std::{env, fs};
+
+/// Embeds every migration in `migrations/`, oldest first, so the binary can
+/// apply and revert them without the directory being deployed alongside it.
+fn main() {
+    let dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("migrations");
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut names: Vec<String> = fs::read_dir(&dir)
+        .expect("unable to read migrations directory")
+        .map(|entry| entry.expect("unable to read migration").path())
+        .filter(|path| path.join("up.sql").is_file())
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+
+    let mut out = String::from("&[\n");
+    for name in names {
+        let path = dir.join(&name);
+        // the same version diesel's cli records, so either can be used on a
+        // database the other has migrated
+        let version = name.split('_').next().unwrap().replace('-', "");
+        out.push_str(&format!(
+            "    Migration {{ version: {:?}, name: {:?}, up: include_str!({:?}), down: include_str!({:?}) }},\n",
+            version,
+            name,
+            path.join("up.sql"),
+            path.join("down.sql"),
+        ));
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    out.push(']');
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    fs::write(out_path, out).expect("unable to write embedded migrations");
+}
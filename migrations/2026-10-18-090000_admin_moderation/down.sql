@@ -0,0 +1,7 @@
+DROP TABLE admin_actions;
+
+ALTER TABLE users
+DROP CONSTRAINT valid_role,
+DROP COLUMN role,
+DROP COLUMN suspended_until,
+DROP COLUMN banned;
@@ -0,0 +1,15 @@
+ALTER TABLE users
+ADD COLUMN role VARCHAR NOT NULL DEFAULT 'user',
+ADD COLUMN suspended_until TIMESTAMP,
+ADD COLUMN banned BOOLEAN NOT NULL DEFAULT FALSE,
+ADD CONSTRAINT valid_role
+    CHECK (role IN ('user', 'admin'));
+
+CREATE TABLE admin_actions (
+    id SERIAL PRIMARY KEY,
+    admin VARCHAR NOT NULL,
+    action VARCHAR NOT NULL,
+    target VARCHAR NOT NULL,
+    details VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
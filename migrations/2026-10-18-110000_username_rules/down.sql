@@ -0,0 +1,4 @@
+ALTER TABLE users
+DROP CONSTRAINT valid_username;
+
+DROP INDEX users_username_lower_idx;
@@ -0,0 +1,62 @@
+-- usernames used to be unique only as typed, so accounts can differ just in
+-- case. The first in each group keeps its name and the rest get a numbered
+-- suffix (Alice -> Alice-2), listed in the notices, so the index can be built.
+-- A suffix is skipped if the name it makes is already taken ignoring case.
+CREATE TEMPORARY TABLE username_renames (
+    old VARCHAR PRIMARY KEY,
+    new VARCHAR NOT NULL
+) ON COMMIT DROP;
+
+DO $$
+DECLARE
+    r RECORD;
+    n BIGINT;
+BEGIN
+    FOR r IN
+        SELECT username, rank
+        FROM (
+            SELECT username, row_number() OVER (PARTITION BY lower(username) ORDER BY username COLLATE "C") AS rank
+            FROM users
+        ) ranked
+        WHERE rank > 1
+        ORDER BY lower(username), rank
+    LOOP
+        n := r.rank;
+        WHILE EXISTS (SELECT 1 FROM users WHERE lower(username) = lower(r.username || '-' || n))
+           OR EXISTS (SELECT 1 FROM username_renames WHERE lower(new) = lower(r.username || '-' || n))
+        LOOP
+            n := n + 1;
+        END LOOP;
+        INSERT INTO username_renames VALUES (r.username, r.username || '-' || n);
+        RAISE NOTICE 'renaming user % to % as the name is taken ignoring case', r.username, r.username || '-' || n;
+    END LOOP;
+END;
+$$;
+
+-- the foreign keys don't cascade, so the renamed accounts are copied, their
+-- swipes and matches moved over, and then the originals deleted
+INSERT INTO users (username, password, lat, long, bio, profile_pic, role, suspended_until, banned, discipline)
+SELECT r.new, u.password, u.lat, u.long, u.bio, u.profile_pic, u.role, u.suspended_until, u.banned, u.discipline
+FROM users u JOIN username_renames r ON u.username = r.old;
+
+UPDATE swipes SET swiper = r.new FROM username_renames r WHERE swiper = r.old;
+UPDATE swipes SET swiped = r.new FROM username_renames r WHERE swiped = r.old;
+
+UPDATE matches SET (username1, username2) = (
+    SELECT LEAST(a, b), GREATEST(a, b)
+    FROM (
+        SELECT COALESCE((SELECT new FROM username_renames WHERE old = username1), username1) AS a,
+               COALESCE((SELECT new FROM username_renames WHERE old = username2), username2) AS b
+    ) renamed
+)
+WHERE username1 IN (SELECT old FROM username_renames)
+   OR username2 IN (SELECT old FROM username_renames);
+
+DELETE FROM users WHERE username IN (SELECT old FROM username_renames);
+
+CREATE UNIQUE INDEX users_username_lower_idx ON users (lower(username));
+
+-- existing accounts are grandfathered in, only new usernames are checked
+ALTER TABLE users
+ADD CONSTRAINT valid_username
+    CHECK (username ~ '^[A-Za-z0-9][A-Za-z0-9_-]{2,31}$') NOT VALID;
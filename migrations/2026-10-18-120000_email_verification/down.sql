@@ -0,0 +1,7 @@
+DROP TABLE email_verifications;
+
+DROP INDEX users_email_lower_idx;
+
+ALTER TABLE users
+DROP COLUMN email,
+DROP COLUMN email_verified;
@@ -0,0 +1,20 @@
+ALTER TABLE users
+ADD COLUMN email VARCHAR,
+ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- accounts from before emails were collected have nothing to verify, so they
+-- stay visible rather than disappearing from everyone's swipe queue
+UPDATE users SET email_verified = TRUE;
+
+CREATE UNIQUE INDEX users_email_lower_idx ON users (lower(email));
+
+CREATE TABLE email_verifications (
+    token_hash VARCHAR NOT NULL PRIMARY KEY,
+    username VARCHAR NOT NULL,
+    email VARCHAR NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    CONSTRAINT username_fk
+        FOREIGN KEY(username)
+            REFERENCES users(username)
+            ON DELETE CASCADE
+);
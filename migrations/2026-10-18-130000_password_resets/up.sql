@@ -0,0 +1,10 @@
+CREATE TABLE password_resets (
+    token_hash VARCHAR NOT NULL PRIMARY KEY,
+    username VARCHAR NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    CONSTRAINT username_fk
+        FOREIGN KEY(username)
+            REFERENCES users(username)
+            ON DELETE CASCADE
+);
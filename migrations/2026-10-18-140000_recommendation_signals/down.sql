@@ -0,0 +1,3 @@
+DROP INDEX swipes_swiped_idx;
+
+ALTER TABLE users DROP COLUMN last_active_at;
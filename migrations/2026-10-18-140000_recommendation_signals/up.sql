@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN last_active_at TIMESTAMP;
+
+-- for counting the right swipes each candidate has received
+CREATE INDEX swipes_swiped_idx ON swipes (swiped);
@@ -0,0 +1,6 @@
+-- super-likes become plain right swipes
+ALTER TABLE swipes RENAME COLUMN kind TO status;
+ALTER TABLE swipes
+    ALTER COLUMN status TYPE BOOLEAN USING status <> 'pass';
+
+DROP TYPE swipe_kind;
@@ -0,0 +1,6 @@
+CREATE TYPE swipe_kind AS ENUM ('pass', 'like', 'super');
+
+ALTER TABLE swipes
+    ALTER COLUMN status TYPE swipe_kind
+        USING CASE WHEN status THEN 'like'::swipe_kind ELSE 'pass'::swipe_kind END;
+ALTER TABLE swipes RENAME COLUMN status TO kind;
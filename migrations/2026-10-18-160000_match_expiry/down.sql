@@ -0,0 +1,8 @@
+DROP TABLE match_history;
+
+DROP INDEX matches_last_activity_at_idx;
+
+ALTER TABLE matches
+DROP COLUMN created_at,
+DROP COLUMN last_activity_at,
+DROP COLUMN extended_by;
@@ -0,0 +1,28 @@
+-- existing matches count as made now, so none of them expire straight away
+ALTER TABLE matches
+ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now(),
+ADD COLUMN last_activity_at TIMESTAMP NOT NULL DEFAULT now(),
+ADD COLUMN extended_by VARCHAR;
+
+CREATE INDEX matches_last_activity_at_idx ON matches (last_activity_at);
+
+CREATE TABLE match_history (
+    id SERIAL PRIMARY KEY,
+    username1 VARCHAR NOT NULL,
+    username2 VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    last_activity_at TIMESTAMP NOT NULL,
+    extended_by VARCHAR,
+    expired_at TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT user1_fk
+        FOREIGN KEY(username1)
+            REFERENCES users(username)
+            ON DELETE CASCADE,
+    CONSTRAINT user2_fk
+        FOREIGN KEY(username2)
+            REFERENCES users(username)
+            ON DELETE CASCADE
+);
+
+CREATE INDEX match_history_username1_idx ON match_history (username1);
+CREATE INDEX match_history_username2_idx ON match_history (username2);
@@ -0,0 +1,20 @@
+CREATE TABLE jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    payload JSONB NOT NULL,
+    state VARCHAR NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at TIMESTAMP NOT NULL DEFAULT now(),
+    locked_at TIMESTAMP,
+    last_error VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    finished_at TIMESTAMP,
+    CONSTRAINT valid_state
+        CHECK (state IN ('queued', 'running', 'done', 'dead'))
+);
+
+-- for workers looking for the next job to run
+CREATE INDEX jobs_due_idx ON jobs (run_at) WHERE state IN ('queued', 'running');
+-- for admins listing jobs by state
+CREATE INDEX jobs_state_idx ON jobs (state, id);
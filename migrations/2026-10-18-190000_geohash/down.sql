@@ -0,0 +1,2 @@
+-- drops users_geohash_idx with it
+ALTER TABLE users DROP COLUMN geohash;
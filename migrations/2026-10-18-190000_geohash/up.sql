@@ -0,0 +1,48 @@
+ALTER TABLE users ADD COLUMN geohash VARCHAR(12);
+
+-- the pattern ops let prefix searches (geohash LIKE 'gcpv%') use the index
+CREATE INDEX users_geohash_idx ON users (geohash varchar_pattern_ops);
+
+-- the server works out geohashes itself from now on, this is only to fill in
+-- the locations users have already set
+CREATE FUNCTION pg_temp.geohash(lat float, long float) RETURNS VARCHAR AS $$
+DECLARE
+    alphabet CONSTANT TEXT := '0123456789bcdefghjkmnpqrstuvwxyz';
+    lat_min float := -90;
+    lat_max float := 90;
+    long_min float := -180;
+    long_max float := 180;
+    mid float;
+    hash TEXT := '';
+    idx INTEGER := 0;
+BEGIN
+    FOR bit IN 0..44 LOOP
+        idx := idx * 2;
+        IF bit % 2 = 0 THEN
+            mid := (long_min + long_max) / 2;
+            IF long >= mid THEN
+                idx := idx + 1;
+                long_min := mid;
+            ELSE
+                long_max := mid;
+            END IF;
+        ELSE
+            mid := (lat_min + lat_max) / 2;
+            IF lat >= mid THEN
+                idx := idx + 1;
+                lat_min := mid;
+            ELSE
+                lat_max := mid;
+            END IF;
+        END IF;
+        IF bit % 5 = 4 THEN
+            hash := hash || substr(alphabet, idx + 1, 1);
+            idx := 0;
+        END IF;
+    END LOOP;
+    RETURN hash;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+UPDATE users SET geohash = pg_temp.geohash(lat, long)
+WHERE lat BETWEEN -90 AND 90 AND long BETWEEN -180 AND 180;
@@ -0,0 +1,6 @@
+DROP INDEX webhook_deliveries_job_idx;
+
+ALTER TABLE webhook_deliveries
+DROP COLUMN job_id;
+
+DROP TABLE webhook_members;
@@ -0,0 +1,21 @@
+-- webhooks are registered for partner gyms, which only hear about events
+-- involving their own members
+CREATE TABLE webhook_members (
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    username VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (webhook_id, username),
+    CONSTRAINT username_fk
+        FOREIGN KEY(username)
+            REFERENCES users(username)
+            ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_members_username_idx ON webhook_members (username);
+
+-- the job that fanned an event out, so a retry of it skips the deliveries
+-- it already made
+ALTER TABLE webhook_deliveries
+ADD COLUMN job_id BIGINT;
+
+CREATE UNIQUE INDEX webhook_deliveries_job_idx ON webhook_deliveries (job_id, webhook_id);
@@ -0,0 +1,6 @@
+ALTER TABLE matches
+ALTER COLUMN created_at SET DEFAULT now(),
+ALTER COLUMN last_activity_at SET DEFAULT now();
+
+ALTER TABLE match_history
+ALTER COLUMN expired_at SET DEFAULT now();
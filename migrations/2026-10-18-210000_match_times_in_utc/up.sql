@@ -0,0 +1,8 @@
+-- the app writes and compares these in UTC, so the defaults must be UTC too
+-- rather than the database server's local time
+ALTER TABLE matches
+ALTER COLUMN created_at SET DEFAULT timezone('utc', now()),
+ALTER COLUMN last_activity_at SET DEFAULT timezone('utc', now());
+
+ALTER TABLE match_history
+ALTER COLUMN expired_at SET DEFAULT timezone('utc', now());
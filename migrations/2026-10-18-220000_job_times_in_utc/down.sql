@@ -0,0 +1,3 @@
+ALTER TABLE jobs
+ALTER COLUMN run_at SET DEFAULT now(),
+ALTER COLUMN created_at SET DEFAULT now();
@@ -0,0 +1,5 @@
+-- the workers compare these against UTC, so the defaults must be UTC too
+-- rather than the database server's local time
+ALTER TABLE jobs
+ALTER COLUMN run_at SET DEFAULT timezone('utc', now()),
+ALTER COLUMN created_at SET DEFAULT timezone('utc', now());
@@ -0,0 +1,503 @@
+//! The commands behind `fightingtinder-admin`, for operational tasks that
+//! would otherwise need raw SQL. Every command prints a table, or a JSON
+//! array of objects with `--json`. Changes are recorded in `admin_actions`
+//! like those made through the admin routes, with `cli:$USER` as the admin.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::{fs, sync::Arc};
+
+use chrono::NaiveDateTime;
+use diesel::dsl::{exists, not};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
+use r2d2_redis::RedisConnectionManager;
+use serde_json::json;
+
+use crate::cli::{failed, split_args, Error, Output};
+use crate::db::{DBUser, SwipeKind, ROLE_ADMIN};
+use crate::paths::admin::{audit, end_sessions};
+use crate::paths::email::valid_email;
+use crate::paths::password::MIN_PASSWORD_LENGTH;
+use crate::pic_cache::PicCache;
+use crate::repo::postgres::conflict_or_backend;
+use crate::repo::{MatchRepository, PgRepository, UserRepository};
+use crate::schema::{matches, password_resets, swipes, users};
+use crate::sessions::{RedisSessionStore, SessionStore};
+use crate::throttle::{LoginThrottle, RedisThrottle};
+use crate::username;
+
+pub const USAGE: &str = "usage: fightingtinder-admin [--json] <command>
+
+commands:
+  create-user <username> [--email <email>] [--admin]
+  reset-password <username>
+  ban <username> [--reason <reason>]
+  unban <username>
+  delete-user <username>
+  matches <username>
+  purge-pic-cache [<username>...]
+  rebuild
+
+create-user and reset-password read the password from stdin.";
+
+/// A parsed command line.
+#[derive(Debug, PartialEq)]
+pub enum Command<'a> {
+    CreateUser {
+        username: &'a str,
+        email: Option<&'a str>,
+        admin: bool,
+    },
+    ResetPassword(&'a str),
+    Ban {
+        username: &'a str,
+        reason: Option<&'a str>,
+    },
+    Unban(&'a str),
+    DeleteUser(&'a str),
+    Matches(&'a str),
+    PurgePicCache(Vec<&'a str>),
+    Rebuild,
+}
+
+pub fn parse<'a>(args: &[&'a str]) -> Result<Command<'a>, Error> {
+    let (command, args) = args.split_first().ok_or(Error::Usage)?;
+    let (options, switches): (&[&str], &[&str]) = match *command {
+        "create-user" => (&["--email"], &["--admin"]),
+        "ban" => (&["--reason"], &[]),
+        _ => (&[], &[]),
+    };
+    let (positional, options, switches) = split_args(args, options, switches)?;
+
+    let command = match (*command, positional.as_slice()) {
+        ("create-user", [username]) => Command::CreateUser {
+            username,
+            email: options.get("--email").copied(),
+            admin: switches.contains(&"--admin"),
+        },
+        ("reset-password", [username]) => Command::ResetPassword(username),
+        ("ban", [username]) => Command::Ban {
+            username,
+            reason: options.get("--reason").copied(),
+        },
+        ("unban", [username]) => Command::Unban(username),
+        ("delete-user", [username]) => Command::DeleteUser(username),
+        ("matches", [username]) => Command::Matches(username),
+        ("purge-pic-cache", usernames) => Command::PurgePicCache(usernames.to_vec()),
+        ("rebuild", []) => Command::Rebuild,
+        _ => return Err(Error::Usage),
+    };
+    Ok(command)
+}
+
+struct Context {
+    pg_pool: Arc<Pool<ConnectionManager<PgConnection>>>,
+    rd_pool: Arc<Pool<RedisConnectionManager>>,
+    repo: PgRepository,
+    pic_cache: PicCache,
+    session_store: Arc<dyn SessionStore>,
+    admin: String,
+}
+
+impl Context {
+    fn connect(database_url: &str, redis_url: &str) -> Result<Self, Error> {
+        let pg_pool = Arc::new(
+            Pool::builder()
+                .max_size(2)
+                .build(ConnectionManager::<PgConnection>::new(database_url))
+                .map_err(failed)?,
+        );
+        // only some commands need redis, so it isn't checked until one does
+        let rd_pool = Arc::new(
+            Pool::builder()
+                .max_size(2)
+                .min_idle(Some(0))
+                .build_unchecked(RedisConnectionManager::new(redis_url).map_err(failed)?),
+        );
+        let admin = format!(
+            "cli:{}",
+            std::env::var("USER").unwrap_or_else(|_| "unknown".to_string())
+        );
+        Ok(Context {
+            repo: PgRepository::new(Arc::clone(&pg_pool)),
+            pic_cache: PicCache::new(Arc::clone(&rd_pool)),
+            session_store: Arc::new(RedisSessionStore::new(Arc::clone(&rd_pool))),
+            pg_pool,
+            rd_pool,
+            admin,
+        })
+    }
+
+    fn conn(
+        &self,
+    ) -> Result<diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>, Error> {
+        self.pg_pool.get().map_err(failed)
+    }
+
+    /// The stored spelling of a username given in any case, as logins accept.
+    fn resolve(&self, username: &str) -> Result<String, Error> {
+        match self.repo.find_ignoring_case(username).map_err(failed)? {
+            Some(user) => Ok(user.username),
+            None => Err(failed("no such user")),
+        }
+    }
+}
+
+/// Parses and runs a command, printing its output.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json")
+        .collect();
+    let command = parse(&args)?;
+
+    let database_url =
+        dotenv::var("DATABASE_URL").map_err(|_| failed("DATABASE_URL must be set"))?;
+    let redis_url = dotenv::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_string());
+    let ctx = Context::connect(&database_url, &redis_url)?;
+
+    let output = match command {
+        Command::CreateUser {
+            username,
+            email,
+            admin,
+        } => create_user(&ctx, username, email, admin)?,
+        Command::ResetPassword(username) => reset_password(&ctx, &ctx.resolve(username)?)?,
+        Command::Ban { username, reason } => ban(&ctx, &ctx.resolve(username)?, reason)?,
+        Command::Unban(username) => unban(&ctx, &ctx.resolve(username)?)?,
+        Command::DeleteUser(username) => delete_user(&ctx, &ctx.resolve(username)?)?,
+        Command::Matches(username) => list_matches(&ctx, &ctx.resolve(username)?)?,
+        Command::PurgePicCache(usernames) => purge_pic_cache(&ctx, usernames)?,
+        Command::Rebuild => rebuild(&ctx)?,
+    };
+
+    output.print(json);
+    Ok(())
+}
+
+/// Reads a password from the first line of stdin, prompting if it's a
+/// terminal, and hashes it.
+fn read_password() -> Result<String, Error> {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        eprint!("password: ");
+        io::stderr().flush().map_err(failed)?;
+    }
+    let mut password = String::new();
+    stdin.lock().read_line(&mut password).map_err(failed)?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(failed(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    bcrypt::hash(password, 10).map_err(failed)
+}
+
+fn not_found(err: diesel::result::Error) -> Error {
+    match err {
+        diesel::NotFound => failed("no such user"),
+        err => failed(err),
+    }
+}
+
+/// Logs a user out everywhere, warning rather than failing, for commands
+/// whose main change has already been made.
+fn try_end_sessions(ctx: &Context, username: &str) -> bool {
+    match end_sessions(&ctx.session_store, &ctx.rd_pool, username) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("warning: couldn't end {}'s sessions: {}", username, err);
+            false
+        }
+    }
+}
+
+/// Accounts made here skip email verification, since no mail is sent and
+/// whoever runs the command vouches for the address.
+fn create_user(
+    ctx: &Context,
+    username: &str,
+    email: Option<&str>,
+    admin: bool,
+) -> Result<Output, Error> {
+    let username = username::normalize(username).map_err(failed)?;
+    if let Some(email) = email {
+        if !valid_email(email) {
+            return Err(failed("invalid email address"));
+        }
+    }
+    let password = read_password()?;
+
+    let mut user = DBUser::new(username, password, email.map(str::to_string));
+    user.email_verified = true;
+    if admin {
+        user.role = ROLE_ADMIN.to_string();
+    }
+
+    let conn = ctx.conn()?;
+    let user = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let user = diesel::insert_into(users::table)
+                .values(&user)
+                .get_result::<DBUser>(&conn)?;
+            audit(
+                &conn,
+                &ctx.admin,
+                "create_user",
+                &user.username,
+                Some(user.role.clone()),
+            )?;
+            Ok(user)
+        })
+        .map_err(|err| failed(conflict_or_backend(err)))?;
+
+    Ok(Output::new(&["username", "role", "email"]).row(vec![
+        json!(user.username),
+        json!(user.role),
+        json!(user.email),
+    ]))
+}
+
+fn reset_password(ctx: &Context, username: &str) -> Result<Output, Error> {
+    let password = read_password()?;
+
+    let conn = ctx.conn()?;
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let updated = diesel::update(users::table.find(username))
+            .set(users::password.eq(&password))
+            .execute(&conn)?;
+        if updated == 0 {
+            return Err(diesel::NotFound);
+        }
+        diesel::delete(
+            password_resets::table
+                .filter(password_resets::username.eq(username))
+                .filter(password_resets::used_at.is_null()),
+        )
+        .execute(&conn)?;
+        audit(&conn, &ctx.admin, "reset_password", username, None)
+    })
+    .map_err(not_found)?;
+
+    let sessions_ended = try_end_sessions(ctx, username);
+    let throttle = RedisThrottle::new(Arc::clone(&ctx.rd_pool));
+    if let Err(err) = throttle.record_success(&username.to_lowercase()) {
+        eprintln!("warning: couldn't clear failed logins: {}", err);
+    }
+
+    Ok(Output::new(&["username", "sessions_ended"])
+        .row(vec![json!(username), json!(sessions_ended)]))
+}
+
+fn ban(ctx: &Context, username: &str, reason: Option<&str>) -> Result<Output, Error> {
+    let conn = ctx.conn()?;
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let updated = diesel::update(users::table.find(username))
+            .set(users::banned.eq(true))
+            .execute(&conn)?;
+        if updated == 0 {
+            return Err(diesel::NotFound);
+        }
+        audit(
+            &conn,
+            &ctx.admin,
+            "ban",
+            username,
+            reason.map(str::to_string),
+        )
+    })
+    .map_err(not_found)?;
+
+    let sessions_ended = try_end_sessions(ctx, username);
+    Ok(
+        Output::new(&["username", "banned", "sessions_ended"]).row(vec![
+            json!(username),
+            json!(true),
+            json!(sessions_ended),
+        ]),
+    )
+}
+
+/// Lifts any ban or suspension, like the reinstate route.
+fn unban(ctx: &Context, username: &str) -> Result<Output, Error> {
+    let conn = ctx.conn()?;
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let updated = diesel::update(users::table.find(username))
+            .set((
+                users::banned.eq(false),
+                users::suspended_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute(&conn)?;
+        if updated == 0 {
+            return Err(diesel::NotFound);
+        }
+        audit(&conn, &ctx.admin, "reinstate", username, None)
+    })
+    .map_err(not_found)?;
+
+    Ok(Output::new(&["username", "banned"]).row(vec![json!(username), json!(false)]))
+}
+
+/// Deletes a user with their swipes, matches, pending emails and resets,
+/// profile picture and sessions. The audit log keeps mentioning them.
+fn delete_user(ctx: &Context, username: &str) -> Result<Output, Error> {
+    let conn = ctx.conn()?;
+    let (user, swipes_deleted, matches_deleted) = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let user = users::table.find(username).first::<DBUser>(&conn)?;
+            let swipes_deleted = diesel::delete(
+                swipes::table.filter(swipes::swiper.eq(username).or(swipes::swiped.eq(username))),
+            )
+            .execute(&conn)?;
+            let matches_deleted = diesel::delete(
+                matches::table.filter(
+                    matches::username1
+                        .eq(username)
+                        .or(matches::username2.eq(username)),
+                ),
+            )
+            .execute(&conn)?;
+            // email verifications and password resets cascade
+            diesel::delete(users::table.find(username)).execute(&conn)?;
+            audit(&conn, &ctx.admin, "delete_user", username, None)?;
+            Ok((user, swipes_deleted, matches_deleted))
+        })
+        .map_err(not_found)?;
+
+    if let Some(filename) = &user.profile_pic {
+        if let Err(err) = fs::remove_file(filename) {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("warning: couldn't remove {}: {}", filename, err);
+            }
+        }
+    }
+    ctx.pic_cache.evict(username);
+    let sessions_ended = try_end_sessions(ctx, username);
+
+    Ok(Output::new(&[
+        "username",
+        "swipes_deleted",
+        "matches_deleted",
+        "sessions_ended",
+    ])
+    .row(vec![
+        json!(username),
+        json!(swipes_deleted),
+        json!(matches_deleted),
+        json!(sessions_ended),
+    ]))
+}
+
+fn list_matches(ctx: &Context, username: &str) -> Result<Output, Error> {
+    let mut matched: Vec<String> = ctx
+        .repo
+        .for_user(username)
+        .map_err(failed)?
+        .into_iter()
+        .map(|m| {
+            if m.username1 == username {
+                m.username2
+            } else {
+                m.username1
+            }
+        })
+        .collect();
+    matched.sort();
+
+    let output = matched
+        .into_iter()
+        .fold(Output::new(&["matched_with"]), |output, name| {
+            output.row(vec![json!(name)])
+        });
+    Ok(output)
+}
+
+/// Evicts the given users' cached pictures, or everyone's if none are given.
+fn purge_pic_cache(ctx: &Context, usernames: Vec<&str>) -> Result<Output, Error> {
+    let usernames: Vec<String> = if usernames.is_empty() {
+        users::table
+            .select(users::username)
+            .load(&ctx.conn()?)
+            .map_err(failed)?
+    } else {
+        usernames.into_iter().map(str::to_string).collect()
+    };
+
+    let evicted = ctx
+        .pic_cache
+        .evict_all(&usernames)
+        .ok_or_else(|| failed("redis is unreachable"))?;
+
+    Ok(Output::new(&["users", "evicted"]).row(vec![json!(usernames.len()), json!(evicted)]))
+}
+
+/// Repairs what's derived from other data: matches must be backed by right
+/// swipes both ways, and profile pictures must exist on disk.
+fn rebuild(ctx: &Context) -> Result<Output, Error> {
+    let conn = ctx.conn()?;
+
+    let with_pics: Vec<(String, Option<String>)> = users::table
+        .select((users::username, users::profile_pic))
+        .filter(users::profile_pic.is_not_null())
+        .load(&conn)
+        .map_err(failed)?;
+    let missing_pics: Vec<String> = with_pics
+        .into_iter()
+        .filter(|(_, filename)| filename.as_deref().is_some_and(|f| !Path::new(f).exists()))
+        .map(|(username, _)| username)
+        .collect();
+
+    let (unbacked_matches, cleared_pics) = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let first_swiped_right = exists(
+                swipes::table
+                    .filter(swipes::swiper.eq(matches::username1))
+                    .filter(swipes::swiped.eq(matches::username2))
+                    .filter(swipes::kind.ne(SwipeKind::Pass)),
+            );
+            let second_swiped_right = exists(
+                swipes::table
+                    .filter(swipes::swiper.eq(matches::username2))
+                    .filter(swipes::swiped.eq(matches::username1))
+                    .filter(swipes::kind.ne(SwipeKind::Pass)),
+            );
+            let unbacked_matches = diesel::delete(
+                matches::table.filter(not(first_swiped_right.and(second_swiped_right))),
+            )
+            .execute(&conn)?;
+
+            let cleared_pics =
+                diesel::update(users::table.filter(users::username.eq_any(&missing_pics)))
+                    .set(users::profile_pic.eq(None::<String>))
+                    .execute(&conn)?;
+
+            let details = format!(
+                "{} unbacked matches, {} missing pictures",
+                unbacked_matches, cleared_pics
+            );
+            audit(&conn, &ctx.admin, "rebuild", "*", Some(details))?;
+            Ok((unbacked_matches, cleared_pics))
+        })
+        .map_err(failed)?;
+
+    if !missing_pics.is_empty() && ctx.pic_cache.evict_all(&missing_pics).is_none() {
+        eprintln!("warning: redis is unreachable, stale pictures may stay cached for a day");
+    }
+
+    Ok(Output::new(&["check", "fixed"])
+        .row(vec![
+            json!("matches without right swipes both ways"),
+            json!(unbacked_matches),
+        ])
+        .row(vec![
+            json!("profile pictures missing from disk"),
+            json!(cleared_pics),
+        ]))
+}
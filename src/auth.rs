@@ -1,25 +1,52 @@
-use std::{sync::Arc, time::Duration};
+use std::{ops::DerefMut, sync::Arc, time::Duration};
 
 use actix_session::UserSession;
 use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderMap};
 use actix_web::{Error, HttpMessage, HttpResponse};
-use diesel::{
-    r2d2::{ConnectionManager, Pool},
-    PgConnection, QueryDsl, RunQueryDsl,
-};
+use diesel::r2d2::Pool;
 use futures_util::future::{Either, Ready};
 use futures_util::task::{Context, Poll};
+use r2d2_redis::RedisConnectionManager;
+use tracing::{debug, error, info};
 
-use crate::{db::DBUser, schema::users};
+use crate::db::DBUser;
+use crate::repo::UserRepository;
+use crate::sessions::{CurrentSession, SessionStore};
+use crate::tokens::{self, TokenIssuer};
 use futures_util::future;
 
+/// Returns the token from an `Authorization: Bearer <token>` header, if set.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Authenticates requests from either a bearer access token or the session
+/// cookie, and stores the logged in `DBUser` in the request extensions.
 pub struct SessionChecker {
-    conn_pool: Arc<Pool<ConnectionManager<PgConnection>>>,
+    users: Arc<dyn UserRepository>,
+    sessions: Arc<dyn SessionStore>,
+    redis_pool: Arc<Pool<RedisConnectionManager>>,
+    issuer: Arc<TokenIssuer>,
 }
 
 impl SessionChecker {
-    pub fn new(conn_pool: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
-        SessionChecker { conn_pool }
+    pub fn new(
+        users: Arc<dyn UserRepository>,
+        sessions: Arc<dyn SessionStore>,
+        redis_pool: Arc<Pool<RedisConnectionManager>>,
+        issuer: Arc<TokenIssuer>,
+    ) -> Self {
+        SessionChecker {
+            users,
+            sessions,
+            redis_pool,
+            issuer,
+        }
     }
 }
 
@@ -37,14 +64,66 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         future::ok(SessionCheckerMiddleware {
             service,
-            conn_pool: Arc::clone(&self.conn_pool),
+            users: Arc::clone(&self.users),
+            sessions: Arc::clone(&self.sessions),
+            redis_pool: Arc::clone(&self.redis_pool),
+            issuer: Arc::clone(&self.issuer),
         })
     }
 }
 
 pub struct SessionCheckerMiddleware<S> {
     service: S,
-    conn_pool: Arc<Pool<ConnectionManager<PgConnection>>>,
+    users: Arc<dyn UserRepository>,
+    sessions: Arc<dyn SessionStore>,
+    redis_pool: Arc<Pool<RedisConnectionManager>>,
+    issuer: Arc<TokenIssuer>,
+}
+
+impl<S> SessionCheckerMiddleware<S> {
+    fn token_username(&self, token: &str) -> Result<String, HttpResponse> {
+        let claims = match self.issuer.verify(token) {
+            Ok(claims) => claims,
+            Err(err) => {
+                info!(error = %err, "rejected invalid access token");
+                return Err(HttpResponse::Unauthorized().body(err.to_string()));
+            }
+        };
+
+        let mut rd_conn = match self.redis_pool.get_timeout(Duration::from_millis(500)) {
+            Ok(rd_conn) => rd_conn,
+            Err(err) => {
+                error!(error = %err, "failed to get redis conn for session checker");
+                return Err(HttpResponse::InternalServerError().body(err.to_string()));
+            }
+        };
+
+        match tokens::is_revoked(rd_conn.deref_mut(), &claims) {
+            Ok(false) => Ok(claims.sub),
+            Ok(true) => {
+                info!(username = %claims.sub, "rejected revoked access token");
+                Err(HttpResponse::Unauthorized().body("access token has been revoked"))
+            }
+            Err(err) => {
+                error!(error = %err, "error checking access token revocation");
+                Err(HttpResponse::InternalServerError().body(err.to_string()))
+            }
+        }
+    }
+
+    fn session_username(&self, secret: &str) -> Result<(String, String), HttpResponse> {
+        match self.sessions.resume(secret) {
+            Ok(Some(record)) => Ok((record.username, record.id)),
+            Ok(None) => {
+                info!("rejected expired or revoked session");
+                Err(HttpResponse::Unauthorized().body("session expired or revoked"))
+            }
+            Err(err) => {
+                error!(error = %err, "error resuming session");
+                Err(HttpResponse::InternalServerError().body(err.to_string()))
+            }
+        }
+    }
 }
 
 impl<S, B> Service for SessionCheckerMiddleware<S>
@@ -62,26 +141,67 @@ where
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
         let session = req.get_session();
-        let username = match session
-            .get::<String>("username")
-            .expect("method literally cannot fail")
-        {
-            Some(username) => username,
+        let from_token = bearer_token(req.headers()).map(|token| self.token_username(token));
+        let (username, session_id) = match from_token {
+            Some(Ok(username)) => (username, None),
+            Some(Err(resp)) => {
+                return Either::Right(future::ok(req.into_response(resp.into_body())))
+            }
             None => {
-                return Either::Right(future::ok(
+                let secret = match session
+                    .get::<String>("session_id")
+                    .expect("method literally cannot fail")
+                {
+                    Some(secret) => secret,
+                    None => {
+                        return Either::Right(future::ok(
+                            req.into_response(
+                                HttpResponse::BadRequest()
+                                    .body("missing session id from session cookie")
+                                    .into_body(),
+                            ),
+                        ))
+                    }
+                };
+                match self.session_username(&secret) {
+                    Ok((username, id)) => (username, Some(id)),
+                    Err(resp) => {
+                        session.remove("session_id");
+                        return Either::Right(future::ok(req.into_response(resp.into_body())));
+                    }
+                }
+            }
+        };
+
+        match self.users.find(&username) {
+            Ok(Some(user)) => {
+                if let Some(restriction) = user.restriction() {
+                    info!(username = %user.username, %restriction, "rejected restricted user");
+                    return Either::Right(future::ok(
+                        req.into_response(HttpResponse::Forbidden().body(restriction).into_body()),
+                    ));
+                }
+                debug!(username = %user.username, "authenticated request");
+                req.extensions_mut().insert(user);
+                if let Some(id) = session_id {
+                    req.extensions_mut().insert(CurrentSession(id));
+                }
+                Either::Left(self.service.call(req))
+            }
+            Ok(None) => {
+                info!(%username, "rejected session for deleted user");
+                session.remove("session_id");
+                Either::Right(future::ok(
                     req.into_response(
                         HttpResponse::BadRequest()
-                            .body("missing username from session cookie")
+                            .body("user no longer exists")
                             .into_body(),
                     ),
                 ))
             }
-        };
-
-        let conn = match self.conn_pool.get_timeout(Duration::from_millis(500)) {
-            Ok(c) => c,
             Err(err) => {
-                return Either::Right(future::ok(
+                error!(error = %err, "error loading authenticated user");
+                Either::Right(future::ok(
                     req.into_response(
                         HttpResponse::InternalServerError()
                             .body(err.to_string())
@@ -89,19 +209,64 @@ where
                     ),
                 ))
             }
+        }
+    }
+}
+
+/// Only lets through requests made by admins. Must be wrapped by a
+/// `SessionChecker` so that the user is already on the request.
+pub struct AdminChecker;
+
+impl<S, B> Transform<S> for AdminChecker
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type Transform = AdminCheckerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(AdminCheckerMiddleware { service })
+    }
+}
+
+pub struct AdminCheckerMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for AdminCheckerMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let is_admin = match req.extensions().get::<DBUser>() {
+            Some(user) => user.is_admin(),
+            None => false,
         };
 
-        match users::table.find(username).first::<DBUser>(&conn) {
-            Ok(user) => {
-                req.extensions_mut().insert(user);
-                Either::Left(self.service.call(req))
-            }
-            Err(err) => {
-                session.remove("username");
-                Either::Right(future::ok(req.into_response(
-                    HttpResponse::BadRequest().body(err.to_string()).into_body(),
-                )))
-            }
+        if is_admin {
+            Either::Left(self.service.call(req))
+        } else {
+            info!("rejected non-admin from admin route");
+            Either::Right(future::ok(
+                req.into_response(
+                    HttpResponse::Forbidden()
+                        .body("admin access required")
+                        .into_body(),
+                ),
+            ))
         }
     }
 }
@@ -0,0 +1,19 @@
+use fightingtinder::admin_cli;
+use fightingtinder::cli::Error;
+
+fn main() {
+    dotenv::dotenv().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match admin_cli::run(&args) {
+        Ok(()) => {}
+        Err(Error::Usage) => {
+            eprintln!("{}", admin_cli::USAGE);
+            std::process::exit(2);
+        }
+        Err(Error::Failed(err)) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
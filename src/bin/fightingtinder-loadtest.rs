@@ -0,0 +1,19 @@
+use fightingtinder::cli::Error;
+use fightingtinder::loadtest;
+
+fn main() {
+    dotenv::dotenv().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match loadtest::run(&args) {
+        Ok(()) => {}
+        Err(Error::Usage) => {
+            eprintln!("{}", loadtest::USAGE);
+            std::process::exit(2);
+        }
+        Err(Error::Failed(err)) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
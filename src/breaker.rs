@@ -0,0 +1,116 @@
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The cooldown is over and one call has been let through to see whether
+    /// the dependency is back. If that call never reports back, another is
+    /// let through at `retry_at`.
+    HalfOpen {
+        retry_at: Instant,
+    },
+}
+
+/// Stops calling a dependency that keeps failing. After `failure_threshold`
+/// failures in a row every call is refused for `cooldown`, then a single call
+/// is let through to test the water: if it works calls resume, otherwise the
+/// breaker opens for another cooldown.
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            name,
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call should be made. Every call this allows must be followed
+    /// by `record_success` or `record_failure`.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } | State::HalfOpen { retry_at: until } if now < until => false,
+            State::Open { .. } | State::HalfOpen { .. } => {
+                *state = State::HalfOpen {
+                    retry_at: now + self.cooldown,
+                };
+                info!(breaker = self.name, "circuit half open, trying a call");
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        if !matches!(*state, State::Closed { .. }) {
+            info!(breaker = self.name, "circuit closed");
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            State::HalfOpen { .. } => self.failure_threshold,
+            State::Open { .. } => return,
+        };
+        *state = if failures >= self.failure_threshold {
+            warn!(
+                breaker = self.name,
+                cooldown_secs = self.cooldown.as_secs(),
+                "circuit open"
+            );
+            State::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            State::Closed { failures }
+        };
+    }
+
+    /// Runs `f` if the breaker allows it, recording how it went. Returns `None`
+    /// if the call was refused or failed, so callers can fall back.
+    pub fn call<T, E: Display>(&self, f: impl FnOnce() -> Result<T, E>) -> Option<T> {
+        if !self.allow() {
+            return None;
+        }
+        match f() {
+            Ok(value) => {
+                self.record_success();
+                Some(value)
+            }
+            Err(err) => {
+                warn!(breaker = self.name, error = %err, "call failed");
+                self.record_failure();
+                None
+            }
+        }
+    }
+
+    pub fn state(&self) -> &'static str {
+        match *self.state.lock().expect("breaker lock poisoned") {
+            State::Closed { .. } => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+}
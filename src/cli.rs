@@ -0,0 +1,128 @@
+//! What the command line tools have in common: parsing flags, failing, and
+//! printing results as a table or JSON.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::iter::once;
+
+use serde_json::{Map, Value};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The arguments didn't make sense, so the usage should be shown.
+    Usage,
+    Failed(String),
+}
+
+pub(crate) fn failed(err: impl Display) -> Error {
+    Error::Failed(err.to_string())
+}
+
+/// Splits `args` into positional arguments, `--option value` pairs for the
+/// names in `options`, and the `switches` that were given.
+pub fn split_args<'a>(
+    args: &[&'a str],
+    options: &[&str],
+    switches: &[&str],
+) -> Result<(Vec<&'a str>, HashMap<&'a str, &'a str>, Vec<&'a str>), Error> {
+    let mut positional = Vec::new();
+    let mut given_options = HashMap::new();
+    let mut given_switches = Vec::new();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        if options.contains(&arg) {
+            let value = args.next().ok_or(Error::Usage)?;
+            given_options.insert(arg, *value);
+        } else if switches.contains(&arg) {
+            given_switches.push(arg);
+        } else if arg.starts_with("--") {
+            return Err(Error::Usage);
+        } else {
+            positional.push(arg);
+        }
+    }
+    Ok((positional, given_options, given_switches))
+}
+
+/// A command's result: rows of values under the same columns.
+pub struct Output {
+    columns: &'static [&'static str],
+    rows: Vec<Vec<Value>>,
+}
+
+impl Output {
+    pub fn new(columns: &'static [&'static str]) -> Self {
+        Output {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row(mut self, row: Vec<Value>) -> Self {
+        self.rows.push(row);
+        self
+    }
+
+    /// An array with an object per row, keyed by column.
+    pub fn to_json(&self) -> Value {
+        let rows = self.rows.iter().map(|row| {
+            let object: Map<String, Value> = self
+                .columns
+                .iter()
+                .map(|column| column.to_string())
+                .zip(row.iter().cloned())
+                .collect();
+            Value::Object(object)
+        });
+        Value::Array(rows.collect())
+    }
+
+    /// The rows as aligned columns under a header.
+    pub fn to_table(&self) -> String {
+        let header: Vec<String> = self.columns.iter().map(|c| c.to_string()).collect();
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| match value {
+                        Value::String(s) => s.clone(),
+                        Value::Null => "-".to_string(),
+                        value => value.to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let widths: Vec<usize> = (0..header.len())
+            .map(|i| {
+                once(&header)
+                    .chain(&cells)
+                    .map(|row| row[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut table = String::new();
+        for row in once(&header).chain(&cells) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            table.push_str(line.join("  ").trim_end());
+            table.push('\n');
+        }
+        table
+    }
+
+    /// Prints the output as JSON if `json` is set, otherwise as a table.
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", self.to_json());
+        } else {
+            print!("{}", self.to_table());
+        }
+    }
+}
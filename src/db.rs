@@ -1,10 +1,45 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{Nullable, Varchar};
 use diesel::Queryable;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
+use utoipa::openapi::schema::{ObjectBuilder, Schema, SchemaType, Type};
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
+
+use crate::schema::{
+    admin_actions, email_verifications, jobs, matches, password_resets, swipes, users,
+    webhook_deliveries, webhook_members, webhooks,
+};
+
+sql_function!(fn lower(x: Varchar) -> Varchar);
+sql_function! {
+    #[sql_name = "lower"]
+    fn lower_nullable(x: Nullable<Varchar>) -> Nullable<Varchar>;
+}
+
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
 
-use crate::schema::{matches, swipes, users};
+pub const DISCIPLINES: &[&str] = &[
+    "boxing",
+    "bjj",
+    "judo",
+    "karate",
+    "kickboxing",
+    "mma",
+    "muay_thai",
+    "taekwondo",
+    "wrestling",
+];
 
-#[derive(Queryable, Insertable, Deserialize)]
+#[derive(Queryable, Insertable, Deserialize, Clone)]
 #[table_name = "users"]
 pub struct DBUser {
     pub(crate) username: String,
@@ -13,6 +48,148 @@ pub struct DBUser {
     pub(crate) long: Option<f64>,
     pub(crate) bio: Option<String>,
     pub(crate) profile_pic: Option<String>,
+    pub(crate) role: String,
+    pub(crate) suspended_until: Option<NaiveDateTime>,
+    pub(crate) banned: bool,
+    pub(crate) discipline: Option<String>,
+    pub(crate) email: Option<String>,
+    pub(crate) email_verified: bool,
+    pub(crate) last_active_at: Option<NaiveDateTime>,
+    /// Kept in step with `lat`/`long`, see `geo::geohash`.
+    pub(crate) geohash: Option<String>,
+}
+
+impl DBUser {
+    /// A new account with the default role, no profile and an unverified
+    /// email. `password` must already be hashed.
+    pub fn new(username: String, password: String, email: Option<String>) -> DBUser {
+        DBUser {
+            username,
+            password,
+            lat: None,
+            long: None,
+            bio: None,
+            profile_pic: None,
+            role: ROLE_USER.to_string(),
+            suspended_until: None,
+            banned: false,
+            discipline: None,
+            email,
+            email_verified: false,
+            last_active_at: None,
+            geohash: None,
+        }
+    }
+
+    /// Places the user at `lat`/`long`, with the geohash to match.
+    pub fn located(mut self, lat: f64, long: f64) -> DBUser {
+        self.lat = Some(lat);
+        self.long = Some(long);
+        self.geohash = Some(crate::geo::geohash((lat, long)));
+        self
+    }
+
+    pub fn with_discipline(mut self, discipline: &str) -> DBUser {
+        self.discipline = Some(discipline.to_string());
+        self
+    }
+
+    pub fn active_at(mut self, at: NaiveDateTime) -> DBUser {
+        self.last_active_at = Some(at);
+        self
+    }
+
+    /// Marks the email as confirmed, as following the verification link does.
+    pub fn verified(mut self) -> DBUser {
+        self.email_verified = true;
+        self
+    }
+
+    pub fn banned(mut self) -> DBUser {
+        self.banned = true;
+        self
+    }
+
+    pub fn admin(mut self) -> DBUser {
+        self.role = ROLE_ADMIN.to_string();
+        self
+    }
+
+    pub fn suspended_until(mut self, until: NaiveDateTime) -> DBUser {
+        self.suspended_until = Some(until);
+        self
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == ROLE_ADMIN
+    }
+
+    /// Returns why the user isn't allowed to use the site, if they aren't.
+    pub fn restriction(&self) -> Option<String> {
+        if self.banned {
+            return Some("account banned".to_string());
+        }
+        match self.suspended_until {
+            Some(until) if until > chrono::Utc::now().naive_utc() => {
+                Some(format!("account suspended until {}", until))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The `swipe_kind` Postgres enum.
+#[derive(SqlType, QueryId)]
+#[postgres(type_name = "swipe_kind")]
+pub struct SwipeKindType;
+
+/// Which way someone swiped. Likes and super-likes both count as swiping
+/// right, so either can make a match.
+#[derive(
+    AsExpression, FromSqlRow, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema,
+)]
+#[sql_type = "SwipeKindType"]
+#[serde(rename_all = "lowercase")]
+pub enum SwipeKind {
+    Pass,
+    Like,
+    Super,
+}
+
+impl SwipeKind {
+    pub fn is_right(self) -> bool {
+        self != SwipeKind::Pass
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SwipeKind::Pass => "pass",
+            SwipeKind::Like => "like",
+            SwipeKind::Super => "super",
+        }
+    }
+}
+
+impl ToSql<SwipeKindType, Pg> for SwipeKind {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<SwipeKindType, Pg> for SwipeKind {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"pass" => Ok(SwipeKind::Pass),
+            b"like" => Ok(SwipeKind::Like),
+            b"super" => Ok(SwipeKind::Super),
+            _ => Err("unknown swipe kind".into()),
+        }
+    }
 }
 
 #[derive(Queryable, Insertable, Debug)]
@@ -20,14 +197,160 @@ pub struct DBUser {
 pub struct DBSwipe {
     pub(crate) swiper: String,
     pub(crate) swiped: String,
-    pub(crate) status: bool,
+    pub(crate) kind: SwipeKind,
 }
 
-#[derive(Queryable, Insertable, Debug)]
+#[derive(Queryable, Insertable, Clone, Debug)]
 #[table_name = "matches"]
 pub struct DBMatch {
     pub(crate) username1: String,
     pub(crate) username2: String,
+    pub(crate) created_at: NaiveDateTime,
+    /// When the match was made, or last opened or extended.
+    pub(crate) last_activity_at: NaiveDateTime,
+    /// Who used the match's one extension, if anyone has.
+    pub(crate) extended_by: Option<String>,
+}
+
+/// A match that expired.
+#[derive(Queryable, Clone, Debug)]
+pub struct DBMatchHistory {
+    pub(crate) id: i32,
+    pub(crate) username1: String,
+    pub(crate) username2: String,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) last_activity_at: NaiveDateTime,
+    pub(crate) extended_by: Option<String>,
+    pub(crate) expired_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "email_verifications"]
+pub struct DBEmailVerification {
+    pub(crate) token_hash: String,
+    pub(crate) username: String,
+    pub(crate) email: String,
+    pub(crate) expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "password_resets"]
+pub struct DBPasswordReset {
+    pub(crate) token_hash: String,
+    pub(crate) username: String,
+    pub(crate) expires_at: NaiveDateTime,
+    pub(crate) used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Serialize, Clone, Debug, ToSchema)]
+pub struct DBAdminAction {
+    pub(crate) id: i32,
+    pub(crate) admin: String,
+    pub(crate) action: String,
+    pub(crate) target: String,
+    pub(crate) details: Option<String>,
+    pub(crate) created_at: NaiveDateTime,
+}
+
+/// A job in the queue, with the job itself as JSON in `payload`.
+#[derive(Queryable, QueryableByName, Serialize, Clone, Debug, ToSchema)]
+#[table_name = "jobs"]
+pub struct DBJob {
+    pub(crate) id: i64,
+    pub(crate) kind: String,
+    pub(crate) payload: serde_json::Value,
+    pub(crate) state: String,
+    pub(crate) attempts: i32,
+    pub(crate) max_attempts: i32,
+    /// When the job is next due to run.
+    pub(crate) run_at: NaiveDateTime,
+    pub(crate) locked_at: Option<NaiveDateTime>,
+    pub(crate) last_error: Option<String>,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "jobs"]
+pub struct NewJob<'a> {
+    pub(crate) kind: &'a str,
+    pub(crate) payload: serde_json::Value,
+    pub(crate) max_attempts: i32,
+    pub(crate) run_at: NaiveDateTime,
+}
+
+/// An endpoint that's sent the events it subscribed to. Subscriptions are the
+/// names of `WebhookEvent`s.
+#[derive(Queryable, Clone, Debug)]
+pub struct DBWebhook {
+    pub(crate) id: i32,
+    pub(crate) url: String,
+    pub(crate) secret: String,
+    pub(crate) events: Vec<String>,
+    pub(crate) created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "webhooks"]
+pub struct NewWebhook<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) secret: &'a str,
+    pub(crate) events: Vec<String>,
+}
+
+/// One event sent, or being sent, to one webhook.
+#[derive(Queryable, Serialize, Clone, Debug, ToSchema)]
+pub struct DBWebhookDelivery {
+    pub(crate) id: i64,
+    pub(crate) webhook_id: i32,
+    pub(crate) event: String,
+    /// The event's `data`.
+    pub(crate) payload: serde_json::Value,
+    /// `pending` until it's delivered or runs out of attempts, then
+    /// `delivered` or `failed`.
+    pub(crate) status: String,
+    pub(crate) attempts: i32,
+    /// The HTTP status of the latest response, if there was one.
+    pub(crate) response_status: Option<i32>,
+    pub(crate) last_error: Option<String>,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) delivered_at: Option<NaiveDateTime>,
+    /// The job that queued it, so a retry of that job doesn't queue it again.
+    pub(crate) job_id: Option<i64>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery<'a> {
+    pub(crate) webhook_id: i32,
+    pub(crate) event: &'a str,
+    pub(crate) payload: &'a serde_json::Value,
+    pub(crate) job_id: i64,
+}
+
+/// A user whose events a webhook is sent, as a member of the gym that
+/// registered it.
+#[derive(Queryable, Serialize, Clone, Debug, ToSchema)]
+pub struct DBWebhookMember {
+    pub(crate) webhook_id: i32,
+    pub(crate) username: String,
+    pub(crate) created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "webhook_members"]
+pub struct NewWebhookMember<'a> {
+    pub(crate) webhook_id: i32,
+    pub(crate) username: &'a str,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "admin_actions"]
+pub struct NewAdminAction<'a> {
+    pub(crate) admin: &'a str,
+    pub(crate) action: &'a str,
+    pub(crate) target: &'a str,
+    pub(crate) details: Option<String>,
 }
 
 impl Serialize for DBUser {
@@ -35,11 +358,43 @@ impl Serialize for DBUser {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("DBUser", 4)?;
+        let mut state = serializer.serialize_struct("DBUser", 6)?;
         state.serialize_field("username", &self.username)?;
         state.serialize_field("lat", &self.lat)?;
         state.serialize_field("long", &self.long)?;
         state.serialize_field("bio", &self.bio)?;
+        state.serialize_field("role", &self.role)?;
+        state.serialize_field("discipline", &self.discipline)?;
         state.end()
     }
 }
+
+/// Describes the fields the `Serialize` impl above writes, since the schema
+/// can't be derived from the struct without exposing the password and email.
+impl PartialSchema for DBUser {
+    fn schema() -> RefOr<Schema> {
+        let string = || ObjectBuilder::new().schema_type(Type::String);
+        let nullable =
+            |ty| ObjectBuilder::new().schema_type(SchemaType::Array(vec![ty, Type::Null]));
+        ObjectBuilder::new()
+            .property("username", string())
+            .property("lat", nullable(Type::Number))
+            .property("long", nullable(Type::Number))
+            .property("bio", nullable(Type::String))
+            .property("role", string())
+            .property("discipline", nullable(Type::String))
+            .required("username")
+            .required("lat")
+            .required("long")
+            .required("bio")
+            .required("role")
+            .required("discipline")
+            .into()
+    }
+}
+
+impl ToSchema for DBUser {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("DBUser")
+    }
+}
@@ -0,0 +1,67 @@
+//! Expires matches that nobody has followed up on, moving them to the match
+//! history.
+
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use tracing::info;
+
+use crate::db::DBMatch;
+use crate::metrics::Metrics;
+use crate::repo::{MatchRepository, RepoResult};
+
+/// How often the server looks for matches to expire.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DEFAULT_DAYS: i64 = 14;
+
+/// How long a match lasts without any activity.
+#[derive(Clone, Copy, Debug)]
+pub struct MatchExpiry {
+    after: chrono::Duration,
+}
+
+impl Default for MatchExpiry {
+    fn default() -> Self {
+        MatchExpiry::days(DEFAULT_DAYS)
+    }
+}
+
+impl MatchExpiry {
+    pub fn days(days: i64) -> Self {
+        MatchExpiry {
+            after: chrono::Duration::days(days),
+        }
+    }
+
+    /// The period set by `MATCH_EXPIRY_DAYS`, or 14 days.
+    pub fn from_env() -> Result<Self, String> {
+        match dotenv::var("MATCH_EXPIRY_DAYS") {
+            Ok(days) => match days.parse() {
+                Ok(days) if days > 0 => Ok(MatchExpiry::days(days)),
+                _ => Err("MATCH_EXPIRY_DAYS must be a whole number of days above 0".to_string()),
+            },
+            Err(_) => Ok(MatchExpiry::default()),
+        }
+    }
+
+    pub fn expires_at(&self, m: &DBMatch) -> NaiveDateTime {
+        m.last_activity_at + self.after
+    }
+
+    /// Moves every match that has expired by `now` to the history, returning
+    /// how many there were.
+    pub fn sweep(
+        &self,
+        match_repo: &dyn MatchRepository,
+        metrics: &Metrics,
+        now: NaiveDateTime,
+    ) -> RepoResult<usize> {
+        let expired = match_repo.expire(now - self.after)?;
+        if expired > 0 {
+            metrics.matches_expired.inc_by(expired as u64);
+            info!(expired, "expired inactive matches");
+        }
+        Ok(expired)
+    }
+}
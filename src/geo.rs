@@ -0,0 +1,149 @@
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great circle distance between two (lat, long) points in degrees.
+pub fn haversine_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, long1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, long2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((long2 - long1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// The same calculation as `haversine_km` as a SQL expression measuring from
+/// the given point to a row's `lat`/`long` columns.
+pub fn haversine_km_sql(from: (f64, f64)) -> String {
+    format!(
+        "(2 * {r} * asin(sqrt(power(sin(radians(lat - {lat}) / 2), 2) \
+         + cos(radians({lat})) * cos(radians(lat)) * power(sin(radians(long - {long}) / 2), 2))))",
+        r = EARTH_RADIUS_KM,
+        lat = from.0,
+        long = from.1,
+    )
+}
+
+/// A (min lat, max lat, min long, max long) box containing every point within
+/// `km` of `centre`, cheap to check before the exact distance.
+pub fn bounding_box(centre: (f64, f64), km: f64) -> (f64, f64, f64, f64) {
+    let lat_delta = (km / EARTH_RADIUS_KM).to_degrees();
+    let long_delta = match centre.0.to_radians().cos() {
+        c if c > 1e-6 => (lat_delta / c).min(180.0),
+        _ => 180.0,
+    };
+    (
+        (centre.0 - lat_delta).max(-90.0),
+        (centre.0 + lat_delta).min(90.0),
+        centre.1 - long_delta,
+        centre.1 + long_delta,
+    )
+}
+
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// How many characters of each user's geohash are stored, naming a cell about
+/// 5m across.
+pub const GEOHASH_PRECISION: usize = 9;
+
+/// The most precise cells `neighbourhoods` starts from, about 5km across.
+const NEIGHBOURHOOD_PRECISION: usize = 5;
+
+/// The geohash of a (lat, long) point. Geohashes name cells of a grid that
+/// get finer with each character, so points in the same cell share a prefix
+/// and a btree index can find the points in a cell.
+pub fn geohash(point: (f64, f64)) -> String {
+    encode(point, GEOHASH_PRECISION)
+}
+
+fn encode((lat, long): (f64, f64), precision: usize) -> String {
+    let mut lat_range = (-90.0, 90.0);
+    let mut long_range = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut index = 0;
+    // bits alternate between longitude and latitude, starting with longitude
+    for bit in 0..precision * 5 {
+        let (range, value) = if bit % 2 == 0 {
+            (&mut long_range, long)
+        } else {
+            (&mut lat_range, lat)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        index <<= 1;
+        if value >= mid {
+            index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        if bit % 5 == 4 {
+            hash.push(GEOHASH_ALPHABET[index] as char);
+            index = 0;
+        }
+    }
+    hash
+}
+
+/// The (height, width) in degrees of cells with `precision` characters.
+fn cell_size(precision: usize) -> (f64, f64) {
+    let lat_bits = precision * 5 / 2;
+    let long_bits = precision * 5 - lat_bits;
+    (
+        180.0 / 2f64.powi(lat_bits as i32),
+        360.0 / 2f64.powi(long_bits as i32),
+    )
+}
+
+/// The cell `centre` is in and the ones around it, with the distance in km
+/// they cover in every direction from `centre`.
+fn neighbourhood(centre: (f64, f64), precision: usize) -> (Vec<String>, f64) {
+    let (height, width) = cell_size(precision);
+    let mut cells = Vec::with_capacity(9);
+    for lat_step in [-1.0, 0.0, 1.0] {
+        let lat = centre.0 + lat_step * height;
+        if !(-90.0..=90.0).contains(&lat) {
+            continue;
+        }
+        for long_step in [-1.0, 0.0, 1.0] {
+            let long = (centre.1 + long_step * width + 540.0).rem_euclid(360.0) - 180.0;
+            let cell = encode((lat, long), precision);
+            if !cells.contains(&cell) {
+                cells.push(cell);
+            }
+        }
+    }
+    // at least a cell's height north and south, and at least a cell's width
+    // of longitude east and west, which is least at the nearest point of the
+    // bounding meridian
+    let north_south = height.to_radians() * EARTH_RADIUS_KM;
+    let east_west =
+        (centre.0.to_radians().cos() * width.min(90.0).to_radians().sin()).asin() * EARTH_RADIUS_KM;
+    (cells, north_south.min(east_west))
+}
+
+/// Ever larger blocks of geohash cells around `centre`, each with the
+/// distance in km it covers in every direction. Searching them in turn finds
+/// the points nearest `centre` without looking at every point: once the
+/// block holds enough points within its distance, no point outside it can be
+/// nearer.
+pub fn neighbourhoods(centre: (f64, f64)) -> impl Iterator<Item = (Vec<String>, f64)> {
+    (1..=NEIGHBOURHOOD_PRECISION)
+        .rev()
+        .map(move |precision| neighbourhood(centre, precision))
+}
+
+/// The smallest block of geohash cells holding every point within `km` of
+/// `centre`, or `None` if no block is big enough.
+pub fn covering(centre: (f64, f64), km: f64) -> Option<Vec<String>> {
+    neighbourhoods(centre)
+        .find(|(_, reach)| *reach >= km)
+        .map(|(cells, _)| cells)
+}
+
+/// A SQL condition matching rows whose `geohash` column is in one of `cells`,
+/// written so that each cell is a prefix scan of the column's index.
+pub fn in_cells_sql(cells: &[String]) -> String {
+    let matches: Vec<String> = cells
+        .iter()
+        // geohashes only use GEOHASH_ALPHABET, so need no escaping
+        .map(|cell| format!("geohash LIKE '{}%'", cell))
+        .collect();
+    format!("({})", matches.join(" OR "))
+}
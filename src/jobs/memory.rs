@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{NaiveDateTime, Utc};
+
+use super::{Job, JobQueue, DEAD, DONE, QUEUED, RUNNING, STALE_AFTER};
+use crate::db::DBJob;
+use crate::repo::{RepoError, RepoResult};
+
+/// Jobs kept in memory, for tests. Nothing runs them unless something calls
+/// `run_next`.
+#[derive(Default)]
+pub struct MemoryJobQueue {
+    jobs: Mutex<BTreeMap<i64, DBJob>>,
+}
+
+impl MemoryJobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn jobs(&self) -> MutexGuard<'_, BTreeMap<i64, DBJob>> {
+        self.jobs.lock().expect("job queue lock poisoned")
+    }
+}
+
+/// Applies `f` to a job if it's still held by the claim `claimed` came from.
+fn update(queue: &MemoryJobQueue, claimed: &DBJob, f: impl FnOnce(&mut DBJob)) -> RepoResult<bool> {
+    match queue.jobs().get_mut(&claimed.id) {
+        Some(job) if job.state == RUNNING && job.locked_at == claimed.locked_at => {
+            f(job);
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+impl JobQueue for MemoryJobQueue {
+    fn enqueue(&self, job: &Job) -> RepoResult<i64> {
+        let payload = serde_json::to_value(job).map_err(RepoError::backend)?;
+        let now = Utc::now().naive_utc();
+        let mut jobs = self.jobs();
+        let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        jobs.insert(
+            id,
+            DBJob {
+                id,
+                kind: job.kind().to_string(),
+                payload,
+                state: QUEUED.to_string(),
+                attempts: 0,
+                max_attempts: job.max_attempts(),
+                run_at: now,
+                locked_at: None,
+                last_error: None,
+                created_at: now,
+                finished_at: None,
+            },
+        );
+        Ok(id)
+    }
+
+    fn enqueue_once(&self, job: &Job) -> RepoResult<Option<i64>> {
+        let pending = self
+            .jobs()
+            .values()
+            .any(|j| j.kind == job.kind() && (j.state == QUEUED || j.state == RUNNING));
+        if pending {
+            return Ok(None);
+        }
+        self.enqueue(job).map(Some)
+    }
+
+    fn claim(&self) -> RepoResult<Option<DBJob>> {
+        let now = Utc::now().naive_utc();
+        let stale = now - chrono::Duration::from_std(STALE_AFTER).map_err(RepoError::backend)?;
+        let mut jobs = self.jobs();
+        let due = jobs
+            .values_mut()
+            .filter(|j| {
+                (j.state == QUEUED && j.run_at <= now)
+                    || (j.state == RUNNING && j.locked_at.is_some_and(|at| at < stale))
+            })
+            .min_by_key(|j| j.run_at);
+        Ok(due.map(|job| {
+            job.state = RUNNING.to_string();
+            job.attempts += 1;
+            job.locked_at = Some(now);
+            job.clone()
+        }))
+    }
+
+    fn complete(&self, claimed: &DBJob) -> RepoResult<bool> {
+        update(self, claimed, |job| {
+            job.state = DONE.to_string();
+            job.locked_at = None;
+            job.finished_at = Some(Utc::now().naive_utc());
+        })
+    }
+
+    fn fail(
+        &self,
+        claimed: &DBJob,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> RepoResult<bool> {
+        update(self, claimed, |job| {
+            job.locked_at = None;
+            job.last_error = Some(error.to_string());
+            match retry_at {
+                Some(retry_at) => {
+                    job.state = QUEUED.to_string();
+                    job.run_at = retry_at;
+                }
+                None => {
+                    job.state = DEAD.to_string();
+                    job.finished_at = Some(Utc::now().naive_utc());
+                }
+            }
+        })
+    }
+
+    fn list(&self, state: Option<&str>, before: Option<i64>, limit: i64) -> RepoResult<Vec<DBJob>> {
+        Ok(self
+            .jobs()
+            .values()
+            .rev()
+            .filter(|j| state.is_none_or(|state| j.state == state))
+            .filter(|j| before.is_none_or(|before| j.id < before))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn find(&self, id: i64) -> RepoResult<Option<DBJob>> {
+        Ok(self.jobs().get(&id).cloned())
+    }
+
+    fn retry(&self, id: i64) -> RepoResult<bool> {
+        let mut jobs = self.jobs();
+        match jobs.get_mut(&id) {
+            Some(job) if job.state == DEAD => {
+                job.state = QUEUED.to_string();
+                job.attempts = 0;
+                job.run_at = Utc::now().naive_utc();
+                job.finished_at = None;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn prune(&self, finished_before: NaiveDateTime) -> RepoResult<usize> {
+        let mut jobs = self.jobs();
+        let before = jobs.len();
+        jobs.retain(|_, j| {
+            !(j.state == DONE && j.finished_at.is_some_and(|at| at < finished_before))
+        });
+        Ok(before - jobs.len())
+    }
+}
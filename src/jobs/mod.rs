@@ -0,0 +1,300 @@
+//! A durable queue for work that shouldn't hold up a request, and the worker
+//! threads that run it. Failed jobs are retried with exponential back-off
+//! until they run out of attempts, when they're left in the `dead` state for
+//! an admin to look at and retry.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, info, info_span, warn};
+
+use crate::db::DBJob;
+use crate::expiry::MatchExpiry;
+use crate::mail::Outbox;
+use crate::metrics::Metrics;
+use crate::repo::{MatchRepository, RepoResult, UserRepository, WebhookRepository};
+use crate::webhooks::{self, WebhookEvent, WebhookSender};
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::MemoryJobQueue;
+pub use postgres::PgJobQueue;
+
+pub const QUEUED: &str = "queued";
+pub const RUNNING: &str = "running";
+pub const DONE: &str = "done";
+/// Jobs that failed on every attempt.
+pub const DEAD: &str = "dead";
+
+/// How long a worker waits before looking again when no jobs are due.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Running jobs not finished after this long are assumed to belong to a worker
+/// that died, and are handed to another one.
+pub const STALE_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// How often finished jobs are cleared out.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long jobs that succeeded are kept after they finish. Dead jobs are
+/// kept until an admin retries them.
+const DONE_RETENTION_DAYS: i64 = 7;
+
+const DEFAULT_WORKERS: usize = 4;
+const FIRST_RETRY_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 60 * 60;
+
+/// The work a job does, stored as JSON tagged with `type`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Job {
+    /// Moves inactive matches to the match history.
+    ExpireMatches,
+    /// Deletes jobs that succeeded more than a week ago.
+    PruneJobs,
+    /// Emails `username` to say they matched with `matched_with`.
+    NotifyMatch {
+        username: String,
+        matched_with: String,
+    },
+    /// Queues a delivery of the event to each webhook subscribed to it that
+    /// has one of `usernames` as a member.
+    EmitWebhook {
+        event: WebhookEvent,
+        #[serde(default)]
+        usernames: Vec<String>,
+        data: Value,
+    },
+    /// Sends one webhook delivery.
+    DeliverWebhook { delivery_id: i64 },
+}
+
+impl Job {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Job::ExpireMatches => "expire_matches",
+            Job::PruneJobs => "prune_jobs",
+            Job::NotifyMatch { .. } => "notify_match",
+            Job::EmitWebhook { .. } => "emit_webhook",
+            Job::DeliverWebhook { .. } => "deliver_webhook",
+        }
+    }
+
+    pub fn max_attempts(&self) -> i32 {
+        match self {
+            // the next scheduled run does the same work
+            Job::ExpireMatches | Job::PruneJobs => 1,
+            Job::NotifyMatch { .. } | Job::EmitWebhook { .. } => 5,
+            // about an hour of retries, for endpoints that are briefly down
+            Job::DeliverWebhook { .. } => 8,
+        }
+    }
+
+    /// Does the job's work. `id` is the job's own, which stays the same
+    /// across attempts.
+    pub fn run(&self, ctx: &JobContext, id: i64, last_attempt: bool) -> Result<(), String> {
+        match self {
+            Job::ExpireMatches => {
+                let now = Utc::now().naive_utc();
+                ctx.match_expiry
+                    .sweep(&*ctx.match_repo, &ctx.metrics, now)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            }
+            Job::PruneJobs => {
+                let before = Utc::now().naive_utc() - chrono::Duration::days(DONE_RETENTION_DAYS);
+                let pruned = ctx.job_queue.prune(before).map_err(|err| err.to_string())?;
+                info!(pruned, "deleted finished jobs");
+                Ok(())
+            }
+            Job::NotifyMatch {
+                username,
+                matched_with,
+            } => {
+                let user = ctx
+                    .user_repo
+                    .find(username)
+                    .map_err(|err| err.to_string())?;
+                // nothing to do if they've gone or haven't shown the address is theirs
+                let to = match user {
+                    Some(user) if user.email_verified => match user.email {
+                        Some(email) => email,
+                        None => return Ok(()),
+                    },
+                    _ => return Ok(()),
+                };
+                let email = ctx.outbox.new_match(&to, username, matched_with);
+                ctx.outbox.send(&email).map_err(|err| err.to_string())
+            }
+            Job::EmitWebhook {
+                event,
+                usernames,
+                data,
+            } => webhooks::fan_out(ctx, id, *event, usernames, data),
+            Job::DeliverWebhook { delivery_id } => {
+                webhooks::deliver(ctx, *delivery_id, last_attempt)
+            }
+        }
+    }
+}
+
+/// Stores jobs until a worker has run them.
+pub trait JobQueue: Send + Sync {
+    /// Queues a job to run as soon as a worker is free, returning its id.
+    fn enqueue(&self, job: &Job) -> RepoResult<i64>;
+
+    /// Queues a job unless one of the same kind is already queued or
+    /// running, returning its id if it was queued.
+    fn enqueue_once(&self, job: &Job) -> RepoResult<Option<i64>>;
+
+    /// Marks the job due longest ago as running and returns it. Jobs left
+    /// running for longer than `STALE_AFTER` are due again. Each claim stamps
+    /// a fresh `locked_at`, which `complete` and `fail` check so a worker
+    /// that outlived its claim can't overwrite the outcome of a newer one.
+    fn claim(&self) -> RepoResult<Option<DBJob>>;
+
+    /// Marks a claimed job as done, returning false if it has been claimed
+    /// again since, in which case nothing is changed.
+    fn complete(&self, claimed: &DBJob) -> RepoResult<bool>;
+
+    /// Records a failed attempt, queueing the job again at `retry_at`, or
+    /// moving it to the dead letters if that's `None`. Like `complete`, does
+    /// nothing and returns false if the job has been claimed again since.
+    fn fail(
+        &self,
+        claimed: &DBJob,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> RepoResult<bool>;
+
+    /// Jobs newest first, optionally only those in `state`, with ids below
+    /// `before` for paging.
+    fn list(&self, state: Option<&str>, before: Option<i64>, limit: i64) -> RepoResult<Vec<DBJob>>;
+
+    fn find(&self, id: i64) -> RepoResult<Option<DBJob>>;
+
+    /// Queues a dead job to run straight away with a fresh set of attempts,
+    /// returning false if there's no dead job with that id.
+    fn retry(&self, id: i64) -> RepoResult<bool>;
+
+    /// Deletes jobs that succeeded before `finished_before`, returning how
+    /// many there were.
+    fn prune(&self, finished_before: NaiveDateTime) -> RepoResult<usize>;
+}
+
+/// How long to wait before the next attempt after `attempts` have failed:
+/// 30 seconds, doubling each time, up to an hour.
+pub fn backoff(attempts: i32) -> chrono::Duration {
+    let doublings = attempts.clamp(1, 32) as u32 - 1;
+    let secs = FIRST_RETRY_SECS.saturating_mul(2i64.saturating_pow(doublings));
+    chrono::Duration::seconds(secs.min(MAX_RETRY_SECS))
+}
+
+/// What jobs need to do their work.
+pub struct JobContext {
+    pub user_repo: Arc<dyn UserRepository>,
+    pub match_repo: Arc<dyn MatchRepository>,
+    pub outbox: Arc<Outbox>,
+    pub webhook_repo: Arc<dyn WebhookRepository>,
+    pub webhook_sender: Arc<dyn WebhookSender>,
+    /// For jobs that queue more jobs.
+    pub job_queue: Arc<dyn JobQueue>,
+    pub match_expiry: Arc<MatchExpiry>,
+    pub metrics: Arc<Metrics>,
+}
+
+/// Claims and runs one job, returning false if none were due.
+pub fn run_next(queue: &dyn JobQueue, ctx: &JobContext) -> RepoResult<bool> {
+    let claimed = match queue.claim()? {
+        Some(claimed) => claimed,
+        None => return Ok(false),
+    };
+
+    let started = Instant::now();
+    let last_attempt = claimed.attempts >= claimed.max_attempts;
+    let result = match serde_json::from_value::<Job>(claimed.payload.clone()) {
+        Ok(job) => info_span!("job", id = claimed.id, kind = %claimed.kind)
+            .in_scope(|| job.run(ctx, claimed.id, last_attempt)),
+        // retrying won't help a job this build doesn't understand
+        Err(err) => {
+            error!(id = claimed.id, kind = %claimed.kind, error = %err, "unreadable job");
+            ctx.metrics
+                .jobs
+                .with_label_values(&[&claimed.kind, DEAD])
+                .inc();
+            queue.fail(&claimed, &format!("unreadable job: {}", err), None)?;
+            return Ok(true);
+        }
+    };
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+
+    let recorded = match result {
+        Ok(()) => {
+            ctx.metrics
+                .jobs
+                .with_label_values(&[&claimed.kind, DONE])
+                .inc();
+            queue.complete(&claimed)?
+        }
+        Err(err) if !last_attempt => {
+            let retry_at = Utc::now().naive_utc() + backoff(claimed.attempts);
+            warn!(id = claimed.id, kind = %claimed.kind, attempts = claimed.attempts, error = %err, elapsed_ms, %retry_at, "job failed, will retry");
+            ctx.metrics
+                .jobs
+                .with_label_values(&[&claimed.kind, "retry"])
+                .inc();
+            queue.fail(&claimed, &err, Some(retry_at))?
+        }
+        Err(err) => {
+            error!(id = claimed.id, kind = %claimed.kind, attempts = claimed.attempts, error = %err, elapsed_ms, "job failed on its last attempt");
+            ctx.metrics
+                .jobs
+                .with_label_values(&[&claimed.kind, DEAD])
+                .inc();
+            queue.fail(&claimed, &err, None)?
+        }
+    };
+    if !recorded {
+        warn!(id = claimed.id, kind = %claimed.kind, elapsed_ms, "job was claimed again while it ran, dropping this outcome");
+    }
+    Ok(true)
+}
+
+/// The number of worker threads set by `JOB_WORKERS`, or 4.
+pub fn workers_from_env() -> Result<usize, String> {
+    match dotenv::var("JOB_WORKERS") {
+        Ok(workers) => match workers.parse() {
+            Ok(workers) if workers > 0 => Ok(workers),
+            _ => Err("JOB_WORKERS must be a whole number above 0".to_string()),
+        },
+        Err(_) => Ok(DEFAULT_WORKERS),
+    }
+}
+
+/// Starts `workers` threads taking jobs from the queue. They run until the
+/// process exits.
+pub fn start_workers(queue: Arc<dyn JobQueue>, ctx: Arc<JobContext>, workers: usize) {
+    info!(workers, "starting job workers");
+    for n in 0..workers {
+        let queue = Arc::clone(&queue);
+        let ctx = Arc::clone(&ctx);
+        thread::Builder::new()
+            .name(format!("job-worker-{}", n))
+            .spawn(move || loop {
+                match run_next(&*queue, &ctx) {
+                    Ok(true) => {}
+                    Ok(false) => thread::sleep(POLL_INTERVAL),
+                    Err(err) => {
+                        error!(error = %err, "error taking a job from the queue");
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                }
+            })
+            .expect("unable to start job worker");
+    }
+}
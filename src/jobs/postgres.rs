@@ -0,0 +1,200 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::{exists, And, Eq, Filter, Find};
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::{Text, Timestamp};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, QueryResult, RunQueryDsl,
+};
+
+use super::{Job, JobQueue, DEAD, DONE, QUEUED, RUNNING, STALE_AFTER};
+use crate::db::{DBJob, NewJob};
+use crate::repo::{RepoError, RepoResult};
+use crate::schema::jobs;
+
+/// Jobs kept in the `jobs` table, so they survive restarts and can be shared
+/// by every instance. Workers claim jobs with `SKIP LOCKED`, so they never
+/// wait on each other or take the same job.
+pub struct PgJobQueue {
+    pool: Arc<Pool<ConnectionManager<PgConnection>>>,
+}
+
+impl PgJobQueue {
+    pub fn new(pool: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
+        PgJobQueue { pool }
+    }
+
+    fn conn(&self) -> RepoResult<PooledConnection<ConnectionManager<PgConnection>>> {
+        self.pool
+            .get_timeout(Duration::from_millis(500))
+            .map_err(RepoError::backend)
+    }
+}
+
+fn insert(conn: &PgConnection, job: &Job, payload: serde_json::Value) -> QueryResult<i64> {
+    diesel::insert_into(jobs::table)
+        .values(NewJob {
+            kind: job.kind(),
+            payload,
+            max_attempts: job.max_attempts(),
+            run_at: Utc::now().naive_utc(),
+        })
+        .returning(jobs::id)
+        .get_result(conn)
+}
+
+/// The job `claimed` came from, as long as no other claim has taken it since.
+fn still_claimed(
+    claimed: &DBJob,
+) -> Filter<
+    Find<jobs::table, i64>,
+    And<Eq<jobs::state, &'static str>, Eq<jobs::locked_at, Option<NaiveDateTime>>>,
+> {
+    jobs::table.find(claimed.id).filter(
+        jobs::state
+            .eq(RUNNING)
+            .and(jobs::locked_at.eq(claimed.locked_at)),
+    )
+}
+
+impl JobQueue for PgJobQueue {
+    fn enqueue(&self, job: &Job) -> RepoResult<i64> {
+        let payload = serde_json::to_value(job).map_err(RepoError::backend)?;
+        insert(&*self.conn()?, job, payload).map_err(RepoError::backend)
+    }
+
+    fn enqueue_once(&self, job: &Job) -> RepoResult<Option<i64>> {
+        let payload = serde_json::to_value(job).map_err(RepoError::backend)?;
+        let conn = self.conn()?;
+        conn.transaction(|| {
+            // held until the transaction ends, so two instances can't both
+            // find nothing pending and both queue one
+            diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                .bind::<Text, _>(job.kind())
+                .execute(&conn)?;
+            let pending: bool = diesel::select(exists(
+                jobs::table
+                    .filter(jobs::kind.eq(job.kind()))
+                    .filter(jobs::state.eq_any(&[QUEUED, RUNNING])),
+            ))
+            .get_result(&conn)?;
+            if pending {
+                return Ok(None);
+            }
+            insert(&conn, job, payload).map(Some)
+        })
+        .map_err(RepoError::backend)
+    }
+
+    fn claim(&self) -> RepoResult<Option<DBJob>> {
+        let claimed_at = Utc::now().naive_utc();
+        let stale =
+            claimed_at - chrono::Duration::from_std(STALE_AFTER).map_err(RepoError::backend)?;
+        diesel::sql_query(
+            "UPDATE jobs SET state = 'running', attempts = attempts + 1, locked_at = $1 \
+             WHERE id = ( \
+                 SELECT id FROM jobs \
+                 WHERE (state = 'queued' AND run_at <= $1) \
+                    OR (state = 'running' AND locked_at < $2) \
+                 ORDER BY run_at \
+                 LIMIT 1 \
+                 FOR UPDATE SKIP LOCKED \
+             ) \
+             RETURNING *",
+        )
+        .bind::<Timestamp, _>(claimed_at)
+        .bind::<Timestamp, _>(stale)
+        .get_result::<DBJob>(&self.conn()?)
+        .optional()
+        .map_err(RepoError::backend)
+    }
+
+    fn complete(&self, claimed: &DBJob) -> RepoResult<bool> {
+        diesel::update(still_claimed(claimed))
+            .set((
+                jobs::state.eq(DONE),
+                jobs::locked_at.eq(None::<NaiveDateTime>),
+                jobs::finished_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(&self.conn()?)
+            .map(|updated| updated > 0)
+            .map_err(RepoError::backend)
+    }
+
+    fn fail(
+        &self,
+        claimed: &DBJob,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> RepoResult<bool> {
+        let conn = self.conn()?;
+        let failed = diesel::update(still_claimed(claimed));
+        let result = match retry_at {
+            Some(retry_at) => failed
+                .set((
+                    jobs::state.eq(QUEUED),
+                    jobs::run_at.eq(retry_at),
+                    jobs::locked_at.eq(None::<NaiveDateTime>),
+                    jobs::last_error.eq(error),
+                ))
+                .execute(&conn),
+            None => failed
+                .set((
+                    jobs::state.eq(DEAD),
+                    jobs::locked_at.eq(None::<NaiveDateTime>),
+                    jobs::last_error.eq(error),
+                    jobs::finished_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(&conn),
+        };
+        result
+            .map(|updated| updated > 0)
+            .map_err(RepoError::backend)
+    }
+
+    fn list(&self, state: Option<&str>, before: Option<i64>, limit: i64) -> RepoResult<Vec<DBJob>> {
+        let mut select = jobs::table.order(jobs::id.desc()).limit(limit).into_boxed();
+        if let Some(state) = state {
+            select = select.filter(jobs::state.eq(state));
+        }
+        if let Some(before) = before {
+            select = select.filter(jobs::id.lt(before));
+        }
+        select
+            .load::<DBJob>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn find(&self, id: i64) -> RepoResult<Option<DBJob>> {
+        jobs::table
+            .find(id)
+            .first::<DBJob>(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn retry(&self, id: i64) -> RepoResult<bool> {
+        diesel::update(jobs::table.filter(jobs::id.eq(id).and(jobs::state.eq(DEAD))))
+            .set((
+                jobs::state.eq(QUEUED),
+                jobs::attempts.eq(0),
+                jobs::run_at.eq(Utc::now().naive_utc()),
+                jobs::finished_at.eq(None::<NaiveDateTime>),
+            ))
+            .execute(&self.conn()?)
+            .map(|updated| updated > 0)
+            .map_err(RepoError::backend)
+    }
+
+    fn prune(&self, finished_before: NaiveDateTime) -> RepoResult<usize> {
+        diesel::delete(
+            jobs::table
+                .filter(jobs::state.eq(DONE))
+                .filter(jobs::finished_at.lt(finished_before)),
+        )
+        .execute(&self.conn()?)
+        .map_err(RepoError::backend)
+    }
+}
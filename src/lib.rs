@@ -1,8 +1,34 @@
 #![allow(clippy::type_complexity)]
+// diesel 1.x derives implement traits from inside generated consts
+#![allow(non_local_definitions)]
 #[macro_use]
 extern crate diesel;
 
+pub mod admin_cli;
 pub mod auth;
+pub mod breaker;
+pub mod cli;
 pub mod db;
+pub mod expiry;
+pub mod geo;
+pub mod jobs;
+pub mod loadtest;
+pub mod logging;
+pub mod mail;
+pub mod metrics;
+pub mod migrations;
+pub mod openapi;
 pub mod paths;
+pub mod pic_cache;
+pub mod quota;
+pub mod recommend;
+pub mod repo;
+pub mod response;
+pub mod routes;
+pub mod scheduler;
 pub mod schema;
+pub mod sessions;
+pub mod throttle;
+pub mod tokens;
+pub mod username;
+pub mod webhooks;
@@ -0,0 +1,225 @@
+//! The commands behind `fightingtinder-loadtest`, which seeds Postgres with
+//! synthetic users and swipes and then replays requests as them against a
+//! running server, to see how discovery and matching hold up at scale.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use diesel::{Connection, PgConnection};
+use serde_json::json;
+
+use crate::cli::{failed, split_args, Error, Output};
+
+pub mod replay;
+pub mod seed;
+
+use replay::{ReplayOptions, ReplayReport, OPERATIONS};
+use seed::SeedOptions;
+
+pub const USAGE: &str = "usage: fightingtinder-loadtest [--json] <command>
+
+commands:
+  seed [--users <n>] [--swipes <n>] [--right-ratio <r>] [--rng-seed <n>]
+      create <n> users (default 5000) named seed-000000 onwards, each
+      swiping on <n> others (default 40)
+  unseed
+      delete every seeded user, with their swipes, matches and pictures
+  replay [--url <url>] [--users <n>] [--duration <secs>] [--mix <weights>]
+      log in as <n> seeded users (default 50) and make requests against
+      the server at <url> (default http://127.0.0.1:8080) for <secs>
+      (default 30), picking them by <weights> (default
+      login=1,available=4,swipe=10,match=2)
+
+Seeded users' password is \"seeded password\" and their email is their
+username at loadtest.invalid, which is how unseed tells them apart from
+real accounts.";
+
+/// Reads `--name` from `options`, or `default` if it wasn't given.
+fn number<T: FromStr>(options: &HashMap<&str, &str>, name: &str, default: T) -> Result<T, Error> {
+    match options.get(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| failed(format!("{} must be a number", name))),
+        None => Ok(default),
+    }
+}
+
+/// Parses weights like `login=1,swipe=10`. Operations left out aren't made.
+fn parse_mix(mix: &str) -> Result<[u32; 4], Error> {
+    let mut weights = [0; 4];
+    for part in mix.split(',') {
+        let (operation, weight) = part
+            .split_once('=')
+            .ok_or_else(|| failed(format!("expected operation=weight, found `{}`", part)))?;
+        let index = OPERATIONS
+            .iter()
+            .position(|o| *o == operation)
+            .ok_or_else(|| {
+                failed(format!(
+                    "unknown operation `{}`, expected one of {}",
+                    operation,
+                    OPERATIONS.join(", ")
+                ))
+            })?;
+        weights[index] = weight
+            .parse()
+            .map_err(|_| failed(format!("weight for {} must be a number", operation)))?;
+    }
+    if weights.iter().all(|w| *w == 0) {
+        return Err(failed("the mix needs at least one non-zero weight"));
+    }
+    Ok(weights)
+}
+
+fn connect() -> Result<PgConnection, Error> {
+    let database_url =
+        dotenv::var("DATABASE_URL").map_err(|_| failed("DATABASE_URL must be set"))?;
+    PgConnection::establish(&database_url).map_err(failed)
+}
+
+/// Parses and runs a command, printing its output.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json")
+        .collect();
+    let (command, args) = args.split_first().ok_or(Error::Usage)?;
+
+    let output = match *command {
+        "seed" => {
+            let (positional, options, _) = split_args(
+                args,
+                &["--users", "--swipes", "--right-ratio", "--rng-seed"],
+                &[],
+            )?;
+            if !positional.is_empty() {
+                return Err(Error::Usage);
+            }
+            let options = SeedOptions {
+                users: number(&options, "--users", 5000)?,
+                swipes_per_user: number(&options, "--swipes", 40)?,
+                right_ratio: number(&options, "--right-ratio", 0.3)?,
+                rng_seed: number(&options, "--rng-seed", 0)?,
+            };
+            if options.users < 2 {
+                return Err(failed("--users must be at least 2"));
+            }
+            if !(0.0..=1.0).contains(&options.right_ratio) {
+                return Err(failed("--right-ratio must be between 0 and 1"));
+            }
+            seed(&options)?
+        }
+        "unseed" if args.is_empty() => {
+            let deleted = seed::unseed(&connect()?).map_err(failed)?;
+            Output::new(&["users_deleted"]).row(vec![json!(deleted)])
+        }
+        "replay" => {
+            let (positional, options, _) =
+                split_args(args, &["--url", "--users", "--duration", "--mix"], &[])?;
+            if !positional.is_empty() {
+                return Err(Error::Usage);
+            }
+            let options = ReplayOptions {
+                base_url: options
+                    .get("--url")
+                    .unwrap_or(&"http://127.0.0.1:8080")
+                    .to_string(),
+                users: number(&options, "--users", 50)?,
+                duration: Duration::from_secs(number(&options, "--duration", 30)?),
+                mix: parse_mix(
+                    options
+                        .get("--mix")
+                        .unwrap_or(&"login=1,available=4,swipe=10,match=2"),
+                )?,
+                right_ratio: 0.3,
+            };
+            let report = actix_web::rt::System::new("loadtest").block_on(replay::replay(options));
+            report_output(&report)
+        }
+        _ => return Err(Error::Usage),
+    };
+
+    output.print(json);
+    Ok(())
+}
+
+fn seed(options: &SeedOptions) -> Result<Output, Error> {
+    let conn = connect()?;
+    if seed::seeded(&conn).map_err(failed)? {
+        return Err(failed(
+            "seeded users already exist, run `fightingtinder-loadtest unseed` first",
+        ));
+    }
+    let report = seed::seed(&conn, options).map_err(failed)?;
+    Ok(
+        Output::new(&["users", "pictures", "swipes", "matches"]).row(vec![
+            json!(report.users),
+            json!(report.pictures),
+            json!(report.swipes),
+            json!(report.matches),
+        ]),
+    )
+}
+
+fn millis(latency: Option<Duration>) -> serde_json::Value {
+    match latency {
+        Some(latency) => json!((latency.as_secs_f64() * 10_000.0).round() / 10.0),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// A row per operation, then one for everything together.
+fn report_output(report: &ReplayReport) -> Output {
+    let mut total = replay::OperationStats::default();
+    let mut rows = Vec::new();
+    for operation in OPERATIONS {
+        let stats = match report.operations.get(operation) {
+            Some(stats) => stats,
+            None => continue,
+        };
+        rows.push((operation.to_string(), row(stats, report.elapsed)));
+        total.latencies.extend(&stats.latencies);
+        total.errors += stats.errors;
+    }
+    rows.push(("total".to_string(), row(&total, report.elapsed)));
+
+    rows.into_iter().fold(
+        Output::new(&[
+            "operation",
+            "requests",
+            "per_second",
+            "errors",
+            "error_rate",
+            "p50_ms",
+            "p90_ms",
+            "p99_ms",
+            "max_ms",
+        ]),
+        |output, (operation, mut row)| {
+            row.insert(0, json!(operation));
+            output.row(row)
+        },
+    )
+}
+
+fn row(stats: &replay::OperationStats, elapsed: Duration) -> Vec<serde_json::Value> {
+    let requests = stats.latencies.len();
+    let error_rate = if requests == 0 {
+        0.0
+    } else {
+        stats.errors as f64 / requests as f64
+    };
+    vec![
+        json!(requests),
+        json!((requests as f64 / elapsed.as_secs_f64() * 10.0).round() / 10.0),
+        json!(stats.errors),
+        json!((error_rate * 1000.0).round() / 1000.0),
+        millis(stats.percentile(0.5)),
+        millis(stats.percentile(0.9)),
+        millis(stats.percentile(0.99)),
+        millis(stats.percentile(1.0)),
+    ]
+}
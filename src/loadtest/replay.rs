@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use actix_web::client::Client;
+use actix_web::cookie::Cookie;
+use actix_web::http::Method;
+use actix_web::web::Bytes;
+use actix_web::HttpMessage;
+use futures_util::future::join_all;
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::seed::{seed_username, SEED_PASSWORD};
+use crate::routes::API_PREFIX;
+
+/// The requests virtual users make, in the order they're reported.
+pub const OPERATIONS: &[&str] = &["login", "available", "swipe", "match"];
+
+pub struct ReplayOptions {
+    pub base_url: String,
+    /// How many seeded users to log in as, each making requests one after
+    /// another alongside the others.
+    pub users: usize,
+    pub duration: Duration,
+    /// How often each of `OPERATIONS` is picked relative to the others.
+    pub mix: [u32; 4],
+    /// The share of swipes that are likes rather than passes.
+    pub right_ratio: f64,
+}
+
+#[derive(Default)]
+pub struct OperationStats {
+    pub latencies: Vec<Duration>,
+    pub errors: usize,
+}
+
+impl OperationStats {
+    /// The latency at or below which `p` of requests completed.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        let rank = ((p * sorted.len() as f64).ceil() as usize).max(1);
+        sorted.get(rank - 1).copied()
+    }
+
+    fn merge(&mut self, other: OperationStats) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+}
+
+pub struct ReplayReport {
+    pub elapsed: Duration,
+    pub operations: BTreeMap<&'static str, OperationStats>,
+}
+
+#[derive(Deserialize)]
+struct Available {
+    items: Vec<AvailableUser>,
+}
+
+#[derive(Deserialize)]
+struct AvailableUser {
+    username: String,
+}
+
+/// One seeded user logging in and then making requests until the deadline.
+struct VirtualUser {
+    client: Client,
+    base_url: String,
+    username: String,
+    session: Option<Cookie<'static>>,
+    to_swipe: Vec<String>,
+    rng: StdRng,
+    stats: BTreeMap<&'static str, OperationStats>,
+}
+
+impl VirtualUser {
+    fn url(&self, path: &str) -> String {
+        format!("{}{}{}", self.base_url, API_PREFIX, path)
+    }
+
+    fn record(&mut self, operation: &'static str, started: Instant, ok: bool) {
+        let stats = self.stats.entry(operation).or_default();
+        stats.latencies.push(started.elapsed());
+        if !ok {
+            stats.errors += 1;
+        }
+    }
+
+    /// Makes a request as this user, reading the whole response so that
+    /// the connection can be reused. Returns the session cookie if one was
+    /// set and the body, or `None` if the request failed.
+    async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Option<(Option<Cookie<'static>>, Bytes)> {
+        let mut request = self.client.request(method, self.url(path));
+        if let Some(session) = &self.session {
+            request = request.cookie(session.clone());
+        }
+        let response = match body {
+            Some(body) => request.send_json(&body).await,
+            None => request.send().await,
+        };
+        let mut response = response.ok()?;
+        let body = response.body().await.ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        let session = response.cookies().ok().and_then(|cookies| {
+            cookies
+                .iter()
+                .find(|c| c.name() == "actix-session")
+                .map(|c| c.clone().into_owned())
+        });
+        Some((session, body))
+    }
+
+    async fn login(&mut self) {
+        let started = Instant::now();
+        let credentials = json!({ "username": self.username, "password": SEED_PASSWORD });
+        let session = self
+            .call(Method::POST, "/user/login", Some(credentials))
+            .await
+            .and_then(|(session, _)| session);
+        let ok = session.is_some();
+        if ok {
+            self.session = session;
+        }
+        self.record("login", started, ok);
+    }
+
+    async fn available(&mut self) {
+        let started = Instant::now();
+        let available = self
+            .call(Method::GET, "/swipe/available", None)
+            .await
+            .and_then(|(_, body)| serde_json::from_slice::<Available>(&body).ok());
+        let ok = available.is_some();
+        if let Some(available) = available {
+            self.to_swipe = available.items.into_iter().map(|u| u.username).collect();
+        }
+        self.record("available", started, ok);
+    }
+
+    async fn swipe(&mut self, right_ratio: f64) {
+        if self.to_swipe.is_empty() {
+            self.available().await;
+        }
+        // nobody left to swipe on, and the empty `available` has been counted
+        let swiped = match self.to_swipe.pop() {
+            Some(swiped) => swiped,
+            None => return,
+        };
+        let kind = if self.rng.gen_bool(right_ratio) {
+            "like"
+        } else {
+            "pass"
+        };
+
+        let started = Instant::now();
+        let swipe = json!({ "swiped": swiped, "kind": kind });
+        let ok = self
+            .call(Method::POST, "/swipe", Some(swipe))
+            .await
+            .is_some();
+        self.record("swipe", started, ok);
+    }
+
+    async fn matches(&mut self) {
+        let started = Instant::now();
+        let ok = self.call(Method::GET, "/match", None).await.is_some();
+        self.record("match", started, ok);
+    }
+}
+
+/// Replays a mix of requests against a running server as `options.users`
+/// seeded users at once until `options.duration` has passed. Each user logs
+/// in first, then picks every request at random according to the mix.
+pub async fn replay(options: ReplayOptions) -> ReplayReport {
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let mix = WeightedIndex::new(options.mix.iter()).expect("mix must have a non-zero weight");
+
+    let users = (0..options.users).map(|index| {
+        let mut user = VirtualUser {
+            client: Client::builder().timeout(Duration::from_secs(10)).finish(),
+            base_url: options.base_url.trim_end_matches('/').to_string(),
+            username: seed_username(index),
+            session: None,
+            to_swipe: Vec::new(),
+            rng: StdRng::from_entropy(),
+            stats: BTreeMap::new(),
+        };
+        let mix = mix.clone();
+        let right_ratio = options.right_ratio;
+        async move {
+            user.login().await;
+            while Instant::now() < deadline {
+                match OPERATIONS[user.rng.sample(&mix)] {
+                    "login" => user.login().await,
+                    "available" => user.available().await,
+                    "swipe" => user.swipe(right_ratio).await,
+                    _ => user.matches().await,
+                }
+            }
+            user.stats
+        }
+    });
+
+    let mut operations = BTreeMap::new();
+    for stats in join_all(users).await {
+        for (operation, stats) in stats {
+            operations
+                .entry(operation)
+                .or_insert_with(OperationStats::default)
+                .merge(stats);
+        }
+    }
+
+    ReplayReport {
+        elapsed: started.elapsed(),
+        operations,
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::{fs, io};
+
+use diesel::pg::Pg;
+use diesel::sql_types::Varchar;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, NullableExpressionMethods, PgConnection,
+    QueryDsl, QueryResult, RunQueryDsl, TextExpressionMethods,
+};
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::db::{DBMatch, DBSwipe, DBUser, SwipeKind, DISCIPLINES};
+use crate::geo;
+use crate::paths::users::{profile_pic_path, PROFILE_PIC_DIR};
+use crate::repo::ordered_pair;
+use crate::schema::{matches, swipes, users};
+
+/// Seeded usernames are this followed by a zero padded index, starting at 0.
+pub const SEED_PREFIX: &str = "seed-";
+
+/// Seeded users' emails are their username at this domain, already verified.
+/// Real accounts can use the same usernames, but nobody can verify an
+/// address at a `.invalid` domain, so this is what marks a seeded user.
+pub const SEED_EMAIL_DOMAIN: &str = "loadtest.invalid";
+
+/// Every seeded user's password.
+pub const SEED_PASSWORD: &str = "seeded password";
+
+/// Users are spread around these cities, weighted by the last field.
+const CITIES: &[(&str, f64, f64, u32)] = &[
+    ("London", 51.5074, -0.1278, 8),
+    ("Manchester", 53.4808, -2.2426, 3),
+    ("Dublin", 53.3498, -6.2603, 2),
+    ("New York", 40.7128, -74.0060, 8),
+    ("Las Vegas", 36.1699, -115.1398, 3),
+    ("Los Angeles", 34.0522, -118.2437, 6),
+    ("Rio de Janeiro", -22.9068, -43.1729, 5),
+    ("Bangkok", 13.7563, 100.5018, 4),
+    ("Tokyo", 35.6762, 139.6503, 5),
+    ("Sydney", -33.8688, 151.2093, 3),
+];
+
+/// How far from the centre of their city users live.
+const CITY_RADIUS_KM: f64 = 40.0;
+
+/// Seeded users were last active at some point in this many days.
+const ACTIVE_WITHIN_DAYS: i64 = 30;
+
+/// The share of swipes on users from other cities.
+const NON_LOCAL_SWIPES: f64 = 0.1;
+
+/// The share of right swipes that are super-likes.
+const SUPER_LIKES: f64 = 0.05;
+
+const EXPERIENCE: &[&str] = &[
+    "Just started",
+    "Two years into",
+    "Five years of",
+    "A decade of",
+    "Competing in",
+    "Coaching",
+];
+
+const GOALS: &[&str] = &[
+    "Looking for hard rounds.",
+    "Need a sparring partner before my next fight.",
+    "Light technical sparring only please.",
+    "Happy to drill, not looking to get hurt.",
+    "Cutting weight, come push the pace.",
+    "Will travel for good rolls.",
+];
+
+const COLOURS: &[&str] = &[
+    "#b71c1c", "#1a237e", "#1b5e20", "#e65100", "#4a148c", "#006064", "#3e2723",
+];
+
+pub struct SeedOptions {
+    pub users: usize,
+    pub swipes_per_user: usize,
+    /// Roughly what share of swipes are right swipes.
+    pub right_ratio: f64,
+    pub rng_seed: u64,
+}
+
+pub struct SeedReport {
+    pub users: usize,
+    pub pictures: usize,
+    pub swipes: usize,
+    pub matches: usize,
+}
+
+pub fn seed_username(index: usize) -> String {
+    format!("{}{:06}", SEED_PREFIX, index)
+}
+
+/// A point up to `CITY_RADIUS_KM` from a city centre, evenly spread over the
+/// area rather than bunched in the middle.
+fn near(rng: &mut StdRng, (lat, long): (f64, f64)) -> (f64, f64) {
+    let distance = CITY_RADIUS_KM * rng.gen::<f64>().sqrt();
+    let bearing = rng.gen_range(0.0, 2.0 * PI);
+    let km_per_degree = 111.32;
+    (
+        lat + distance * bearing.cos() / km_per_degree,
+        long + distance * bearing.sin() / (km_per_degree * lat.to_radians().cos()),
+    )
+}
+
+fn placeholder_pic(username: &str, colour: &str) -> String {
+    format!(
+        concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"256\" height=\"256\">",
+            "<rect width=\"256\" height=\"256\" fill=\"{}\"/>",
+            "<text x=\"128\" y=\"140\" font-size=\"32\" fill=\"#fff\" text-anchor=\"middle\">{}</text>",
+            "</svg>"
+        ),
+        colour, username
+    )
+}
+
+/// Seeds `options.users` users spread over a handful of cities, most with a
+/// discipline, a bio and a placeholder picture, and all recently active with
+/// a verified email so they show up to each other. Each user then swipes on
+/// others, mostly from their own city, with a few popular users getting most
+/// of the right swipes, and mutual right swipes are matched.
+pub fn seed(conn: &PgConnection, options: &SeedOptions) -> Result<SeedReport, String> {
+    let mut rng = StdRng::seed_from_u64(options.rng_seed);
+    // hashing once keeps seeding fast, and the cost matches real accounts so
+    // logins take as long as they would in production
+    let password = bcrypt::hash(SEED_PASSWORD, 10).expect("unable to encrypt seed password");
+    let city_weights = WeightedIndex::new(CITIES.iter().map(|city| city.3)).unwrap();
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut seeded = Vec::with_capacity(options.users);
+    let mut homes = Vec::with_capacity(options.users);
+    let mut by_city = vec![Vec::new(); CITIES.len()];
+    let mut pictures = Vec::new();
+    for index in 0..options.users {
+        let username = seed_username(index);
+        let city = rng.sample(&city_weights);
+        homes.push(city);
+        by_city[city].push(index);
+        let (_, lat, long, _) = CITIES[city];
+        let (lat, long) = near(&mut rng, (lat, long));
+
+        let mut user = DBUser::new(
+            username.clone(),
+            password.clone(),
+            Some(format!("{}@{}", username, SEED_EMAIL_DOMAIN)),
+        );
+        user.email_verified = true;
+        user.lat = Some(lat);
+        user.long = Some(long);
+        user.geohash = Some(geo::geohash((lat, long)));
+        user.last_active_at =
+            Some(now - chrono::Duration::minutes(rng.gen_range(0, ACTIVE_WITHIN_DAYS * 24 * 60)));
+        if rng.gen_bool(0.9) {
+            user.discipline = DISCIPLINES.choose(&mut rng).map(|d| d.to_string());
+        }
+        if rng.gen_bool(0.8) {
+            let discipline = user.discipline.as_deref().unwrap_or("fighting");
+            user.bio = Some(format!(
+                "{} {}. {}",
+                EXPERIENCE.choose(&mut rng).unwrap(),
+                discipline.replace('_', " "),
+                GOALS.choose(&mut rng).unwrap()
+            ));
+        }
+        if rng.gen_bool(0.8) {
+            let filename = profile_pic_path(&username);
+            let colour = COLOURS.choose(&mut rng).unwrap();
+            pictures.push((filename.clone(), placeholder_pic(&username, colour)));
+            user.profile_pic = Some(filename);
+        }
+        seeded.push(user);
+    }
+
+    // how likely others are to swipe right on each user, skewed so that a
+    // few are far more popular than the rest but averaging out at 1
+    let appeal: Vec<f64> = (0..options.users)
+        .map(|_| 4.0 * rng.gen::<f64>().powi(3))
+        .collect();
+
+    let mut right_swipes = HashSet::new();
+    let mut new_swipes = Vec::new();
+    for (index, user) in seeded.iter().enumerate() {
+        let neighbours = &by_city[homes[index]];
+        let wanted = options.swipes_per_user.min(options.users - 1);
+        let mut swiped = HashSet::new();
+        let mut attempts = 0;
+        while swiped.len() < wanted && attempts < wanted * 10 {
+            attempts += 1;
+            let other = if rng.gen_bool(NON_LOCAL_SWIPES) || neighbours.len() < 2 {
+                rng.gen_range(0, options.users)
+            } else {
+                *neighbours.choose(&mut rng).unwrap()
+            };
+            if other == index || !swiped.insert(other) {
+                continue;
+            }
+            let kind = if !rng.gen_bool((options.right_ratio * appeal[other]).min(1.0)) {
+                SwipeKind::Pass
+            } else if rng.gen_bool(SUPER_LIKES) {
+                SwipeKind::Super
+            } else {
+                SwipeKind::Like
+            };
+            if kind.is_right() {
+                right_swipes.insert((index, other));
+            }
+            new_swipes.push(DBSwipe {
+                swiper: user.username.clone(),
+                swiped: seeded[other].username.clone(),
+                kind,
+            });
+        }
+    }
+
+    let new_matches: Vec<DBMatch> = right_swipes
+        .iter()
+        .filter(|(swiper, swiped)| swiper < swiped && right_swipes.contains(&(*swiped, *swiper)))
+        .map(|(swiper, swiped)| ordered_pair(&seeded[*swiper].username, &seeded[*swiped].username))
+        .collect();
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        // batched to stay under Postgres' limit on bind parameters
+        for chunk in seeded.chunks(1000) {
+            diesel::insert_into(users::table)
+                .values(chunk)
+                .execute(conn)?;
+        }
+        for chunk in new_swipes.chunks(10_000) {
+            diesel::insert_into(swipes::table)
+                .values(chunk)
+                .execute(conn)?;
+        }
+        for chunk in new_matches.chunks(10_000) {
+            diesel::insert_into(matches::table)
+                .values(chunk)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+    .map_err(|err| err.to_string())?;
+
+    // only once the users exist, so a failed seed leaves no pictures behind
+    write_pictures(&pictures).map_err(|err| err.to_string())?;
+
+    Ok(SeedReport {
+        users: seeded.len(),
+        pictures: pictures.len(),
+        swipes: new_swipes.len(),
+        matches: new_matches.len(),
+    })
+}
+
+fn write_pictures(pictures: &[(String, String)]) -> io::Result<()> {
+    fs::create_dir_all(PROFILE_PIC_DIR)?;
+    for (filename, pic) in pictures {
+        fs::write(filename, pic)?;
+    }
+    Ok(())
+}
+
+/// The usernames of every seeded user.
+fn seeded_usernames() -> users::BoxedQuery<'static, Pg, Varchar> {
+    users::table
+        .select(users::username)
+        .filter(users::username.like(format!("{}%", SEED_PREFIX)))
+        .filter(
+            users::email.eq(users::username
+                .concat(format!("@{}", SEED_EMAIL_DOMAIN))
+                .nullable()),
+        )
+        .filter(users::email_verified.eq(true))
+        .into_boxed()
+}
+
+/// Whether any seeded users exist already.
+pub fn seeded(conn: &PgConnection) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(seeded_usernames())).get_result(conn)
+}
+
+/// Deletes every seeded user with their swipes, matches and pictures,
+/// returning how many users there were.
+pub fn unseed(conn: &PgConnection) -> QueryResult<usize> {
+    let (deleted, pictures) = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let pictures: Vec<Option<String>> = users::table
+            .select(users::profile_pic)
+            .filter(users::username.eq_any(seeded_usernames()))
+            .load(conn)?;
+        diesel::delete(
+            swipes::table.filter(
+                swipes::swiper
+                    .eq_any(seeded_usernames())
+                    .or(swipes::swiped.eq_any(seeded_usernames())),
+            ),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            matches::table.filter(
+                matches::username1
+                    .eq_any(seeded_usernames())
+                    .or(matches::username2.eq_any(seeded_usernames())),
+            ),
+        )
+        .execute(conn)?;
+        let deleted =
+            diesel::delete(users::table.filter(users::username.eq_any(seeded_usernames())))
+                .execute(conn)?;
+        Ok((deleted, pictures))
+    })?;
+
+    for filename in pictures.into_iter().flatten() {
+        if let Err(err) = fs::remove_file(&filename) {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("warning: couldn't remove {}: {}", filename, err);
+            }
+        }
+    }
+    Ok(deleted)
+}
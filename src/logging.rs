@@ -0,0 +1,133 @@
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use futures_util::future::{self, LocalBoxFuture, Ready};
+use futures_util::task::{Context, Poll};
+use tracing::{info, info_span, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Sets up the global logger. The level is read from `RUST_LOG` (default
+/// `info`), and `LOG_FORMAT=json` switches from human readable lines to one
+/// JSON object per line.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match dotenv::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+        _ => builder.init(),
+    }
+}
+
+/// The id of the request being handled, stored in the request extensions by
+/// `RequestTracing`.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Takes the request id from the client's `X-Request-Id` header if it looks
+/// sane, so that ids can be followed across services, or makes a new one.
+fn request_id(req: &ServiceRequest) -> String {
+    let from_header = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        });
+    match from_header {
+        Some(id) => id.to_string(),
+        None => format!("{:032x}", rand::random::<u128>()),
+    }
+}
+
+/// Runs each request inside a span holding its request id, so that every log
+/// line written while handling it carries the id, logs how each request went,
+/// and echoes the id back in the `X-Request-Id` response header. Must be the
+/// outermost middleware so the span covers the others.
+pub struct RequestTracing;
+
+impl<S, B> Transform<S> for RequestTracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RequestTracingMiddleware { service })
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestTracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let id = request_id(&req);
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        req.extensions_mut().insert(RequestId(id.clone()));
+
+        // inner middleware like `SessionChecker` does its work in `call`
+        // rather than in the future, so that has to be inside the span too
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let mut res = match fut.await {
+                    Ok(res) => res,
+                    Err(err) => {
+                        warn!(error = %err, elapsed_ms = started.elapsed().as_millis() as u64, "request failed");
+                        return Err(err);
+                    }
+                };
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                info!(
+                    status = res.status().as_u16(),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "request finished"
+                );
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
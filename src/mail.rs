@@ -0,0 +1,200 @@
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct MailError(String);
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to send email: {}", self.0)
+    }
+}
+
+/// Somewhere emails can be delivered to. Sending blocks, so call it from
+/// `web::block` inside handlers.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: String,
+}
+
+impl SmtpMailer {
+    /// Connects to `host` over TLS, logging in if credentials are given.
+    pub fn new(
+        host: &str,
+        credentials: Option<(String, String)>,
+        from: String,
+    ) -> Result<Self, MailError> {
+        let mut builder = SmtpTransport::relay(host).map_err(|err| MailError(err.to_string()))?;
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|err| MailError(format!("{}", err)))?,
+            )
+            .to(email
+                .to
+                .parse()
+                .map_err(|err| MailError(format!("{}", err)))?)
+            .subject(email.subject.as_str())
+            .body(email.body.clone())
+            .map_err(|err| MailError(err.to_string()))?;
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|err| MailError(err.to_string()))
+    }
+}
+
+/// Writes each email to its own file in a directory instead of sending it,
+/// for running locally without a mail server. Files are named by when they
+/// were sent and a hash of the recipient; the address is in the `To:` line.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        FileMailer { dir }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        fs::create_dir_all(&self.dir).map_err(|err| MailError(err.to_string()))?;
+        // addresses can hold `/` and `..`, so files are named by a hash of
+        // the address rather than the address itself
+        let name = format!(
+            "{}-{}.txt",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.f"),
+            &hex::encode(Sha256::digest(email.to.as_bytes()))[..16]
+        );
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        fs::write(self.dir.join(name), contents).map_err(|err| MailError(err.to_string()))
+    }
+}
+
+/// Keeps every email in memory so tests can check what would have been sent.
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().expect("mailer lock poisoned").clone()
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        self.sent
+            .lock()
+            .expect("mailer lock poisoned")
+            .push(email.clone());
+        Ok(())
+    }
+}
+
+/// Picks a mailer from the environment: SMTP if `SMTP_HOST` is set, otherwise
+/// files written to `MAIL_DIR` (default `./mail`).
+pub fn from_env() -> Result<Arc<dyn Mailer>, MailError> {
+    match dotenv::var("SMTP_HOST") {
+        Ok(host) => {
+            let credentials = dotenv::var("SMTP_USERNAME")
+                .ok()
+                .zip(dotenv::var("SMTP_PASSWORD").ok());
+            let from = dotenv::var("MAIL_FROM")
+                .unwrap_or_else(|_| "fightingtinder <noreply@fightingtinder.local>".to_string());
+            Ok(Arc::new(SmtpMailer::new(&host, credentials, from)?))
+        }
+        Err(_) => {
+            let dir = dotenv::var("MAIL_DIR").unwrap_or_else(|_| "./mail".to_string());
+            Ok(Arc::new(FileMailer::new(PathBuf::from(dir))))
+        }
+    }
+}
+
+/// Writes the emails the site sends and hands them to a `Mailer`.
+pub struct Outbox {
+    mailer: Arc<dyn Mailer>,
+    public_url: String,
+}
+
+impl Outbox {
+    pub fn new(mailer: Arc<dyn Mailer>, public_url: String) -> Self {
+        Outbox {
+            mailer,
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn send(&self, email: &Email) -> Result<(), MailError> {
+        self.mailer.send(email)
+    }
+
+    pub fn verification(&self, to: &str, username: &str, token: &str) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: "Confirm your fightingtinder email".to_string(),
+            body: format!(
+                "Hi {},\n\nConfirm this is your email address by visiting:\n\n{}/api/v1/user/verify?token={}\n\nUntil you do, other fighters won't be shown your profile.\n",
+                username, self.public_url, token
+            ),
+        }
+    }
+
+    pub fn password_reset(&self, to: &str, username: &str, token: &str) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: "Reset your fightingtinder password".to_string(),
+            body: format!(
+                "Hi {},\n\nSomeone asked to reset the password for your account. If it was you, choose a new one at:\n\n{}/password-reset?token={}\n\nThe link works once and expires in an hour. If it wasn't you, you can ignore this email.\n",
+                username, self.public_url, token
+            ),
+        }
+    }
+
+    pub fn new_match(&self, to: &str, username: &str, matched_with: &str) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: format!("You matched with {}", matched_with),
+            body: format!(
+                "Hi {},\n\nYou and {} both swiped right. Say hello before the match expires:\n\n{}/matches\n",
+                username, matched_with, self.public_url
+            ),
+        }
+    }
+}
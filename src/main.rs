@@ -1,73 +1,249 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_session::CookieSession;
+use actix_web::middleware::DefaultHeaders;
 use actix_web::{web, App, HttpServer};
 use diesel::r2d2::{ConnectionManager, Pool};
 
-use web::{get, post, scope};
+use web::{get, scope};
 
-use diesel::PgConnection;
-use fightingtinder::auth::SessionChecker;
-use fightingtinder::paths::{matches, swipe, users};
+use diesel::{Connection, PgConnection};
+use fightingtinder::expiry::{self, MatchExpiry};
+use fightingtinder::jobs::{self, Job, JobContext, JobQueue, PgJobQueue};
+use fightingtinder::logging::{self, RequestTracing};
+use fightingtinder::mail::{self, Outbox};
+use fightingtinder::metrics::{self, Metrics, RequestMetrics};
+use fightingtinder::migrations;
+use fightingtinder::openapi;
+use fightingtinder::paths::health;
+use fightingtinder::pic_cache::PicCache;
+use fightingtinder::quota::{RedisQuota, SuperLikeQuota};
+use fightingtinder::recommend::Weights;
+use fightingtinder::repo::{
+    AdminRepository, MatchRepository, PgRepository, SwipeRepository, UserRepository,
+    WebhookRepository,
+};
+use fightingtinder::routes::{self, API_PREFIX};
+use fightingtinder::scheduler::Scheduler;
+use fightingtinder::sessions::{RedisSessionStore, SessionStore};
+use fightingtinder::throttle::{LoginThrottle, RedisThrottle};
+use fightingtinder::tokens::TokenIssuer;
+use fightingtinder::webhooks::HttpSender;
+use tracing::{error, info, warn};
+
+const USAGE: &str = "usage: fightingtinder [migrate [up|down|status]]";
+
+/// Runs `fightingtinder migrate`: `up` (the default) applies every pending
+/// migration, `down` reverts the latest one and `status` lists them all.
+fn migrate(database_url: &str, action: Option<&str>) -> Result<(), String> {
+    let conn = PgConnection::establish(database_url).map_err(|err| err.to_string())?;
+    match action.unwrap_or("up") {
+        "up" => {
+            let applied = migrations::run_pending(&conn).map_err(|err| err.to_string())?;
+            println!("applied {} migration(s)", applied.len());
+        }
+        "down" => match migrations::revert_latest(&conn).map_err(|err| err.to_string())? {
+            Some(migration) => println!("reverted {}", migration.name),
+            None => println!("no migrations to revert"),
+        },
+        "status" => {
+            for (migration, applied) in migrations::status(&conn).map_err(|err| err.to_string())? {
+                let state = if applied { "applied" } else { "pending" };
+                println!("{:<8} {}", state, migration.name);
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+/// Applies pending migrations if `MIGRATE_ON_STARTUP=true`, otherwise fails
+/// if there are any, so that the server never runs against a schema older
+/// than the one it was built for.
+fn prepare_database(conn: &PgConnection) -> Result<(), String> {
+    let apply = dotenv::var("MIGRATE_ON_STARTUP").is_ok_and(|v| v == "true");
+    if apply {
+        let applied = migrations::run_pending(conn).map_err(|err| err.to_string())?;
+        info!(applied = applied.len(), "database migrated");
+        return Ok(());
+    }
+
+    let pending = migrations::pending(conn).map_err(|err| err.to_string())?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<&str> = pending.iter().map(|m| m.name).collect();
+    Err(format!(
+        "database has unapplied migrations ({}), run `fightingtinder migrate` or set MIGRATE_ON_STARTUP=true",
+        names.join(", ")
+    ))
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    let session_secret = dotenv::var("SESSION_SECRET").expect("SESSION_SECRET should be set");
+    logging::init();
+
     let database_url = dotenv::var("DATABASE_URL").expect("DATABASE_URL env var should be set");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("migrate") if args.len() <= 2 => {
+            if let Err(err) = migrate(&database_url, args.get(1).map(String::as_str)) {
+                error!(error = %err, "migrate failed");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    let session_secret = dotenv::var("SESSION_SECRET").expect("SESSION_SECRET should be set");
+    let token_secret = dotenv::var("TOKEN_SECRET").expect("TOKEN_SECRET should be set");
+    let metrics = Arc::new(Metrics::new().expect("unable to create metrics"));
+
     let manager = ConnectionManager::<PgConnection>::new(&database_url);
     let pg_pool = Arc::new(
         Pool::builder()
             .max_size(200)
+            .event_handler(metrics.pool_events("postgres"))
             .build(manager)
             .expect("unable to create pool of pg connections"),
     );
 
-    println!("created pg pool");
+    info!("created pg pool");
 
-    let rd_pool = r2d2_redis::RedisConnectionManager::new("redis://127.0.0.1")
+    let conn = pg_pool.get().expect("unable to connect to postgres");
+    if let Err(err) = prepare_database(&conn) {
+        error!(error = %err, "refusing to start");
+        std::process::exit(1);
+    }
+    drop(conn);
+
+    let pg_repo = Arc::new(PgRepository::new(Arc::clone(&pg_pool)));
+    let user_repo: Arc<dyn UserRepository> = pg_repo.clone();
+    let swipe_repo: Arc<dyn SwipeRepository> = pg_repo.clone();
+    let match_repo: Arc<dyn MatchRepository> = pg_repo.clone();
+    let webhook_repo: Arc<dyn WebhookRepository> = pg_repo.clone();
+    let admin_repo: Arc<dyn AdminRepository> = pg_repo;
+
+    let redis_url = dotenv::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1".to_string());
+    let rd_pool = r2d2_redis::RedisConnectionManager::new(redis_url)
         .expect("unable to create connection manager");
+    // redis being down shouldn't stop the server starting, the picture cache
+    // works without it and the pool keeps trying to connect in the background
     let rd_pool = Arc::new(
         r2d2_redis::r2d2::Pool::builder()
             .max_size(200)
-            .build(rd_pool)
-            .expect("unable to create redis pool"),
+            .event_handler(metrics.pool_events("redis"))
+            .build_unchecked(rd_pool),
     );
+    match rd_pool.get_timeout(Duration::from_secs(5)) {
+        Ok(_) => info!("created rd pool"),
+        Err(err) => warn!(error = %err, "created rd pool, but redis is unreachable"),
+    }
+    let pic_cache = Arc::new(PicCache::new(Arc::clone(&rd_pool)));
+    let session_store: Arc<dyn SessionStore> =
+        Arc::new(RedisSessionStore::new(Arc::clone(&rd_pool)));
+    let throttle: Arc<dyn LoginThrottle> = Arc::new(RedisThrottle::new(Arc::clone(&rd_pool)));
+    let quota: Arc<dyn SuperLikeQuota> = Arc::new(RedisQuota::new(Arc::clone(&rd_pool)));
+
+    let token_issuer = Arc::new(TokenIssuer::new(token_secret.as_bytes()));
+    let weights = Arc::new(Weights::from_env().expect("invalid RECOMMENDATION_WEIGHTS"));
+    let match_expiry = Arc::new(MatchExpiry::from_env().expect("invalid MATCH_EXPIRY_DAYS"));
 
-    println!("created rd pool");
+    let public_url =
+        dotenv::var("PUBLIC_URL").unwrap_or_else(|_| "http://127.0.0.1:8080".to_string());
+    let mailer = mail::from_env().expect("unable to create mailer");
+    let outbox = Arc::new(Outbox::new(mailer, public_url));
+    let swagger_config = openapi::swagger_config();
+
+    let job_queue: Arc<dyn JobQueue> = Arc::new(PgJobQueue::new(Arc::clone(&pg_pool)));
+    let job_context = Arc::new(JobContext {
+        user_repo: Arc::clone(&user_repo),
+        match_repo: Arc::clone(&match_repo),
+        outbox: Arc::clone(&outbox),
+        webhook_repo: Arc::clone(&webhook_repo),
+        webhook_sender: Arc::new(HttpSender),
+        job_queue: Arc::clone(&job_queue),
+        match_expiry: Arc::clone(&match_expiry),
+        metrics: Arc::clone(&metrics),
+    });
+    let workers = jobs::workers_from_env().expect("invalid JOB_WORKERS");
+    jobs::start_workers(Arc::clone(&job_queue), job_context, workers);
+
+    {
+        // every instance runs these, but one of each at a time is enough
+        let queue_once = |job: Job| {
+            let job_queue = Arc::clone(&job_queue);
+            move || {
+                job_queue
+                    .enqueue_once(&job)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            }
+        };
+        Scheduler::new()
+            .every(
+                "expire_matches",
+                expiry::SWEEP_INTERVAL,
+                queue_once(Job::ExpireMatches),
+            )
+            .every(
+                "prune_jobs",
+                jobs::PRUNE_INTERVAL,
+                queue_once(Job::PruneJobs),
+            )
+            .start();
+    }
 
     HttpServer::new(move || {
         App::new()
             .wrap(CookieSession::signed(session_secret.as_bytes()).secure(false))
+            .wrap(RequestMetrics::new(Arc::clone(&metrics)))
+            .wrap(RequestTracing)
             .data(Arc::clone(&pg_pool))
             .data(Arc::clone(&rd_pool))
+            .data(Arc::clone(&user_repo))
+            .data(Arc::clone(&swipe_repo))
+            .data(Arc::clone(&match_repo))
+            .data(Arc::clone(&webhook_repo))
+            .data(Arc::clone(&admin_repo))
+            .data(Arc::clone(&session_store))
+            .data(Arc::clone(&throttle))
+            .data(Arc::clone(&quota))
+            .data(Arc::clone(&job_queue))
+            .data(Arc::clone(&pic_cache))
+            .data(Arc::clone(&token_issuer))
+            .data(Arc::clone(&weights))
+            .data(Arc::clone(&match_expiry))
+            .data(Arc::clone(&outbox))
+            .data(Arc::clone(&swagger_config))
+            .data(Arc::clone(&metrics))
+            .route("/metrics", get().to(metrics::metrics))
+            .route("/healthz", get().to(health::healthz))
+            .route("/readyz", get().to(health::readyz))
+            .route("/openapi.json", get().to(openapi::openapi_json))
+            .route("/docs", get().to(openapi::docs_redirect))
+            .route("/docs/{tail:.*}", get().to(openapi::docs))
+            .service(scope(API_PREFIX).configure(|cfg| {
+                routes::configure(cfg, &user_repo, &session_store, &rd_pool, &token_issuer)
+            }))
+            // the unversioned routes, kept until clients have moved to /api/v1
             .service(
-                scope("/user")
-                    .route("", get().to(users::get_users))
-                    .route("", post().to(users::create_user))
-                    .route("/u/{username}", get().to(users::get_user_pic))
-                    .route("/login", post().to(users::login))
-                    .route("/logout", get().to(users::logout))
-                    .service(
-                        scope("/manage")
-                            .wrap(SessionChecker::new(Arc::clone(&pg_pool)))
-                            .route("/li", get().to(users::check_login))
-                            .route("/location", post().to(users::set_location))
-                            .route("/bio", post().to(users::set_bio))
-                            .route("/profile_pic", post().to(users::upload_profile_pic)),
-                    ),
-            )
-            .service(
-                scope("/swipe")
-                    .wrap(SessionChecker::new(Arc::clone(&pg_pool)))
-                    .route("", post().to(swipe::do_swipe))
-                    .route("/available", get().to(swipe::available)),
-            )
-            .service(
-                scope("/match")
-                    .wrap(SessionChecker::new(Arc::clone(&pg_pool)))
-                    .route("", get().to(matches::matches))
-                    .route("/{username}", web::delete().to(matches::delete_match)),
+                scope("")
+                    .wrap(
+                        DefaultHeaders::new()
+                            .header("Deprecation", "true")
+                            .header("Link", "</api/v1>; rel=\"successor-version\""),
+                    )
+                    .configure(|cfg| {
+                        routes::configure(cfg, &user_repo, &session_store, &rd_pool, &token_issuer)
+                    }),
             )
     })
     .bind("127.0.0.1:8080")?
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpResponse, Responder};
+use diesel::r2d2::event::{CheckoutEvent, TimeoutEvent};
+use diesel::r2d2::{ConnectionManager, HandleEvent, Pool, State};
+use diesel::PgConnection;
+use futures_util::future::{self, LocalBoxFuture, Ready};
+use futures_util::task::{Context, Poll};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use r2d2_redis::RedisConnectionManager;
+use tracing::warn;
+
+/// Every metric the server exports, shared between handlers as app data.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_idle_connections: IntGaugeVec,
+    pool_max_connections: IntGaugeVec,
+    pool_checkout_duration: HistogramVec,
+    pool_checkout_timeouts: IntCounterVec,
+    pub pic_cache: IntCounterVec,
+    pub swipes: IntCounterVec,
+    pub super_likes: IntCounter,
+    pub matches_created: IntCounter,
+    pub unmatches: IntCounter,
+    pub matches_expired: IntCounter,
+    pub jobs: IntCounterVec,
+    pub signups: IntCounter,
+    pub logins: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("fightingtinder".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("pool_connections", "Connections currently open in the pool"),
+            &["pool"],
+        )?;
+        let pool_idle_connections = IntGaugeVec::new(
+            Opts::new("pool_idle_connections", "Open connections not checked out"),
+            &["pool"],
+        )?;
+        let pool_max_connections = IntGaugeVec::new(
+            Opts::new(
+                "pool_max_connections",
+                "The most connections the pool will open",
+            ),
+            &["pool"],
+        )?;
+        let pool_checkout_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "pool_checkout_duration_seconds",
+                "Time spent waiting to check a connection out of the pool",
+            )
+            .buckets(vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0]),
+            &["pool"],
+        )?;
+        let pool_checkout_timeouts = IntCounterVec::new(
+            Opts::new(
+                "pool_checkout_timeouts_total",
+                "Checkouts that gave up waiting for a connection",
+            ),
+            &["pool"],
+        )?;
+        let pic_cache = IntCounterVec::new(
+            Opts::new(
+                "profile_pic_cache_total",
+                "Profile picture lookups, by whether they were cached in redis",
+            ),
+            &["result"],
+        )?;
+        let swipes = IntCounterVec::new(
+            Opts::new("swipes_total", "Swipes made, by direction"),
+            &["status"],
+        )?;
+        let super_likes = IntCounter::new(
+            "super_likes_total",
+            "Super-likes made, also counted as right swipes",
+        )?;
+        let matches_created = IntCounter::new("matches_created_total", "Matches created")?;
+        let unmatches = IntCounter::new("unmatches_total", "Matches deleted by one of the users")?;
+        let matches_expired =
+            IntCounter::new("matches_expired_total", "Matches expired for inactivity")?;
+        let jobs = IntCounterVec::new(
+            Opts::new("jobs_total", "Job attempts, by kind and outcome"),
+            &["kind", "result"],
+        )?;
+        let signups = IntCounter::new("signups_total", "Accounts created")?;
+        let logins = IntCounterVec::new(
+            Opts::new("logins_total", "Login attempts, by outcome"),
+            &["result"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_idle_connections.clone()))?;
+        registry.register(Box::new(pool_max_connections.clone()))?;
+        registry.register(Box::new(pool_checkout_duration.clone()))?;
+        registry.register(Box::new(pool_checkout_timeouts.clone()))?;
+        registry.register(Box::new(pic_cache.clone()))?;
+        registry.register(Box::new(swipes.clone()))?;
+        registry.register(Box::new(super_likes.clone()))?;
+        registry.register(Box::new(matches_created.clone()))?;
+        registry.register(Box::new(unmatches.clone()))?;
+        registry.register(Box::new(matches_expired.clone()))?;
+        registry.register(Box::new(jobs.clone()))?;
+        registry.register(Box::new(signups.clone()))?;
+        registry.register(Box::new(logins.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            pool_connections,
+            pool_idle_connections,
+            pool_max_connections,
+            pool_checkout_duration,
+            pool_checkout_timeouts,
+            pic_cache,
+            swipes,
+            super_likes,
+            matches_created,
+            unmatches,
+            matches_expired,
+            jobs,
+            signups,
+            logins,
+        })
+    }
+
+    /// Records the state of a pool. Called when metrics are scraped, since r2d2
+    /// has no event for a connection being returned to the idle set.
+    pub fn observe_pool(&self, pool: &str, state: State, max_size: u32) {
+        self.pool_connections
+            .with_label_values(&[pool])
+            .set(state.connections as i64);
+        self.pool_idle_connections
+            .with_label_values(&[pool])
+            .set(state.idle_connections as i64);
+        self.pool_max_connections
+            .with_label_values(&[pool])
+            .set(max_size as i64);
+    }
+
+    /// An r2d2 event handler recording checkouts from the pool named `pool`.
+    pub fn pool_events(&self, pool: &'static str) -> Box<PoolEvents> {
+        Box::new(PoolEvents {
+            pool,
+            checkout_duration: self.pool_checkout_duration.with_label_values(&[pool]),
+            checkout_timeouts: self.pool_checkout_timeouts.with_label_values(&[pool]),
+        })
+    }
+
+    pub fn encode(&self) -> prometheus::Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf).expect("prometheus text format is utf-8"))
+    }
+}
+
+#[derive(Debug)]
+pub struct PoolEvents {
+    pool: &'static str,
+    checkout_duration: Histogram,
+    checkout_timeouts: IntCounter,
+}
+
+impl HandleEvent for PoolEvents {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        self.checkout_duration
+            .observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        warn!(pool = self.pool, timeout = ?event.timeout(), "timed out checking out a connection");
+        self.checkout_timeouts.inc();
+    }
+}
+
+/// Counts and times every request by the route pattern it matched, rather than
+/// its path, so that `/user/u/{username}` is one series and not one per user.
+pub struct RequestMetrics {
+    metrics: Arc<Metrics>,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        RequestMetrics { metrics }
+    }
+}
+
+impl<S, B> Transform<S> for RequestMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RequestMetricsMiddleware {
+            service,
+            metrics: Arc::clone(&self.metrics),
+        })
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service for RequestMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let metrics = Arc::clone(&self.metrics);
+        let method = req.method().to_string();
+        // unmatched paths all share one label so scanners can't create
+        // unbounded numbers of series
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            // errors still become responses further out, so they're counted
+            // with the status they'll be sent with
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            metrics
+                .http_requests
+                .with_label_values(&[&method, &route, status.as_str()])
+                .inc();
+            metrics
+                .http_request_duration
+                .with_label_values(&[&method, &route])
+                .observe(started.elapsed().as_secs_f64());
+            res
+        })
+    }
+}
+
+pub async fn metrics(
+    metrics: web::Data<Arc<Metrics>>,
+    pg_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+) -> impl Responder {
+    metrics.observe_pool("postgres", pg_pool.state(), pg_pool.max_size());
+    metrics.observe_pool("redis", redis_pool.state(), redis_pool.max_size());
+
+    match metrics.encode() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
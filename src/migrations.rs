@@ -0,0 +1,102 @@
+use diesel::connection::SimpleConnection;
+use diesel::sql_types::{BigInt, Varchar};
+use diesel::{Connection, PgConnection, QueryResult, RunQueryDsl};
+use diesel_migrations::{setup_database, MigrationConnection};
+use tracing::info;
+
+/// A migration from `migrations/`, embedded in the binary by `build.rs`.
+pub struct Migration {
+    /// The directory name's timestamp without dashes, as diesel records it.
+    pub version: &'static str,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+/// Identifies the advisory lock held while migrating. Any number will do, as
+/// long as nothing else locks it.
+const LOCK_KEY: i64 = 0x6674_6d69_6772_6174;
+
+/// Every migration, oldest first.
+pub const MIGRATIONS: &[Migration] = include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// Migrations that haven't been applied to the database yet, oldest first.
+pub fn pending(conn: &PgConnection) -> QueryResult<Vec<&'static Migration>> {
+    setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| !applied.contains(m.version))
+        .collect())
+}
+
+/// Applies every pending migration, each in its own transaction, returning
+/// the ones that were applied. Instances starting at the same time take
+/// turns, so only the first applies anything.
+pub fn run_pending(conn: &PgConnection) -> QueryResult<Vec<&'static Migration>> {
+    locked(conn, || {
+        let pending = pending(conn)?;
+        for migration in &pending {
+            info!(migration = migration.name, "applying migration");
+            conn.transaction(|| {
+                conn.batch_execute(migration.up)?;
+                conn.insert_new_migration(migration.version)
+            })?;
+        }
+        Ok(pending)
+    })
+}
+
+/// Runs `f` holding the session advisory lock migrations take, waiting for
+/// any other connection holding it to finish first.
+fn locked<T>(conn: &PgConnection, f: impl FnOnce() -> QueryResult<T>) -> QueryResult<T> {
+    diesel::sql_query("SELECT pg_advisory_lock($1)")
+        .bind::<BigInt, _>(LOCK_KEY)
+        .execute(conn)?;
+    let result = f();
+    let unlocked = diesel::sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<BigInt, _>(LOCK_KEY)
+        .execute(conn);
+    let value = result?;
+    unlocked?;
+    Ok(value)
+}
+
+/// Reverts the most recently applied migration, returning it, or `None` if
+/// nothing has been applied. Takes the same lock as `run_pending`.
+pub fn revert_latest(conn: &PgConnection) -> QueryResult<Option<&'static Migration>> {
+    locked(conn, || {
+        setup_database(conn)?;
+        let latest = match conn.latest_run_migration_version()? {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        let migration = match MIGRATIONS.iter().find(|m| m.version == latest) {
+            Some(migration) => migration,
+            None => {
+                return Err(diesel::result::Error::QueryBuilderError(
+                    format!("migration {} was applied but isn't in this build", latest).into(),
+                ))
+            }
+        };
+
+        info!(migration = migration.name, "reverting migration");
+        conn.transaction(|| {
+            conn.batch_execute(migration.down)?;
+            diesel::sql_query("DELETE FROM __diesel_schema_migrations WHERE version = $1")
+                .bind::<Varchar, _>(migration.version)
+                .execute(conn)
+        })?;
+        Ok(Some(migration))
+    })
+}
+
+/// Every migration alongside whether it has been applied.
+pub fn status(conn: &PgConnection) -> QueryResult<Vec<(&'static Migration, bool)>> {
+    setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| (m, applied.contains(m.version)))
+        .collect())
+}
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse, Responder};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+
+use crate::db::{DBAdminAction, DBJob, DBUser, DBWebhookDelivery, DBWebhookMember, SwipeKind};
+use crate::paths::{admin, email, matches, password, sessions, swipe, tokens, users};
+use crate::recommend;
+use crate::response::Page;
+use crate::sessions::SessionRecord;
+use crate::webhooks::WebhookEvent;
+
+/// The OpenAPI document for every route, generated from the handlers'
+/// `#[utoipa::path]` attributes. Routes must be listed here as well as in
+/// `routes.rs`; `tests/openapi.rs` fails if the checked in `openapi.json` is
+/// out of date.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "fightingtinder"),
+    servers((url = "/api/v1")),
+    paths(
+        users::search_users,
+        users::create_user,
+        users::get_user_pic,
+        users::login,
+        users::logout,
+        email::verify_email,
+        password::request_reset,
+        password::confirm_reset,
+        tokens::issue_token,
+        tokens::refresh_token,
+        tokens::revoke_token,
+        users::check_login,
+        users::set_location,
+        users::set_bio,
+        users::set_discipline,
+        email::set_email,
+        email::resend_verification,
+        users::upload_profile_pic,
+        sessions::list_sessions,
+        sessions::revoke_all_sessions,
+        sessions::revoke_session,
+        swipe::do_swipe,
+        swipe::available,
+        swipe::likes_received,
+        matches::matches,
+        matches::get_match,
+        matches::delete_match,
+        matches::extend_match,
+        matches::expired_matches,
+        admin::search_users,
+        admin::get_user,
+        admin::clear_bio,
+        admin::clear_profile_pic,
+        admin::suspend_user,
+        admin::ban_user,
+        admin::reinstate_user,
+        admin::delete_match,
+        admin::audit_log,
+        admin::list_jobs,
+        admin::get_job,
+        admin::retry_job,
+        admin::register_webhook,
+        admin::list_webhooks,
+        admin::unregister_webhook,
+        admin::webhook_deliveries,
+        admin::webhook_members,
+        admin::add_webhook_member,
+        admin::remove_webhook_member,
+    ),
+    components(schemas(
+        DBUser,
+        DBAdminAction,
+        DBJob,
+        DBWebhookDelivery,
+        DBWebhookMember,
+        SessionRecord,
+        users::UserDTO,
+        users::SignupDTO,
+        users::LatLongDTO,
+        users::BioDTO,
+        users::DisciplineDTO,
+        users::PublicProfile,
+        email::EmailDTO,
+        password::ResetRequestDTO,
+        password::ResetConfirmDTO,
+        tokens::TokenPair,
+        tokens::RefreshDTO,
+        tokens::RevokeDTO,
+        sessions::SessionView,
+        swipe::SwipeDTO,
+        SwipeKind,
+        matches::UserMatch,
+        matches::ExpiredMatch,
+        admin::AdminUserView,
+        admin::SuspendDTO,
+        admin::BanDTO,
+        admin::WebhookView,
+        admin::RegisteredWebhook,
+        admin::WebhookDTO,
+        admin::WebhookMemberDTO,
+        WebhookEvent,
+        Page<users::PublicProfile>,
+        Page<swipe::Candidate>,
+        Page<swipe::LikeReceived>,
+        recommend::Score,
+        Page<matches::UserMatch>,
+        Page<matches::ExpiredMatch>,
+        Page<sessions::SessionView>,
+        Page<admin::AdminUserView>,
+        Page<DBAdminAction>,
+        Page<DBJob>,
+        Page<admin::WebhookView>,
+        Page<DBWebhookDelivery>,
+        Page<DBWebhookMember>,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "users", description = "Accounts, profiles and logging in"),
+        (name = "tokens", description = "Bearer tokens for clients that can't keep cookies"),
+        (name = "sessions", description = "Devices a user is logged in on"),
+        (name = "swipes"),
+        (name = "matches"),
+        (name = "admin", description = "Moderation, only for users with the admin role"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                "actix-session",
+                "set by logging in or signing up",
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+pub async fn openapi_json() -> impl Responder {
+    match ApiDoc::openapi().to_json() {
+        Ok(json) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+pub async fn docs_redirect() -> impl Responder {
+    HttpResponse::Found().header("Location", "/docs/").finish()
+}
+
+/// Serves the bundled Swagger UI, pointed at `/openapi.json`.
+pub async fn docs(
+    tail: web::Path<String>,
+    config: web::Data<Arc<Config<'static>>>,
+) -> impl Responder {
+    match utoipa_swagger_ui::serve(&tail, Arc::clone(&config)) {
+        Ok(Some(file)) => HttpResponse::Ok()
+            .content_type(file.content_type)
+            .body(file.bytes.into_owned()),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+pub fn swagger_config() -> Arc<Config<'static>> {
+    Arc::new(Config::from("/openapi.json"))
+}
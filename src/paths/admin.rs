@@ -0,0 +1,831 @@
+use std::{fs, io, ops::DerefMut, sync::Arc, time::Duration};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use diesel::{PgConnection, QueryResult, RunQueryDsl};
+use r2d2_redis::{r2d2::Pool, RedisConnectionManager};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db::{
+    DBAdminAction, DBJob, DBUser, DBWebhook, DBWebhookDelivery, DBWebhookMember, NewAdminAction,
+};
+use crate::jobs::{self, JobQueue};
+use crate::pic_cache::PicCache;
+use crate::repo::{AdminRepository, Audit, UserRepository, WebhookRepository};
+use crate::response::{json, Page};
+use crate::schema::admin_actions;
+use crate::sessions::SessionStore;
+use crate::tokens::{self, random_token};
+use crate::webhooks::{self, WebhookEvent};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// The longest suspension, about ten years. Anything longer should be a ban.
+const MAX_SUSPENSION_HOURS: i64 = 10 * 365 * 24;
+
+/// Everything about a user apart from their password hash.
+#[derive(Serialize, ToSchema)]
+pub struct AdminUserView {
+    username: String,
+    role: String,
+    lat: Option<f64>,
+    long: Option<f64>,
+    bio: Option<String>,
+    profile_pic: Option<String>,
+    suspended_until: Option<NaiveDateTime>,
+    banned: bool,
+}
+
+impl From<DBUser> for AdminUserView {
+    fn from(user: DBUser) -> Self {
+        AdminUserView {
+            username: user.username,
+            role: user.role,
+            lat: user.lat,
+            long: user.long,
+            bio: user.bio,
+            profile_pic: user.profile_pic,
+            suspended_until: user.suspended_until,
+            banned: user.banned,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    q: Option<String>,
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobsQuery {
+    /// Only jobs in this state: `queued`, `running`, `done` or `dead`.
+    state: Option<String>,
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesQuery {
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+/// A webhook, without its secret.
+#[derive(Serialize, ToSchema)]
+pub struct WebhookView {
+    id: i32,
+    url: String,
+    events: Vec<String>,
+    created_at: NaiveDateTime,
+}
+
+impl From<DBWebhook> for WebhookView {
+    fn from(webhook: DBWebhook) -> Self {
+        WebhookView {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+/// A new webhook, with the secret its deliveries are signed with. This is the
+/// only time the secret is shown.
+#[derive(Serialize, ToSchema)]
+pub struct RegisteredWebhook {
+    #[serde(flatten)]
+    webhook: WebhookView,
+    secret: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebhookDTO {
+    /// An http or https URL.
+    url: String,
+    events: Vec<WebhookEvent>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebhookMemberDTO {
+    username: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SuspendDTO {
+    /// From 1 up to about ten years.
+    hours: i64,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BanDTO {
+    reason: Option<String>,
+}
+
+fn admin_name(request: &HttpRequest) -> Option<String> {
+    request
+        .extensions()
+        .get::<DBUser>()
+        .map(|u| u.username.clone())
+}
+
+pub(crate) fn audit(
+    conn: &PgConnection,
+    admin: &str,
+    action: &str,
+    target: &str,
+    details: Option<String>,
+) -> QueryResult<()> {
+    diesel::insert_into(admin_actions::table)
+        .values(NewAdminAction {
+            admin,
+            action,
+            target,
+            details,
+        })
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Kicks a user off every device they're logged in on.
+pub(crate) fn end_sessions(
+    session_store: &Arc<dyn SessionStore>,
+    redis_pool: &Pool<RedisConnectionManager>,
+    username: &str,
+) -> Result<(), String> {
+    session_store
+        .revoke_all(username)
+        .map_err(|err| err.to_string())?;
+    let mut rd_conn = redis_pool
+        .get_timeout(Duration::from_millis(500))
+        .map_err(|err| err.to_string())?;
+    tokens::revoke_all(rd_conn.deref_mut(), username).map_err(|err| err.to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    params(SearchQuery),
+    responses((status = 200, description = "Matching users, including their location", body = Page<AdminUserView>)),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn search_users(
+    query: web::Query<SearchQuery>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    match admin_repo.users(query.q.as_deref(), query.after.as_deref(), limit + 1) {
+        Ok(found) => {
+            json(&Page::from_rows(found, limit, |u| u.username.clone()).map(AdminUserView::from))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users/{username}",
+    tag = "admin",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, description = "The user's full profile", body = AdminUserView),
+        (status = 404, description = "No such user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn get_user(
+    username: web::Path<String>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+) -> impl Responder {
+    match user_repo.find(&username) {
+        Ok(Some(user)) => json(&AdminUserView::from(user)),
+        Ok(None) => HttpResponse::NotFound().body("no such user"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{username}/bio",
+    tag = "admin",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, description = "Bio cleared"),
+        (status = 404, description = "No such user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn clear_bio(
+    request: HttpRequest,
+    username: web::Path<String>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let audit = Audit {
+        admin: &admin,
+        action: "clear_bio",
+        details: None,
+    };
+    match admin_repo.clear_bio(&username, &audit) {
+        Ok(false) => HttpResponse::NotFound().body("no such user"),
+        Ok(true) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{username}/profile_pic",
+    tag = "admin",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, description = "Picture removed"),
+        (status = 404, description = "No such user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn clear_profile_pic(
+    request: HttpRequest,
+    username: web::Path<String>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+    pic_cache: web::Data<Arc<PicCache>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let audit = Audit {
+        admin: &admin,
+        action: "clear_profile_pic",
+        details: None,
+    };
+    let filename = match admin_repo.clear_profile_pic(&username, &audit) {
+        Ok(Some(user)) => user.profile_pic,
+        Ok(None) => return HttpResponse::NotFound().body("no such user"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    if let Some(filename) = filename {
+        if let Err(err) = fs::remove_file(&filename) {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!(%filename, error = %err, "failed to remove profile pic");
+            }
+        }
+    }
+
+    pic_cache.evict(&username);
+
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{username}/suspend",
+    tag = "admin",
+    params(("username" = String, Path)),
+    request_body = SuspendDTO,
+    responses(
+        (status = 200, description = "User suspended and logged out everywhere"),
+        (status = 400, description = "`hours` is below 1 or over about ten years"),
+        (status = 404, description = "No such user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn suspend_user(
+    request: HttpRequest,
+    username: web::Path<String>,
+    suspend: web::Json<SuspendDTO>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    if suspend.hours <= 0 {
+        return HttpResponse::BadRequest().body("suspension must be at least an hour");
+    }
+    if suspend.hours > MAX_SUSPENSION_HOURS {
+        return HttpResponse::BadRequest().body("suspension must be at most ten years");
+    }
+
+    let until = chrono::Utc::now().naive_utc() + chrono::Duration::hours(suspend.hours);
+    let details = match &suspend.reason {
+        Some(reason) => format!("until {}: {}", until, reason),
+        None => format!("until {}", until),
+    };
+    let audit = Audit {
+        admin: &admin,
+        action: "suspend",
+        details: Some(details),
+    };
+    match admin_repo.suspend(&username, until, &audit) {
+        Ok(false) => return HttpResponse::NotFound().body("no such user"),
+        Ok(true) => {}
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    }
+
+    match end_sessions(&session_store, &redis_pool, &username) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{username}/ban",
+    tag = "admin",
+    params(("username" = String, Path)),
+    request_body = BanDTO,
+    responses(
+        (status = 200, description = "User banned and logged out everywhere"),
+        (status = 404, description = "No such user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn ban_user(
+    request: HttpRequest,
+    username: web::Path<String>,
+    ban: web::Json<BanDTO>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let audit = Audit {
+        admin: &admin,
+        action: "ban",
+        details: ban.into_inner().reason,
+    };
+    match admin_repo.ban(&username, &audit) {
+        Ok(false) => return HttpResponse::NotFound().body("no such user"),
+        Ok(true) => {}
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    }
+
+    match end_sessions(&session_store, &redis_pool, &username) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err),
+    }
+}
+
+/// Lifts any ban or suspension on a user.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{username}/reinstate",
+    tag = "admin",
+    params(("username" = String, Path)),
+    responses(
+        (status = 200, description = "Ban and suspension lifted"),
+        (status = 404, description = "No such user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn reinstate_user(
+    request: HttpRequest,
+    username: web::Path<String>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let audit = Audit {
+        admin: &admin,
+        action: "reinstate",
+        details: None,
+    };
+    match admin_repo.reinstate(&username, &audit) {
+        Ok(false) => HttpResponse::NotFound().body("no such user"),
+        Ok(true) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/matches/{username1}/{username2}",
+    tag = "admin",
+    params(("username1" = String, Path), ("username2" = String, Path)),
+    responses(
+        (status = 200, description = "Match deleted"),
+        (status = 404, description = "No such match"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn delete_match(
+    request: HttpRequest,
+    path: web::Path<(String, String)>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+    job_queue: web::Data<Arc<dyn JobQueue>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let (username1, username2) = path.into_inner();
+    let audit = Audit {
+        admin: &admin,
+        action: "delete_match",
+        details: None,
+    };
+    match admin_repo.delete_match(&username1, &username2, &audit) {
+        Ok(false) => HttpResponse::NotFound().body("no such match"),
+        Ok(true) => {
+            let usernames = webhooks::match_usernames(&username1, &username2);
+            let data = serde_json::json!({
+                "usernames": usernames,
+                "reason": "removed",
+            });
+            webhooks::emit(&***job_queue, WebhookEvent::MatchDeleted, &usernames, data);
+            HttpResponse::Ok().finish()
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    params(SearchQuery),
+    responses((status = 200, description = "Admin actions, newest first", body = Page<DBAdminAction>)),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn audit_log(
+    query: web::Query<SearchQuery>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+) -> impl Responder {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let before = query.after.as_ref().and_then(|a| a.parse::<i32>().ok());
+
+    match admin_repo.actions(query.q.as_deref(), before, limit + 1) {
+        Ok(actions) => json(&Page::from_rows(actions, limit, |a| a.id.to_string())),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "admin",
+    params(JobsQuery),
+    responses(
+        (status = 200, description = "Jobs, newest first", body = Page<DBJob>),
+        (status = 400, description = "Unknown state"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn list_jobs(
+    query: web::Query<JobsQuery>,
+    job_queue: web::Data<Arc<dyn JobQueue>>,
+) -> impl Responder {
+    let state = query.state.as_deref();
+    if state.is_some_and(|s| ![jobs::QUEUED, jobs::RUNNING, jobs::DONE, jobs::DEAD].contains(&s)) {
+        return HttpResponse::BadRequest().body("unknown job state");
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let before = query.after.as_ref().and_then(|a| a.parse::<i64>().ok());
+
+    match job_queue.list(state, before, limit + 1) {
+        Ok(found) => json(&Page::from_rows(found, limit, |j| j.id.to_string())),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/jobs/{id}",
+    tag = "admin",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, description = "The job, with its last error if it has failed", body = DBJob),
+        (status = 404, description = "No such job"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn get_job(
+    id: web::Path<i64>,
+    job_queue: web::Data<Arc<dyn JobQueue>>,
+) -> impl Responder {
+    match job_queue.find(id.into_inner()) {
+        Ok(Some(job)) => json(&job),
+        Ok(None) => HttpResponse::NotFound().body("no such job"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Gives a dead job another full set of attempts.
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{id}/retry",
+    tag = "admin",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, description = "Job queued to run again"),
+        (status = 404, description = "No such job"),
+        (status = 409, description = "The job isn't dead"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn retry_job(
+    request: HttpRequest,
+    id: web::Path<i64>,
+    job_queue: web::Data<Arc<dyn JobQueue>>,
+    admin_repo: web::Data<Arc<dyn AdminRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let id = id.into_inner();
+    match job_queue.retry(id) {
+        Ok(true) => {}
+        Ok(false) => {
+            return match job_queue.find(id) {
+                Ok(Some(_)) => HttpResponse::Conflict().body("only dead jobs can be retried"),
+                Ok(None) => HttpResponse::NotFound().body("no such job"),
+                Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+            }
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    }
+
+    let audit = Audit {
+        admin: &admin,
+        action: "retry_job",
+        details: None,
+    };
+    match admin_repo.record(&id.to_string(), &audit) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    request_body = WebhookDTO,
+    responses(
+        (status = 200, description = "Webhook registered", body = RegisteredWebhook),
+        (status = 400, description = "Invalid URL or no events"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn register_webhook(
+    request: HttpRequest,
+    webhook: web::Json<WebhookDTO>,
+    webhook_repo: web::Data<Arc<dyn WebhookRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let valid_url = (webhook.url.starts_with("http://") || webhook.url.starts_with("https://"))
+        && webhook.url.parse::<actix_web::http::Uri>().is_ok();
+    if !valid_url {
+        return HttpResponse::BadRequest().body("url must be an http or https URL");
+    }
+    let mut events = Vec::new();
+    for event in &webhook.events {
+        if !events.contains(event) {
+            events.push(*event);
+        }
+    }
+    if events.is_empty() {
+        return HttpResponse::BadRequest().body("events must not be empty");
+    }
+
+    let secret = random_token();
+    let audit = Audit {
+        admin: &admin,
+        action: "register_webhook",
+        details: Some(webhook.url.clone()),
+    };
+    match webhook_repo.register(&webhook.url, &secret, &events, &audit) {
+        Ok(registered) => json(&RegisteredWebhook {
+            webhook: WebhookView::from(registered),
+            secret,
+        }),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    responses((status = 200, description = "Every webhook, oldest first", body = Page<WebhookView>)),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn list_webhooks(webhook_repo: web::Data<Arc<dyn WebhookRepository>>) -> impl Responder {
+    match webhook_repo.webhooks() {
+        Ok(found) => json(&Page::all(found).map(WebhookView::from)),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Stops sending events to a webhook and removes its deliveries.
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}",
+    tag = "admin",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, description = "Webhook removed"),
+        (status = 404, description = "No such webhook"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn unregister_webhook(
+    request: HttpRequest,
+    id: web::Path<i32>,
+    webhook_repo: web::Data<Arc<dyn WebhookRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let audit = Audit {
+        admin: &admin,
+        action: "unregister_webhook",
+        details: None,
+    };
+    match webhook_repo.unregister(id.into_inner(), &audit) {
+        Ok(false) => HttpResponse::NotFound().body("no such webhook"),
+        Ok(true) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{id}/members",
+    tag = "admin",
+    params(("id" = i32, Path)),
+    responses(
+        (status = 200, description = "The users whose events the webhook is sent, by username", body = Page<DBWebhookMember>),
+        (status = 404, description = "No such webhook"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn webhook_members(
+    id: web::Path<i32>,
+    webhook_repo: web::Data<Arc<dyn WebhookRepository>>,
+) -> impl Responder {
+    let id = id.into_inner();
+    match webhook_repo.webhook(id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().body("no such webhook"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    }
+
+    match webhook_repo.members(id) {
+        Ok(found) => json(&Page::all(found)),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Sends the webhook events involving a user, as a member of the gym that
+/// registered it.
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/{id}/members",
+    tag = "admin",
+    params(("id" = i32, Path)),
+    request_body = WebhookMemberDTO,
+    responses(
+        (status = 200, description = "Member added, or already one"),
+        (status = 404, description = "No such webhook or user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn add_webhook_member(
+    request: HttpRequest,
+    id: web::Path<i32>,
+    member: web::Json<WebhookMemberDTO>,
+    webhook_repo: web::Data<Arc<dyn WebhookRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let audit = Audit {
+        admin: &admin,
+        action: "add_webhook_member",
+        details: Some(member.username.clone()),
+    };
+    match webhook_repo.add_member(id.into_inner(), &member.username, &audit) {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().body("no such webhook or user"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}/members/{username}",
+    tag = "admin",
+    params(("id" = i32, Path), ("username" = String, Path)),
+    responses(
+        (status = 200, description = "Member removed"),
+        (status = 404, description = "The user isn't a member of this webhook"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn remove_webhook_member(
+    request: HttpRequest,
+    path: web::Path<(i32, String)>,
+    webhook_repo: web::Data<Arc<dyn WebhookRepository>>,
+) -> impl Responder {
+    let admin = match admin_name(&request) {
+        Some(admin) => admin,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let (id, username) = path.into_inner();
+    let audit = Audit {
+        admin: &admin,
+        action: "remove_webhook_member",
+        details: Some(username.clone()),
+    };
+    match webhook_repo.remove_member(id, &username, &audit) {
+        Ok(false) => HttpResponse::NotFound().body("not a member of this webhook"),
+        Ok(true) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{id}/deliveries",
+    tag = "admin",
+    params(("id" = i32, Path), DeliveriesQuery),
+    responses(
+        (status = 200, description = "The webhook's deliveries, newest first", body = Page<DBWebhookDelivery>),
+        (status = 404, description = "No such webhook"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn webhook_deliveries(
+    id: web::Path<i32>,
+    query: web::Query<DeliveriesQuery>,
+    webhook_repo: web::Data<Arc<dyn WebhookRepository>>,
+) -> impl Responder {
+    let id = id.into_inner();
+    match webhook_repo.webhook(id) {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().body("no such webhook"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let before = query.after.as_ref().and_then(|a| a.parse::<i64>().ok());
+
+    match webhook_repo.deliveries(id, before, limit + 1) {
+        Ok(found) => json(&Page::from_rows(found, limit, |d| d.id.to_string())),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db::{DBEmailVerification, DBUser};
+use crate::mail::Outbox;
+use crate::repo::{RepoError, UserRepository};
+use crate::tokens::{hash_token, random_token};
+
+/// How long a verification link stays valid.
+const VERIFICATION_TTL_HOURS: i64 = 48;
+
+#[derive(Deserialize, ToSchema)]
+pub struct EmailDTO {
+    email: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerifyQuery {
+    token: String,
+}
+
+/// A loose sanity check; the only real test of an address is whether the
+/// verification email arrives.
+pub(crate) fn valid_email(email: &str) -> bool {
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    email.len() <= 254
+        && !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Issues a new verification token for a user's email and mails it to them.
+pub(crate) async fn send_verification(
+    user_repo: &dyn UserRepository,
+    outbox: &Arc<Outbox>,
+    username: &str,
+    email: &str,
+) -> Result<(), String> {
+    let token = random_token();
+    let verification = DBEmailVerification {
+        token_hash: hash_token(&token),
+        username: username.to_string(),
+        email: email.to_string(),
+        expires_at: chrono::Utc::now().naive_utc()
+            + chrono::Duration::hours(VERIFICATION_TTL_HOURS),
+    };
+    user_repo
+        .add_email_verification(verification)
+        .map_err(|err| err.to_string())?;
+
+    let message = outbox.verification(email, username, &token);
+    let outbox = Arc::clone(outbox);
+    web::block(move || outbox.send(&message))
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/verify",
+    tag = "users",
+    params(VerifyQuery),
+    responses(
+        (status = 200, description = "Email verified"),
+        (status = 400, description = "Link invalid or expired"),
+    )
+)]
+pub async fn verify_email(
+    query: web::Query<VerifyQuery>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+) -> impl Responder {
+    match user_repo.verify_email(&hash_token(&query.token)) {
+        Ok(false) => HttpResponse::BadRequest().body("verification link invalid or expired"),
+        Ok(true) => HttpResponse::Ok().body("email verified"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/manage/email",
+    tag = "users",
+    request_body = EmailDTO,
+    responses(
+        (status = 200, description = "Email changed and verification email sent"),
+        (status = 400, description = "Invalid email"),
+        (status = 409, description = "Email already registered"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn set_email(
+    request: HttpRequest,
+    email: web::Json<EmailDTO>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    outbox: web::Data<Arc<Outbox>>,
+) -> impl Responder {
+    let username = match request.extensions().get::<DBUser>() {
+        Some(user) => user.username.clone(),
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let email = email.into_inner().email.trim().to_string();
+    if !valid_email(&email) {
+        return HttpResponse::BadRequest().body("invalid email address");
+    }
+
+    match user_repo.set_email(&username, &email) {
+        Ok(()) => {}
+        Err(RepoError::Conflict(_)) => {
+            return HttpResponse::Conflict().body("email already registered")
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    }
+
+    match send_verification(&***user_repo, &outbox, &username, &email).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/manage/email/resend",
+    tag = "users",
+    responses(
+        (status = 200, description = "Verification email sent"),
+        (status = 400, description = "No email set, or it's already verified"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn resend_verification(
+    request: HttpRequest,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    outbox: web::Data<Arc<Outbox>>,
+) -> impl Responder {
+    let (username, email, verified) = match request.extensions().get::<DBUser>() {
+        Some(user) => (
+            user.username.clone(),
+            user.email.clone(),
+            user.email_verified,
+        ),
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let email = match email {
+        Some(email) if !verified => email,
+        Some(_) => return HttpResponse::BadRequest().body("email already verified"),
+        None => return HttpResponse::BadRequest().body("no email set"),
+    };
+
+    match send_verification(&***user_repo, &outbox, &username, &email).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err),
+    }
+}
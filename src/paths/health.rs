@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::{fs, ops::DerefMut, sync::Arc, time::Duration, time::Instant};
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, Responder};
+use r2d2_redis::{r2d2::Pool, RedisConnectionManager};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::paths::users::PROFILE_PIC_DIR;
+use crate::pic_cache::PicCache;
+use crate::repo::UserRepository;
+use crate::response::json_with_status;
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    /// Whether the instance can't serve traffic while this check is failing.
+    critical: bool,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    breaker: Option<&'static str>,
+}
+
+impl Check {
+    fn run(critical: bool, check: impl FnOnce() -> Result<(), String>) -> Check {
+        let started = Instant::now();
+        let result = check();
+        Check {
+            ok: result.is_ok(),
+            critical,
+            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+            error: result.err(),
+            breaker: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+fn check_redis(pool: &Pool<RedisConnectionManager>) -> Result<(), String> {
+    let mut conn = pool
+        .get_timeout(Duration::from_millis(500))
+        .map_err(|err| err.to_string())?;
+    r2d2_redis::redis::cmd("PING")
+        .query::<String>(conn.deref_mut())
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn check_profile_pics() -> Result<(), String> {
+    let probe = format!("{}/.readyz-{:016x}", PROFILE_PIC_DIR, rand::random::<u64>());
+    fs::write(&probe, b"ok").map_err(|err| err.to_string())?;
+    fs::remove_file(&probe).map_err(|err| err.to_string())
+}
+
+/// Liveness: answers as long as the process is serving requests at all.
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness: checks every dependency, answering 503 if a critical one is
+/// down so that traffic is sent elsewhere. Redis and the profile picture
+/// directory only mark the instance degraded: without redis logins and
+/// sessions fail but pictures are still served from disk, and without the
+/// directory only uploads fail. Redis is shared by every instance, so taking
+/// this one out of rotation wouldn't help anyway.
+pub async fn readyz(
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    pic_cache: web::Data<Arc<PicCache>>,
+) -> impl Responder {
+    let mut redis = Check::run(false, || check_redis(&redis_pool));
+    redis.breaker = Some(pic_cache.breaker_state());
+
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "postgres",
+        Check::run(true, || user_repo.ping().map_err(|err| err.to_string())),
+    );
+    checks.insert("redis", redis);
+    checks.insert("profile_pics", Check::run(false, check_profile_pics));
+
+    for (name, check) in checks.iter().filter(|(_, check)| !check.ok) {
+        warn!(dependency = name, error = ?check.error, "readiness check failed");
+    }
+
+    let (status, code) = if checks.values().any(|c| c.critical && !c.ok) {
+        ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+    } else if checks.values().any(|c| !c.ok) {
+        ("degraded", StatusCode::OK)
+    } else {
+        ("ok", StatusCode::OK)
+    };
+
+    json_with_status(code, &Readiness { status, checks })
+}
@@ -1,33 +1,85 @@
-use crate::db::{DBMatch, DBUser};
+use crate::db::{DBMatch, DBMatchHistory, DBUser};
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use diesel::r2d2::{ConnectionManager, Pool};
-use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use chrono::NaiveDateTime;
 use serde::Serialize;
 use std::sync::Arc;
-use std::time::Duration;
+use utoipa::ToSchema;
 
-use crate::schema::matches;
+use crate::expiry::MatchExpiry;
+use crate::jobs::JobQueue;
+use crate::metrics::Metrics;
+use crate::repo::{MatchRepository, RepoError};
+use crate::response::{json, Page};
+use crate::webhooks::{self, WebhookEvent};
 
-#[derive(Debug, Serialize)]
-struct UserMatch {
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserMatch {
     name: String,
+    matched_at: NaiveDateTime,
+    /// When the match expires unless it's extended first.
+    expires_at: NaiveDateTime,
+    /// Whether either user has used the match's one extension.
+    extended: bool,
 }
 
 impl UserMatch {
-    fn from_record(username: &str, m: DBMatch) -> UserMatch {
+    fn from_record(username: &str, m: DBMatch, expiry: &MatchExpiry) -> UserMatch {
+        let expires_at = expiry.expires_at(&m);
         let other = if m.username1 == username {
             m.username2
         } else {
             m.username1
         };
 
-        UserMatch { name: other }
+        UserMatch {
+            name: other,
+            matched_at: m.created_at,
+            expires_at,
+            extended: m.extended_by.is_some(),
+        }
     }
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExpiredMatch {
+    id: i32,
+    name: String,
+    matched_at: NaiveDateTime,
+    last_activity_at: NaiveDateTime,
+    extended: bool,
+    expired_at: NaiveDateTime,
+}
+
+impl ExpiredMatch {
+    fn from_record(username: &str, m: DBMatchHistory) -> ExpiredMatch {
+        let other = if m.username1 == username {
+            m.username2
+        } else {
+            m.username1
+        };
+
+        ExpiredMatch {
+            id: m.id,
+            name: other,
+            matched_at: m.created_at,
+            last_activity_at: m.last_activity_at,
+            extended: m.extended_by.is_some(),
+            expired_at: m.expired_at,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/match",
+    tag = "matches",
+    responses((status = 200, description = "Everyone the caller has matched with", body = Page<UserMatch>)),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn matches(
     request: HttpRequest,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    match_repo: web::Data<Arc<dyn MatchRepository>>,
+    expiry: web::Data<Arc<MatchExpiry>>,
 ) -> impl Responder {
     let ext = request.extensions();
     let username: &str = match ext.get::<DBUser>() {
@@ -35,32 +87,62 @@ pub async fn matches(
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-    };
-
-    let matches = match matches::table
-        .filter(matches::username1.eq(&username))
-        .or_filter(matches::username2.eq(&username))
-        .load::<DBMatch>(&conn)
-    {
+    let matches = match match_repo.for_user(username) {
         Ok(matches) => matches,
         Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
 
     let matches: Vec<UserMatch> = matches
         .into_iter()
-        .map(|m| UserMatch::from_record(&username, m))
+        .map(|m| UserMatch::from_record(username, m, &expiry))
         .collect();
-    let as_string = serde_json::to_string(&matches).expect("unable to jsonify DBUsers");
-    HttpResponse::Ok().body(as_string)
+    json(&Page::all(matches))
+}
+
+#[utoipa::path(
+    get,
+    path = "/match/{username}",
+    tag = "matches",
+    params(("username" = String, Path, description = "Who the match is with")),
+    responses(
+        (status = 200, description = "The match, whose expiry now counts from now", body = UserMatch),
+        (status = 404, description = "Not matched with this user"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn get_match(
+    request: HttpRequest,
+    other: web::Path<String>,
+    match_repo: web::Data<Arc<dyn MatchRepository>>,
+    expiry: web::Data<Arc<MatchExpiry>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(u) => &u.username,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    match match_repo.open(username, &other) {
+        Ok(Some(m)) => json(&UserMatch::from_record(username, m, &expiry)),
+        Ok(None) => HttpResponse::NotFound().body("not matched with this user"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/match/{username}",
+    tag = "matches",
+    params(("username" = String, Path, description = "Who to unmatch from")),
+    responses((status = 200, description = "Unmatched")),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn delete_match(
     request: HttpRequest,
     other: web::Path<String>,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    match_repo: web::Data<Arc<dyn MatchRepository>>,
+    job_queue: web::Data<Arc<dyn JobQueue>>,
+    metrics: web::Data<Arc<Metrics>>,
 ) -> impl Responder {
     let ext = request.extensions();
     let username: &str = match ext.get::<DBUser>() {
@@ -68,28 +150,80 @@ pub async fn delete_match(
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    match match_repo.delete(username, &other) {
+        Ok(deleted) => {
+            if deleted {
+                metrics.unmatches.inc();
+                let usernames = webhooks::match_usernames(username, &other);
+                let data = serde_json::json!({
+                    "usernames": usernames,
+                    "reason": "unmatched",
+                });
+                webhooks::emit(&***job_queue, WebhookEvent::MatchDeleted, &usernames, data);
+            }
+            HttpResponse::Ok().finish()
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/match/{username}/extend",
+    tag = "matches",
+    params(("username" = String, Path, description = "Who the match to extend is with")),
+    responses(
+        (status = 200, description = "Match extended, its expiry counting from now"),
+        (status = 404, description = "Not matched with this user"),
+        (status = 409, description = "The match has already been extended"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn extend_match(
+    request: HttpRequest,
+    other: web::Path<String>,
+    match_repo: web::Data<Arc<dyn MatchRepository>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(u) => &u.username,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let other = other.into_inner();
-    match diesel::delete(
-        matches::table
-            .filter(
-                matches::username1
-                    .eq(&username)
-                    .and(matches::username2.eq(&other)),
-            )
-            .or_filter(
-                matches::username1
-                    .eq(&other)
-                    .and(matches::username2.eq(&username)),
-            ),
-    )
-    .execute(&conn)
-    {
-        Ok(_) => HttpResponse::Ok().finish(),
+    match match_repo.extend(username, &other) {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().body("not matched with this user"),
+        Err(RepoError::Conflict(_)) => {
+            HttpResponse::Conflict().body("this match has already been extended")
+        }
         Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
     }
 }
+
+#[utoipa::path(
+    get,
+    path = "/match/expired",
+    tag = "matches",
+    responses((status = 200, description = "The caller's matches that expired, most recent first", body = Page<ExpiredMatch>)),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn expired_matches(
+    request: HttpRequest,
+    match_repo: web::Data<Arc<dyn MatchRepository>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(u) => &u.username,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let expired = match match_repo.expired_for(username) {
+        Ok(expired) => expired,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let expired: Vec<ExpiredMatch> = expired
+        .into_iter()
+        .map(|m| ExpiredMatch::from_record(username, m))
+        .collect();
+    json(&Page::all(expired))
+}
@@ -1,3 +1,9 @@
+pub mod admin;
+pub mod email;
+pub mod health;
 pub mod matches;
+pub mod password;
+pub mod sessions;
 pub mod swipe;
+pub mod tokens;
 pub mod users;
@@ -0,0 +1,163 @@
+use std::{ops::DerefMut, sync::Arc, time::Duration};
+
+use actix_web::{rt, web, HttpResponse, Responder};
+use r2d2_redis::{r2d2::Pool, RedisConnectionManager};
+use serde::Deserialize;
+use tracing::{error, warn, Instrument};
+use utoipa::ToSchema;
+
+use crate::db::DBPasswordReset;
+use crate::mail::Outbox;
+use crate::repo::UserRepository;
+use crate::sessions::SessionStore;
+use crate::throttle::LoginThrottle;
+use crate::tokens::{self, hash_token, random_token};
+
+/// How long a reset link stays valid.
+const RESET_TTL_HOURS: i64 = 1;
+/// Only one reset email is sent for an account per this many seconds, so the
+/// endpoint can't be used to flood someone's inbox.
+const RESET_EMAIL_INTERVAL_SECS: u64 = 60;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetRequestDTO {
+    email: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetConfirmDTO {
+    token: String,
+    password: String,
+}
+
+/// Always answers the same way, and as quickly, whether or not the email
+/// belongs to anyone, so this can't be used to find out who has an account.
+/// Only verified addresses are sent a reset link.
+#[utoipa::path(
+    post,
+    path = "/user/password-reset/request",
+    tag = "users",
+    request_body = ResetRequestDTO,
+    responses((status = 200, description = "A reset email was sent if the address belongs to an account"))
+)]
+pub async fn request_reset(
+    reset: web::Json<ResetRequestDTO>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    outbox: web::Data<Arc<Outbox>>,
+) -> impl Responder {
+    let email = reset.into_inner().email.trim().to_string();
+
+    // everything, looking the address up included, happens in the background
+    // so the response takes as long for a known address as an unknown one
+    let user_repo = Arc::clone(&user_repo);
+    let redis_pool = Arc::clone(&redis_pool);
+    let outbox = Arc::clone(&outbox);
+    rt::spawn(
+        async move {
+            let sent = web::block(move || send_reset(&**user_repo, &redis_pool, &outbox, &email));
+            if let Err(err) = sent.await {
+                error!(error = %err, "error sending password reset email");
+            }
+        }
+        .in_current_span(),
+    );
+
+    HttpResponse::Ok().finish()
+}
+
+/// Emails a reset link to the account with this verified address, if there is
+/// one and it wasn't sent one in the last `RESET_EMAIL_INTERVAL_SECS`.
+fn send_reset(
+    user_repo: &dyn UserRepository,
+    redis_pool: &Pool<RedisConnectionManager>,
+    outbox: &Outbox,
+    email: &str,
+) -> Result<(), String> {
+    let user = match user_repo.find_by_verified_email(email) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(()),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut rd_conn = redis_pool
+        .get_timeout(Duration::from_millis(500))
+        .map_err(|err| err.to_string())?;
+    let first_recently: Option<String> = r2d2_redis::redis::cmd("SET")
+        .arg(format!("password_reset:{}", user.username))
+        .arg(1)
+        .arg("EX")
+        .arg(RESET_EMAIL_INTERVAL_SECS)
+        .arg("NX")
+        .query(rd_conn.deref_mut())
+        .map_err(|err| err.to_string())?;
+    if first_recently.is_none() {
+        return Ok(());
+    }
+
+    let token = random_token();
+    let reset = DBPasswordReset {
+        token_hash: hash_token(&token),
+        username: user.username.clone(),
+        expires_at: chrono::Utc::now().naive_utc() + chrono::Duration::hours(RESET_TTL_HOURS),
+        used_at: None,
+    };
+    user_repo
+        .add_password_reset(reset)
+        .map_err(|err| err.to_string())?;
+
+    let message = outbox.password_reset(email, &user.username, &token);
+    outbox.send(&message).map_err(|err| err.to_string())
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/password-reset/confirm",
+    tag = "users",
+    request_body = ResetConfirmDTO,
+    responses(
+        (status = 200, description = "Password changed and every session ended"),
+        (status = 400, description = "Link invalid or expired, or the password is too short"),
+    )
+)]
+pub async fn confirm_reset(
+    reset: web::Json<ResetConfirmDTO>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+    throttle: web::Data<Arc<dyn LoginThrottle>>,
+) -> impl Responder {
+    let reset = reset.into_inner();
+    if reset.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return HttpResponse::BadRequest().body(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+
+    let password = bcrypt::hash(&reset.password, 10).expect("unable to encrypt user password");
+    let username = match user_repo.reset_password(&hash_token(&reset.token), &password) {
+        Ok(Some(username)) => username,
+        Ok(None) => return HttpResponse::BadRequest().body("reset link invalid or expired"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    // whoever knew the old password shouldn't stay logged in with it
+    if let Err(err) = session_store.revoke_all(&username) {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
+
+    let mut rd_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
+        Ok(rd_conn) => rd_conn,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    if let Err(err) = tokens::revoke_all(rd_conn.deref_mut(), &username) {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
+    if let Err(err) = throttle.record_success(&username.to_lowercase()) {
+        warn!(%username, error = %err, "error clearing failed logins");
+    }
+
+    HttpResponse::Ok().finish()
+}
@@ -0,0 +1,120 @@
+use std::{ops::DerefMut, sync::Arc, time::Duration};
+
+use actix_session::Session;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use diesel::r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::DBUser;
+use crate::response::{json, Page};
+use crate::sessions::{CurrentSession, SessionRecord, SessionStore};
+use crate::tokens;
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionView {
+    #[serde(flatten)]
+    #[schema(inline)]
+    record: SessionRecord,
+    current: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/manage/sessions",
+    tag = "sessions",
+    responses((status = 200, description = "Every device the user is logged in on", body = Page<SessionView>)),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn list_sessions(
+    request: HttpRequest,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(u) => &u.username,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+    let current = ext.get::<CurrentSession>().map(|c| c.0.as_str());
+
+    let records = match session_store.list(username) {
+        Ok(records) => records,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let views: Vec<SessionView> = records
+        .into_iter()
+        .map(|record| SessionView {
+            current: Some(record.id.as_str()) == current,
+            record,
+        })
+        .collect();
+    json(&Page::all(views))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/manage/sessions/{id}",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session id from the session list")),
+    responses(
+        (status = 200, description = "Session ended"),
+        (status = 404, description = "No such session"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn revoke_session(
+    request: HttpRequest,
+    id: web::Path<String>,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(u) => &u.username,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    match session_store.revoke(username, &id) {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().body("no such session"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Logs the user out everywhere, ending every cookie session and revoking
+/// every token they've been issued.
+#[utoipa::path(
+    delete,
+    path = "/user/manage/sessions",
+    tag = "sessions",
+    responses((status = 200, description = "Logged out everywhere")),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn revoke_all_sessions(
+    request: HttpRequest,
+    session: Session,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(u) => &u.username,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    if let Err(err) = session_store.revoke_all(username) {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
+
+    let mut rd_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
+        Ok(rd_conn) => rd_conn,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    if let Err(err) = tokens::revoke_all(rd_conn.deref_mut(), username) {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
+
+    session.purge();
+    HttpResponse::Ok().finish()
+}
@@ -1,69 +1,210 @@
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use diesel::{
-    dsl::{exists, not},
-    r2d2::{ConnectionManager, Pool},
-    BoolExpressionMethods, ExpressionMethods, Insertable, PgConnection, QueryDsl, RunQueryDsl,
-};
 use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::db::{DBMatch, DBSwipe, DBUser};
-use crate::schema::matches;
-use crate::schema::swipes;
-use crate::schema::users;
+use crate::db::{DBUser, SwipeKind};
+use crate::jobs::{Job, JobQueue};
+use crate::metrics::Metrics;
+use crate::paths::users::PublicProfile;
+use crate::quota::{self, SuperLikeQuota};
+use crate::recommend::{self, Score, Signals, Weights};
+use crate::repo::{MatchRepository, RepoError, SwipeRepository, UserRepository};
+use crate::response::{json, Page};
+use crate::webhooks::{self, WebhookEvent};
 
-#[derive(Insertable, Serialize, Deserialize, Debug)]
-#[table_name = "swipes"]
+/// How many users `available` offers at a time.
+const AVAILABLE_PAGE_SIZE: usize = 10;
+
+/// How many of the nearest unswiped users `available` ranks to pick from.
+const CANDIDATE_POOL_SIZE: i64 = 200;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct SwipeDTO {
     swiped: String,
-    status: bool,
+    kind: Option<SwipeKind>,
+    /// Deprecated, use `kind`. True for a like, false for a pass. Ignored if
+    /// `kind` is set.
+    status: Option<bool>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AvailableQuery {
+    /// Include each candidate's score breakdown. Admins only.
+    #[serde(default)]
+    debug: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LikesReceivedQuery {
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Someone who swiped right on the caller.
+#[derive(Serialize, ToSchema)]
+pub struct LikeReceived {
+    #[serde(flatten)]
+    profile: PublicProfile,
+    super_liked: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Candidate {
+    #[serde(flatten)]
+    profile: PublicProfile,
+    /// Whether they super-liked the caller. Super-likers come first.
+    super_liked: bool,
+    /// Only included when an admin asks for it with `debug=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<Score>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/swipe/available",
+    tag = "swipes",
+    params(AvailableQuery),
+    responses(
+        (status = 200, description = "Users the caller hasn't swiped on yet, best match first", body = Page<Candidate>),
+        (status = 403, description = "`debug` was set by someone who isn't an admin"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn available(
     request: HttpRequest,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    query: web::Query<AvailableQuery>,
+    swipe_repo: web::Data<Arc<dyn SwipeRepository>>,
+    weights: web::Data<Arc<Weights>>,
 ) -> impl Responder {
     let ext = request.extensions();
-    let username: &str = match ext.get::<DBUser>() {
-        Some(u) => &u.username,
+    let user: &DBUser = match ext.get::<DBUser>() {
+        Some(u) => u,
         None => panic!("route must always be accessed through auth"),
     };
+    if query.debug && !user.is_admin() {
+        return HttpResponse::Forbidden().body("only admins can see scores");
+    }
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => {
-            eprintln!("failed to get pg conn for available handler");
-            return HttpResponse::InternalServerError().body(err.to_string());
-        }
+    let super_likers = match swipe_repo.super_likers(&user.username, AVAILABLE_PAGE_SIZE as i64) {
+        Ok(super_likers) => super_likers,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
+    let near = user.lat.zip(user.long);
+    let candidates = match swipe_repo.unswiped(&user.username, near, CANDIDATE_POOL_SIZE) {
+        Ok(candidates) => candidates,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let candidates: Vec<DBUser> = candidates
+        .into_iter()
+        .filter(|c| !super_likers.iter().any(|s| s.username == c.username))
+        .collect();
 
-    let not_swiped_on = users::table
-        .filter(
-            not(exists(
-                swipes::table
-                    .filter(swipes::swiper.eq(&username))
-                    .filter(swipes::swiped.eq(users::username)),
-            ))
-            .and(users::username.ne(&username)),
-        )
-        .filter(not(users::lat.is_null()))
-        .filter(not(users::long.is_null()))
-        .limit(10);
-
-    match not_swiped_on.load::<DBUser>(&conn) {
-        Ok(users) => {
-            let as_string = serde_json::to_string(&users).expect("failed to jsonify DBUsers");
-            HttpResponse::Ok().body(as_string)
+    let names: Vec<String> = super_likers
+        .iter()
+        .chain(&candidates)
+        .map(|c| c.username.clone())
+        .collect();
+    let mut with_user = names.clone();
+    with_user.push(user.username.clone());
+    let signals = match (
+        swipe_repo.received(&with_user),
+        swipe_repo.liked_by_similar(&user.username, &names),
+    ) {
+        (Ok(received), Ok(liked_by_similar)) => Signals {
+            received,
+            liked_by_similar,
+        },
+        (Err(err), _) | (_, Err(err)) => {
+            return HttpResponse::InternalServerError().body(err.to_string())
         }
-        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
-    }
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    let super_likers = recommend::rank(user, super_likers, &signals, &weights, now);
+    let ranked = recommend::rank(user, candidates, &signals, &weights, now);
+    let items = super_likers
+        .into_iter()
+        .map(|ranked| (ranked, true))
+        .chain(ranked.into_iter().map(|ranked| (ranked, false)))
+        .take(AVAILABLE_PAGE_SIZE)
+        .map(|((candidate, score), super_liked)| Candidate {
+            profile: PublicProfile::new(&candidate, near),
+            super_liked,
+            score: if query.debug { Some(score) } else { None },
+        })
+        .collect();
+    json(&Page::all(items))
 }
 
+#[utoipa::path(
+    get,
+    path = "/swipe/likes-received",
+    tag = "swipes",
+    params(LikesReceivedQuery),
+    responses((status = 200, description = "Users who swiped right on the caller and whom the caller hasn't swiped on yet. Swiping right on one of them matches them straight away", body = Page<LikeReceived>)),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn likes_received(
+    request: HttpRequest,
+    query: web::Query<LikesReceivedQuery>,
+    swipe_repo: web::Data<Arc<dyn SwipeRepository>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let user: &DBUser = match ext.get::<DBUser>() {
+        Some(u) => u,
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let found = match swipe_repo.likes_received(&user.username, query.cursor.as_deref(), limit + 1)
+    {
+        Ok(found) => found,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let viewer = user.lat.zip(user.long);
+    let page =
+        Page::from_rows(found, limit, |(u, _)| u.username.clone()).map(|(u, kind)| LikeReceived {
+            profile: PublicProfile::new(&u, viewer),
+            super_liked: kind == SwipeKind::Super,
+        });
+    json(&page)
+}
+
+#[utoipa::path(
+    post,
+    path = "/swipe",
+    tag = "swipes",
+    request_body = SwipeDTO,
+    responses(
+        (status = 200, description = "Swipe recorded, matching the users if they both swiped right"),
+        (status = 400, description = "Neither `kind` nor `status` was set"),
+        (status = 409, description = "The caller has already swiped on this user"),
+        (status = 429, description = "The caller has used all of today's super-likes"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+#[allow(clippy::too_many_arguments)]
 pub async fn do_swipe(
     swipe: web::Json<SwipeDTO>,
     request: HttpRequest,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    swipe_repo: web::Data<Arc<dyn SwipeRepository>>,
+    match_repo: web::Data<Arc<dyn MatchRepository>>,
+    quota: web::Data<Arc<dyn SuperLikeQuota>>,
+    job_queue: web::Data<Arc<dyn JobQueue>>,
+    metrics: web::Data<Arc<Metrics>>,
 ) -> impl Responder {
     let ext = request.extensions();
     let swiper = match ext.get::<DBUser>() {
@@ -71,54 +212,82 @@ pub async fn do_swipe(
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => {
-            eprintln!("failed to get pg conn for do_swipe handler");
-            return HttpResponse::InternalServerError().body(err.to_string());
-        }
+    let kind = match (swipe.kind, swipe.status) {
+        (Some(kind), _) => kind,
+        (None, Some(true)) => SwipeKind::Like,
+        (None, Some(false)) => SwipeKind::Pass,
+        (None, None) => return HttpResponse::BadRequest().body("kind must be set"),
     };
+    let day = quota::today();
+    if kind == SwipeKind::Super {
+        match quota.take(swiper, day) {
+            Ok(true) => {}
+            Ok(false) => return HttpResponse::TooManyRequests().body("no super-likes left today"),
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        }
+    }
 
-    let swipe = DBSwipe {
-        swiper: swiper.clone(),
-        swiped: swipe.swiped.clone(),
-        status: swipe.status,
-    };
+    let recorded = swipe_repo.record(swiper, &swipe.swiped, kind);
+    if recorded.is_err() && kind == SwipeKind::Super {
+        if let Err(err) = quota.give_back(swiper, day) {
+            warn!(username = %swiper, error = %err, "error returning unused super-like");
+        }
+    }
+    match recorded {
+        Ok(()) => {
+            let status = if kind.is_right() { "right" } else { "left" };
+            metrics.swipes.with_label_values(&[status]).inc();
+            if kind == SwipeKind::Super {
+                metrics.super_likes.inc();
+            }
+            if kind.is_right() {
+                let data = serde_json::json!({
+                    "swiper": swiper,
+                    "swiped": swipe.swiped,
+                    "super_like": kind == SwipeKind::Super,
+                });
+                let usernames = [swiper.as_str(), swipe.swiped.as_str()];
+                webhooks::emit(&***job_queue, WebhookEvent::SwipeLike, &usernames, data);
+            }
+            if let Err(err) = user_repo.touch(swiper) {
+                warn!(username = %swiper, error = %err, "error recording activity");
+            }
 
-    match diesel::insert_into(swipes::table)
-        .values(&swipe)
-        .get_result::<DBSwipe>(&conn)
-    {
-        Ok(_) => {
-            if swipes::table
-                .filter(swipes::swiper.eq(&swipe.swiped))
-                .filter(swipes::swiped.eq(swiper.clone()))
-                .filter(swipes::status.eq(true))
-                .first::<DBSwipe>(&conn)
-                .is_ok()
-            {
-                let mut username1 = swiper.clone();
-                let mut username2 = swipe.swiped;
-                if username1 >= username2 {
-                    std::mem::swap(&mut username1, &mut username2);
-                }
-                let new_match = DBMatch {
-                    username1: username1.clone(),
-                    username2: username2.clone(),
-                };
-                if let Err(err) = diesel::insert_into(matches::table)
-                    .values(new_match)
-                    .get_result::<DBMatch>(&conn)
-                {
-                    eprintln!(
-                        "error creating new match for `{}` and `{}`: {:?}",
-                        username1, username2, err
-                    )
+            let matched = match swipe_repo.swiped_right(&swipe.swiped, swiper) {
+                Ok(matched) => kind.is_right() && matched,
+                Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+            };
+            if matched {
+                match match_repo.create(swiper, &swipe.swiped) {
+                    Ok(()) => {
+                        metrics.matches_created.inc();
+                        let usernames = webhooks::match_usernames(swiper, &swipe.swiped);
+                        let data = serde_json::json!({ "usernames": usernames });
+                        webhooks::emit(&***job_queue, WebhookEvent::MatchCreated, &usernames, data);
+                        for (username, matched_with) in
+                            [(swiper, &swipe.swiped), (&swipe.swiped, swiper)]
+                        {
+                            let job = Job::NotifyMatch {
+                                username: username.clone(),
+                                matched_with: matched_with.clone(),
+                            };
+                            if let Err(err) = job_queue.enqueue(&job) {
+                                warn!(username = %username, error = %err, "error queueing match email");
+                            }
+                        }
+                    }
+                    // both swiped right at the same moment, and the other
+                    // swipe made the match
+                    Err(RepoError::Conflict(_)) => {}
+                    Err(err) => {
+                        error!(username1 = %swiper, username2 = %swipe.swiped, error = %err, "error creating new match")
+                    }
                 }
             }
 
             HttpResponse::Ok().finish()
         }
+        Err(RepoError::Conflict(_)) => HttpResponse::Conflict().body("already swiped on this user"),
         Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
     }
 }
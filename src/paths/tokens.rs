@@ -0,0 +1,163 @@
+use std::{ops::DerefMut, sync::Arc, time::Duration};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use diesel::r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::bearer_token;
+use crate::metrics::Metrics;
+use crate::paths::users::{authenticate, UserDTO};
+use crate::repo::UserRepository;
+use crate::response::json;
+use crate::throttle::LoginThrottle;
+use crate::tokens::{self, TokenIssuer, ACCESS_TOKEN_TTL};
+
+#[derive(Serialize, ToSchema)]
+pub struct TokenPair {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    refresh_token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshDTO {
+    refresh_token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RevokeDTO {
+    refresh_token: Option<String>,
+}
+
+fn token_pair(
+    username: &str,
+    issuer: &TokenIssuer,
+    rd_conn: &mut r2d2_redis::redis::Connection,
+) -> HttpResponse {
+    let access_token = match issuer.issue(username) {
+        Ok(token) => token,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let refresh_token = match tokens::issue_refresh(rd_conn, username) {
+        Ok(token) => token,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let pair = TokenPair {
+        access_token,
+        token_type: "Bearer",
+        expires_in: ACCESS_TOKEN_TTL.as_secs(),
+        refresh_token,
+    };
+    json(&pair)
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/token",
+    tag = "tokens",
+    request_body = UserDTO,
+    responses(
+        (status = 200, description = "A new access and refresh token", body = TokenPair),
+        (status = 400, description = "Username or password incorrect"),
+        (status = 403, description = "Account banned or suspended"),
+        (status = 429, description = "Too many failed attempts, see the Retry-After header"),
+    )
+)]
+pub async fn issue_token(
+    request: HttpRequest,
+    user: web::Json<UserDTO>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    throttle: web::Data<Arc<dyn LoginThrottle>>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    issuer: web::Data<Arc<TokenIssuer>>,
+    metrics: web::Data<Arc<Metrics>>,
+) -> impl Responder {
+    let db_user = match authenticate(&request, &user, &user_repo, &throttle, &metrics) {
+        Ok(db_user) => db_user,
+        Err(resp) => return resp,
+    };
+
+    let mut rd_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
+        Ok(rd_conn) => rd_conn,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    token_pair(&db_user.username, &issuer, rd_conn.deref_mut())
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/token/refresh",
+    tag = "tokens",
+    request_body = RefreshDTO,
+    responses(
+        (status = 200, description = "A new access and refresh token", body = TokenPair),
+        (status = 401, description = "Refresh token invalid, expired or already used"),
+    )
+)]
+pub async fn refresh_token(
+    refresh: web::Json<RefreshDTO>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    issuer: web::Data<Arc<TokenIssuer>>,
+) -> impl Responder {
+    let mut rd_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
+        Ok(rd_conn) => rd_conn,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let username = match tokens::take_refresh(rd_conn.deref_mut(), &refresh.refresh_token) {
+        Ok(Some(username)) => username,
+        Ok(None) => return HttpResponse::Unauthorized().body("refresh token invalid or expired"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    token_pair(&username, &issuer, rd_conn.deref_mut())
+}
+
+/// Revokes the refresh token in the body and the access token in the
+/// `Authorization` header, whichever of them are present.
+#[utoipa::path(
+    post,
+    path = "/user/token/revoke",
+    tag = "tokens",
+    request_body = RevokeDTO,
+    responses(
+        (status = 200, description = "Tokens revoked"),
+        (status = 401, description = "The bearer token is invalid"),
+    ),
+    security((), ("bearer" = []))
+)]
+pub async fn revoke_token(
+    request: HttpRequest,
+    revoke: web::Json<RevokeDTO>,
+    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    issuer: web::Data<Arc<TokenIssuer>>,
+) -> impl Responder {
+    let mut rd_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
+        Ok(rd_conn) => rd_conn,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    if let Some(refresh_token) = &revoke.refresh_token {
+        if let Err(err) = tokens::take_refresh(rd_conn.deref_mut(), refresh_token) {
+            return HttpResponse::InternalServerError().body(err.to_string());
+        }
+    }
+
+    if let Some(token) = bearer_token(request.headers()) {
+        let claims = match issuer.verify(token) {
+            Ok(claims) => claims,
+            Err(err) => return HttpResponse::Unauthorized().body(err.to_string()),
+        };
+        if let Err(err) = tokens::revoke_access(rd_conn.deref_mut(), &claims) {
+            return HttpResponse::InternalServerError().body(err.to_string());
+        }
+    }
+
+    HttpResponse::Ok().finish()
+}
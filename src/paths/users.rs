@@ -1,128 +1,259 @@
-use std::{fs, io::Write, sync::Arc, time::Duration};
+use std::{fs, io::Write, sync::Arc};
 
 use actix_multipart::Multipart;
 use actix_session::Session;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use diesel::r2d2::{ConnectionManager, Pool};
-use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
 use futures_util::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db::{DBUser, DISCIPLINES};
+use crate::mail::Outbox;
+use crate::metrics::Metrics;
+use crate::paths::email;
+use crate::pic_cache::{Lookup, PicCache};
+use crate::repo::{RepoError, UserRepository, UserSearch};
+use crate::response::{json, Page};
+use crate::sessions::SessionStore;
+use crate::throttle::LoginThrottle;
+use crate::{geo, username};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// A valid bcrypt hash of a throwaway password, used to spend the same time
+/// on logins for unknown usernames as on real ones.
+const DUMMY_HASH: &str = "$2b$10$mi8LWW5EJa9T76EPG3XNjepLF3gStR3iRGnAvDshAtGxQxCcx1Ccm";
+/// Where uploaded profile pictures are stored.
+pub const PROFILE_PIC_DIR: &str = "./profile_pics";
+
+/// Where a user's uploaded picture is written. Usernames from before they
+/// were validated can hold `/` and `..`, so the file is named by a hash of
+/// the username rather than the username itself.
+pub(crate) fn profile_pic_path(username: &str) -> String {
+    let hash = hex::encode(Sha256::digest(username.as_bytes()));
+    format!("{}/{}", PROFILE_PIC_DIR, &hash[..16])
+}
 
-use crate::db::DBUser;
-use crate::schema::users;
-use r2d2_redis::RedisConnectionManager;
-use std::ops::DerefMut;
-
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct UserDTO {
     username: String,
     password: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SignupDTO {
+    username: String,
+    password: String,
+    email: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct LatLongDTO {
     lat: f64,
     long: f64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct BioDTO {
     bio: String,
 }
 
-pub async fn get_users(
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DisciplineDTO {
+    discipline: Option<String>,
+}
+
+/// What other users get to see of someone. Deliberately leaves out their
+/// exact location, giving only how far away they are from the viewer.
+#[derive(Serialize, ToSchema)]
+pub struct PublicProfile {
+    username: String,
+    bio: Option<String>,
+    discipline: Option<String>,
+    has_profile_pic: bool,
+    distance_km: Option<f64>,
+}
+
+impl PublicProfile {
+    pub(crate) fn new(user: &DBUser, viewer: Option<(f64, f64)>) -> PublicProfile {
+        let distance_km = match (viewer, user.lat, user.long) {
+            // whole km, and never under 1, so distances from a few places
+            // can't be combined to find where someone lives
+            (Some(from), Some(lat), Some(long)) => {
+                Some(geo::haversine_km(from, (lat, long)).ceil().max(1.0))
+            }
+            _ => None,
+        };
+
+        PublicProfile {
+            username: user.username.clone(),
+            bio: user.bio.clone(),
+            discipline: user.discipline.clone(),
+            has_profile_pic: user.profile_pic.is_some(),
+            distance_km,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    prefix: Option<String>,
+    discipline: Option<String>,
+    within_km: Option<f64>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Escapes the wildcards in user input that is about to go into a LIKE.
+pub(crate) fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[utoipa::path(
+    get,
+    path = "/user",
+    tag = "users",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "A page of matching users", body = Page<PublicProfile>),
+        (status = 400, description = "Invalid filters, or a distance filter without a location set"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn search_users(
+    request: HttpRequest,
+    query: web::Query<SearchQuery>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
 ) -> impl Responder {
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    let viewer = match request.extensions().get::<DBUser>() {
+        Some(u) => u.lat.zip(u.long),
+        None => return HttpResponse::BadRequest().body("user not set on request"),
+    };
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let within_km = match query.within_km {
+        Some(km) if !km.is_finite() || km <= 0.0 => {
+            return HttpResponse::BadRequest().body("within_km must be a positive number");
+        }
+        Some(km) => match viewer {
+            Some(from) => Some((from, km)),
+            None => {
+                return HttpResponse::BadRequest()
+                    .body("set your location before searching by distance")
+            }
+        },
+        None => None,
     };
 
-    let db_users = match users::table.load::<DBUser>(&conn) {
-        Ok(db_users) => db_users,
+    let search = UserSearch {
+        prefix: query.prefix.as_deref(),
+        discipline: query.discipline.as_deref(),
+        within_km,
+        after: query.cursor.as_deref(),
+        limit: limit + 1,
+    };
+    let found = match user_repo.search(&search) {
+        Ok(found) => found,
         Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
 
-    let as_string = serde_json::to_string(&db_users).expect("unable to jsonify user records");
-    HttpResponse::Ok().body(as_string)
+    let page = Page::from_rows(found, limit, |u| u.username.clone())
+        .map(|u| PublicProfile::new(&u, viewer));
+    json(&page)
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/u/{username}",
+    tag = "users",
+    params(("username" = String, Path, description = "Whose picture to fetch, ignoring case")),
+    responses(
+        (status = 200, description = "The user's profile picture", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "No such user, or they have no picture"),
+    )
+)]
 pub async fn get_user_pic(
     username: web::Path<String>,
-    pg_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
-    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    pic_cache: web::Data<Arc<PicCache>>,
+    metrics: web::Data<Arc<Metrics>>,
 ) -> impl Responder {
-    let conn = match pg_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
+    let dbu: DBUser = match user_repo.find_ignoring_case(&username) {
+        Ok(Some(dbu)) => dbu,
+        Ok(None) => return HttpResponse::NotFound().body("no such user"),
         Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
 
-    let dbu: DBUser = match users::table.find(username.into_inner()).first::<DBUser>(&conn) {
-        Ok(dbu) => dbu,
-        Err(err) => return HttpResponse::NotFound().body(err.to_string()),
-    };
-
-    let mut rd_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(rd_conn) => rd_conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    let lookup = pic_cache.get(&dbu.username);
+    let label = match lookup {
+        Lookup::Hit(_) => "hit",
+        Lookup::Miss => "miss",
+        Lookup::Unavailable => "unavailable",
     };
-
-    let contents = match r2d2_redis::redis::cmd("GET")
-        .arg(dbu.username.as_str())
-        .query::<Option<Vec<u8>>>(rd_conn.deref_mut())
-    {
-        Ok(opt) => match opt {
-            Some(val) => val,
-            None => {
-                let filename = match &dbu.profile_pic {
-                    Some(s) => s.as_str(),
-                    None => return HttpResponse::NotFound().finish(),
-                };
-
-                let contents = match fs::read(filename) {
-                    Ok(contents) => contents,
-                    Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-                };
-
-                if let Err(err) = r2d2_redis::redis::Cmd::new()
-                    .arg("SET")
-                    .arg(dbu.username.as_str())
-                    .arg(contents.clone())
-                    .query::<()>(rd_conn.deref_mut())
-                {
-                    eprintln!("error storing user pic to redis: {}", err.to_string());
-                }
-
-                contents
+    metrics.pic_cache.with_label_values(&[label]).inc();
+
+    let contents = match lookup {
+        Lookup::Hit(contents) => contents,
+        Lookup::Miss | Lookup::Unavailable => {
+            let filename = match &dbu.profile_pic {
+                Some(s) => s.as_str(),
+                None => return HttpResponse::NotFound().finish(),
+            };
+
+            let contents = match fs::read(filename) {
+                Ok(contents) => contents,
+                Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+            };
+
+            if let Lookup::Miss = lookup {
+                pic_cache.put(&dbu.username, &contents);
             }
-        },
-        Err(err) => {
-            eprintln!("redis error: {}", err.to_string());
-            return HttpResponse::InternalServerError().body(err.to_string());
+            contents
         }
     };
 
     HttpResponse::Ok().body(contents)
 }
 
+#[utoipa::path(
+    post,
+    path = "/user/manage/profile_pic",
+    tag = "users",
+    request_body(content = Vec<u8>, content_type = "multipart/form-data", description = "A single file field holding the picture"),
+    responses(
+        (status = 200, description = "Picture replaced"),
+        (status = 400, description = "No file was uploaded"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn upload_profile_pic(
     request: HttpRequest,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
-    redis_pool: web::Data<Arc<Pool<RedisConnectionManager>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    pic_cache: web::Data<Arc<PicCache>>,
     mut payload: Multipart,
 ) -> impl Responder {
-    let ext = request.extensions();
-    let username: &str = match ext.get::<DBUser>() {
-        Some(user) => user.username.as_str(),
+    let username = match request.extensions().get::<DBUser>() {
+        Some(user) => user.username.clone(),
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
+    let username = username.as_str();
 
     let mut field = match payload.try_next().await {
         Ok(Some(field)) => field,
         _ => return HttpResponse::BadRequest().body("missing file upload"),
     };
 
-    let filename = format!("./profile_pics/{}", username);
+    let filename = profile_pic_path(username);
     let filename_to_make = filename.clone();
 
     let mut f = web::block(|| fs::File::create(filename_to_make))
@@ -136,115 +267,240 @@ pub async fn upload_profile_pic(
             .expect("pls");
     }
 
-    let pg_conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-    };
-
-    let mut redis_conn = match redis_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-    };
-
-    if let Err(err) = diesel::update(users::table.filter(users::username.eq(username)))
-        .set(users::profile_pic.eq(&filename))
-        .execute(&pg_conn)
-    {
+    if let Err(err) = user_repo.set_profile_pic(username, &filename) {
         return HttpResponse::InternalServerError().body(err.to_string());
     }
 
-    if let Err(err) = r2d2_redis::redis::Cmd::new()
-        .arg("DEL")
-        .arg(username)
-        .query::<()>(redis_conn.deref_mut())
-    {
-        eprintln!(
-            "failed to update redis cache for user {}: {}",
-            username,
-            err.to_string()
-        );
-    }
+    pic_cache.evict(username);
 
     HttpResponse::Ok().finish()
 }
 
+#[utoipa::path(
+    post,
+    path = "/user",
+    tag = "users",
+    request_body = SignupDTO,
+    responses(
+        (status = 200, description = "Account created, logged in and verification email sent", body = DBUser),
+        (status = 400, description = "Invalid username or email"),
+        (status = 409, description = "Username or email already registered"),
+    )
+)]
 pub async fn create_user(
+    request: HttpRequest,
     session: Session,
-    user: web::Json<UserDTO>,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    user: web::Json<SignupDTO>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+    outbox: web::Data<Arc<Outbox>>,
+    metrics: web::Data<Arc<Metrics>>,
 ) -> impl Responder {
     let mut user = user.into_inner();
-    user.password = bcrypt::hash(&user.password, 10).expect("unable to encrypt user password");
-
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    user.username = match username::normalize(&user.username) {
+        Ok(username) => username,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
     };
+    user.email = user.email.trim().to_string();
+    if !email::valid_email(&user.email) {
+        return HttpResponse::BadRequest().body("invalid email address");
+    }
+    user.password = bcrypt::hash(&user.password, 10).expect("unable to encrypt user password");
 
-    let user = DBUser {
-        username: user.username,
-        password: user.password,
-        lat: None,
-        long: None,
-        bio: None,
-        profile_pic: None,
-    };
-    let user_record = match diesel::insert_into(users::table)
-        .values(&user)
-        .get_result::<DBUser>(&conn)
-    {
+    let user = DBUser::new(user.username, user.password, Some(user.email));
+    let user_record = match user_repo.create(user) {
         Ok(user_record) => user_record,
+        Err(RepoError::Conflict("email")) => {
+            return HttpResponse::Conflict().body("email already registered")
+        }
+        Err(RepoError::Conflict(_)) => {
+            return HttpResponse::Conflict().body("username already taken")
+        }
         Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
+    metrics.signups.inc();
+
+    // the account works without a verified email, it just isn't shown to
+    // anyone else yet, so a failure here shouldn't fail the signup
+    if let Some(email) = &user_record.email {
+        let sent =
+            email::send_verification(&***user_repo, &outbox, &user_record.username, email).await;
+        if let Err(err) = sent {
+            error!(username = %user_record.username, error = %err, "error sending verification email");
+        }
+    }
 
-    if let Err(err) = session.set("username", &user_record.username) {
-        eprintln!("error setting username in session: {:?}", err);
+    if let Err(resp) = start_session(&request, &session, &user_record.username, &session_store) {
+        return resp;
     }
 
-    let as_string = serde_json::to_string(&user_record).expect("failed to jsonify DBUser");
-    HttpResponse::Ok().body(as_string)
+    json(&user_record)
 }
 
+#[utoipa::path(
+    post,
+    path = "/user/login",
+    tag = "users",
+    request_body = UserDTO,
+    responses(
+        (status = 200, description = "Logged in, session cookie set"),
+        (status = 400, description = "Username or password incorrect"),
+        (status = 403, description = "Account banned or suspended"),
+        (status = 429, description = "Too many failed attempts, see the Retry-After header"),
+    )
+)]
 pub async fn login(
+    request: HttpRequest,
     session: Session,
     user: web::Json<UserDTO>,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+    throttle: web::Data<Arc<dyn LoginThrottle>>,
+    metrics: web::Data<Arc<Metrics>>,
 ) -> impl Responder {
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    let db_user = match authenticate(&request, &user, &user_repo, &throttle, &metrics) {
+        Ok(db_user) => db_user,
+        Err(resp) => return resp,
     };
 
-    let user = user.into_inner();
+    if let Err(resp) = start_session(&request, &session, &db_user.username, &session_store) {
+        return resp;
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Creates a server side session for a user and points the cookie at it.
+fn start_session(
+    request: &HttpRequest,
+    session: &Session,
+    username: &str,
+    session_store: &Arc<dyn SessionStore>,
+) -> Result<(), HttpResponse> {
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(String::from);
+    let ip = request.peer_addr().map(|addr| addr.ip().to_string());
+
+    let secret = match session_store.create(username, user_agent, ip) {
+        Ok(secret) => secret,
+        Err(err) => return Err(HttpResponse::InternalServerError().body(err.to_string())),
+    };
 
-    let db_user = match users::table.find(&user.username).first::<DBUser>(&conn) {
+    session.renew();
+    if let Err(err) = session.set("session_id", secret) {
+        error!(error = %err, "error setting session id in session");
+    }
+    Ok(())
+}
+
+/// Checks a username and password, applying the same throttling and giving
+/// the same responses for every way of logging in.
+pub(crate) fn authenticate(
+    request: &HttpRequest,
+    user: &UserDTO,
+    user_repo: &Arc<dyn UserRepository>,
+    throttle: &Arc<dyn LoginThrottle>,
+    metrics: &Metrics,
+) -> Result<DBUser, HttpResponse> {
+    let ip = match request.peer_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => return Err(HttpResponse::BadRequest().body("unable to determine client address")),
+    };
+
+    // usernames are unique ignoring case, so attempts on every casing of a
+    // name count towards the same limit
+    let lowered = user.username.to_lowercase();
+
+    match throttle.check(&lowered, &ip) {
+        Ok(None) => {}
+        Ok(Some(wait)) => {
+            metrics.logins.with_label_values(&["throttled"]).inc();
+            return Err(HttpResponse::TooManyRequests()
+                .header(header::RETRY_AFTER, wait.as_secs().max(1).to_string())
+                .body("too many failed login attempts, try again later"));
+        }
+        Err(err) => return Err(HttpResponse::InternalServerError().body(err.to_string())),
+    }
+
+    // unknown usernames are checked against a dummy hash so that they take as
+    // long to reject as a wrong password and can't be told apart from one
+    let db_user = match user_repo.find_ignoring_case(&lowered) {
         Ok(db_user) => db_user,
-        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+        Err(err) => return Err(HttpResponse::InternalServerError().body(err.to_string())),
+    };
+    let hash = match &db_user {
+        Some(db_user) => db_user.password.as_str(),
+        None => DUMMY_HASH,
     };
 
-    let found = match bcrypt::verify(&user.password, &db_user.password) {
+    let found = match bcrypt::verify(&user.password, hash) {
         Ok(found) => found,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        Err(err) => return Err(HttpResponse::InternalServerError().body(err.to_string())),
     };
 
-    if found {
-        if let Err(err) = session.set("username", db_user.username) {
-            eprintln!("err setting username in session: {:?}", err);
+    match db_user {
+        Some(db_user) if found => {
+            if let Some(restriction) = db_user.restriction() {
+                metrics.logins.with_label_values(&["restricted"]).inc();
+                return Err(HttpResponse::Forbidden().body(restriction));
+            }
+            metrics.logins.with_label_values(&["success"]).inc();
+            if let Err(err) = throttle.record_success(&lowered) {
+                warn!(username = %user.username, error = %err, "error clearing failed logins");
+            }
+            if let Err(err) = user_repo.touch(&db_user.username) {
+                warn!(username = %db_user.username, error = %err, "error recording activity");
+            }
+            Ok(db_user)
+        }
+        _ => {
+            metrics.logins.with_label_values(&["failure"]).inc();
+            if let Err(err) = throttle.record_failure(&lowered, &ip) {
+                warn!(username = %user.username, error = %err, "error recording failed login");
+            }
+            Err(HttpResponse::BadRequest().body("username or password incorrect"))
         }
-        HttpResponse::Ok().finish()
-    } else {
-        HttpResponse::BadRequest().body("password incorrect")
     }
 }
 
-pub async fn logout(session: Session) -> impl Responder {
-    session.remove("username");
+#[utoipa::path(
+    get,
+    path = "/user/logout",
+    tag = "users",
+    responses((status = 200, description = "Session ended"))
+)]
+pub async fn logout(
+    session: Session,
+    session_store: web::Data<Arc<dyn SessionStore>>,
+) -> impl Responder {
+    let secret = match session.get::<String>("session_id") {
+        Ok(Some(secret)) => secret,
+        _ => {
+            session.purge();
+            return HttpResponse::Ok().finish();
+        }
+    };
+
+    if let Err(err) = session_store.revoke_secret(&secret) {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
+
+    session.purge();
     HttpResponse::Ok().finish()
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/manage/li",
+    tag = "users",
+    responses((status = 200, description = "The logged in user", body = DBUser)),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn check_login(
     request: HttpRequest,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
 ) -> impl Responder {
     let ext = request.extensions();
     let username: &str = match ext.get::<DBUser>() {
@@ -252,27 +508,30 @@ pub async fn check_login(
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-    };
-
-    let user = match users::table
-        .filter(users::username.eq(&username))
-        .first::<DBUser>(&conn)
-    {
-        Ok(user) => user,
+    let user = match user_repo.find(username) {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().body("no such user"),
         Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
     };
 
-    let as_string = serde_json::to_string(&user).expect("failed to jsonify DBUser");
-    HttpResponse::Ok().body(as_string)
+    json(&user)
 }
 
+#[utoipa::path(
+    post,
+    path = "/user/manage/location",
+    tag = "users",
+    request_body = LatLongDTO,
+    responses(
+        (status = 200, description = "Location updated"),
+        (status = 400, description = "Not a point on the map")
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn set_location(
     request: HttpRequest,
     latlong: web::Json<LatLongDTO>,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
 ) -> impl Responder {
     let ext = request.extensions();
     let username: &str = match ext.get::<DBUser>() {
@@ -280,25 +539,29 @@ pub async fn set_location(
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
-    };
-
     let ll = latlong.into_inner();
-    match diesel::update(users::table.filter(users::username.eq(username)))
-        .set((users::lat.eq(ll.lat), users::long.eq(ll.long)))
-        .execute(&conn)
-    {
+    if !(-90.0..=90.0).contains(&ll.lat) || !(-180.0..=180.0).contains(&ll.long) {
+        return HttpResponse::BadRequest()
+            .body("lat must be between -90 and 90 and long between -180 and 180");
+    }
+    match user_repo.set_location(username, ll.lat, ll.long) {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/user/manage/bio",
+    tag = "users",
+    request_body = BioDTO,
+    responses((status = 200, description = "Bio updated")),
+    security(("session" = []), ("bearer" = []))
+)]
 pub async fn set_bio(
     request: HttpRequest,
     bio: web::Json<BioDTO>,
-    conn_pool: web::Data<Arc<Pool<ConnectionManager<PgConnection>>>>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
 ) -> impl Responder {
     let ext = request.extensions();
     let username: &str = match ext.get::<DBUser>() {
@@ -306,15 +569,45 @@ pub async fn set_bio(
         None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    let conn = match conn_pool.get_timeout(Duration::from_millis(500)) {
-        Ok(conn) => conn,
-        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    match user_repo.set_bio(username, bio.into_inner().bio) {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/manage/discipline",
+    tag = "users",
+    request_body = DisciplineDTO,
+    responses(
+        (status = 200, description = "Discipline updated"),
+        (status = 400, description = "Unknown discipline"),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
+pub async fn set_discipline(
+    request: HttpRequest,
+    discipline: web::Json<DisciplineDTO>,
+    user_repo: web::Data<Arc<dyn UserRepository>>,
+) -> impl Responder {
+    let ext = request.extensions();
+    let username: &str = match ext.get::<DBUser>() {
+        Some(user) => user.username.as_str(),
+        None => return HttpResponse::BadRequest().body("user not set on request"),
     };
 
-    match diesel::update(users::table.filter(users::username.eq(username)))
-        .set(users::bio.eq(bio.into_inner().bio))
-        .execute(&conn)
-    {
+    let discipline = discipline.into_inner().discipline;
+    if let Some(d) = &discipline {
+        if !DISCIPLINES.contains(&d.as_str()) {
+            return HttpResponse::BadRequest().body(format!(
+                "discipline must be one of {}",
+                DISCIPLINES.join(", ")
+            ));
+        }
+    }
+
+    match user_repo.set_discipline(username, discipline) {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
     }
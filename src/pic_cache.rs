@@ -0,0 +1,92 @@
+use std::{ops::DerefMut, sync::Arc, time::Duration};
+
+use diesel::r2d2::Pool;
+use r2d2_redis::redis;
+use r2d2_redis::RedisConnectionManager;
+
+use crate::breaker::CircuitBreaker;
+
+/// How long a picture stays cached, so that one whose eviction failed while
+/// redis was down doesn't stay stale forever.
+const PIC_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub enum Lookup {
+    Hit(Vec<u8>),
+    Miss,
+    /// Redis is down or the breaker is open, so the cache wasn't checked.
+    Unavailable,
+}
+
+/// Caches profile pictures in redis, keyed by username. The pictures on disk
+/// are the source of truth, so every operation fails open: if redis is down
+/// callers carry on without the cache, and the breaker stops each request
+/// waiting on a connection that won't come.
+pub struct PicCache {
+    pool: Arc<Pool<RedisConnectionManager>>,
+    breaker: CircuitBreaker,
+}
+
+impl PicCache {
+    pub fn new(pool: Arc<Pool<RedisConnectionManager>>) -> Self {
+        PicCache {
+            pool,
+            breaker: CircuitBreaker::new("redis", 5, Duration::from_secs(30)),
+        }
+    }
+
+    fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>,
+    ) -> Option<T> {
+        self.breaker.call(|| {
+            let mut conn = self
+                .pool
+                .get_timeout(Duration::from_millis(500))
+                .map_err(|err| err.to_string())?;
+            f(conn.deref_mut()).map_err(|err| err.to_string())
+        })
+    }
+
+    pub fn get(&self, username: &str) -> Lookup {
+        match self.with_conn(|conn| {
+            redis::cmd("GET")
+                .arg(username)
+                .query::<Option<Vec<u8>>>(conn)
+        }) {
+            Some(Some(pic)) => Lookup::Hit(pic),
+            Some(None) => Lookup::Miss,
+            None => Lookup::Unavailable,
+        }
+    }
+
+    pub fn put(&self, username: &str, pic: &[u8]) {
+        self.with_conn(|conn| {
+            redis::cmd("SET")
+                .arg(username)
+                .arg(pic)
+                .arg("EX")
+                .arg(PIC_TTL.as_secs())
+                .query::<()>(conn)
+        });
+    }
+
+    pub fn evict(&self, username: &str) {
+        self.with_conn(|conn| redis::cmd("DEL").arg(username).query::<()>(conn));
+    }
+
+    /// Evicts every given user's picture, returning how many were cached, or
+    /// `None` if redis couldn't be reached.
+    pub fn evict_all(&self, usernames: &[String]) -> Option<usize> {
+        self.with_conn(|conn| {
+            let mut evicted = 0;
+            for chunk in usernames.chunks(500) {
+                evicted += redis::cmd("DEL").arg(chunk).query::<usize>(conn)?;
+            }
+            Ok(evicted)
+        })
+    }
+
+    pub fn breaker_state(&self) -> &'static str {
+        self.breaker.state()
+    }
+}
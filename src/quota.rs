@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use diesel::r2d2::Pool;
+use r2d2_redis::redis::{self, Connection, RedisResult};
+use r2d2_redis::RedisConnectionManager;
+
+use crate::repo::{RepoError, RepoResult};
+
+/// How many super-likes each user can send per day. Days start at midnight
+/// UTC.
+pub const SUPER_LIKES_PER_DAY: u32 = 3;
+
+/// Long enough for a day's counter to outlive the day wherever it started.
+const COUNTER_TTL: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// The day super-likes sent now count against.
+pub fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+/// Caps how many super-likes each user sends per day.
+pub trait SuperLikeQuota: Send + Sync {
+    /// Uses up one of `username`'s super-likes for `day`, returning false
+    /// without using one if they have none left.
+    fn take(&self, username: &str, day: NaiveDate) -> RepoResult<bool>;
+
+    /// Hands back a super-like taken for a swipe that then wasn't recorded.
+    /// `day` must be the one it was taken for, even if that has since passed.
+    fn give_back(&self, username: &str, day: NaiveDate) -> RepoResult<()>;
+}
+
+/// Super-likes counted in redis, so that every instance sees them.
+pub struct RedisQuota {
+    pool: Arc<Pool<RedisConnectionManager>>,
+}
+
+impl RedisQuota {
+    pub fn new(pool: Arc<Pool<RedisConnectionManager>>) -> Self {
+        RedisQuota { pool }
+    }
+
+    fn key(username: &str, day: NaiveDate) -> String {
+        format!("superlikes:{}:{}", username, day)
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&mut Connection) -> RedisResult<T>) -> RepoResult<T> {
+        let mut conn = self
+            .pool
+            .get_timeout(Duration::from_millis(500))
+            .map_err(RepoError::backend)?;
+        f(conn.deref_mut()).map_err(RepoError::backend)
+    }
+}
+
+impl SuperLikeQuota for RedisQuota {
+    fn take(&self, username: &str, day: NaiveDate) -> RepoResult<bool> {
+        let key = Self::key(username, day);
+        let (used,): (u32,) = self.with_conn(|conn| {
+            redis::pipe()
+                .atomic()
+                .cmd("INCR")
+                .arg(&key)
+                .cmd("EXPIRE")
+                .arg(&key)
+                .arg(COUNTER_TTL.as_secs())
+                .ignore()
+                .query(conn)
+        })?;
+        if used > SUPER_LIKES_PER_DAY {
+            self.give_back(username, day)?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn give_back(&self, username: &str, day: NaiveDate) -> RepoResult<()> {
+        // only decremented while the counter is still there, so a late give
+        // back can't leave a negative counter without an expiry
+        let script = redis::Script::new(
+            r"if redis.call('EXISTS', KEYS[1]) == 1 then redis.call('DECR', KEYS[1]) end",
+        );
+        self.with_conn(|conn| script.key(Self::key(username, day)).invoke(conn))
+    }
+}
+
+/// Super-likes counted in memory, for tests.
+#[derive(Default)]
+pub struct MemoryQuota {
+    used: Mutex<HashMap<(String, NaiveDate), u32>>,
+}
+
+impl MemoryQuota {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SuperLikeQuota for MemoryQuota {
+    fn take(&self, username: &str, day: NaiveDate) -> RepoResult<bool> {
+        let mut used = self.used.lock().expect("quota lock poisoned");
+        let used = used.entry((username.to_string(), day)).or_default();
+        if *used >= SUPER_LIKES_PER_DAY {
+            return Ok(false);
+        }
+        *used += 1;
+        Ok(true)
+    }
+
+    fn give_back(&self, username: &str, day: NaiveDate) -> RepoResult<()> {
+        let mut used = self.used.lock().expect("quota lock poisoned");
+        if let Some(used) = used.get_mut(&(username.to_string(), day)) {
+            *used = used.saturating_sub(1);
+        }
+        Ok(())
+    }
+}
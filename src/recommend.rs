@@ -0,0 +1,198 @@
+//! Orders the users someone could swipe on, best first, by a weighted blend
+//! of signals that each score a candidate between 0 and 1.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::DBUser;
+use crate::geo;
+use crate::repo::Received;
+
+/// Candidates this far away score 0.5 for distance, and those twice as far
+/// score a third.
+const DISTANCE_SCALE_KM: f64 = 10.0;
+
+/// A candidate's activity score halves for every this many days since they
+/// were last active.
+const ACTIVITY_HALF_LIFE_DAYS: f64 = 3.0;
+
+/// How much each signal counts towards a candidate's total. Only the ratio
+/// between them matters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights {
+    pub distance: f64,
+    pub preference: f64,
+    pub rating: f64,
+    pub activity: f64,
+    pub collaborative: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            distance: 3.0,
+            preference: 1.0,
+            rating: 1.0,
+            activity: 1.0,
+            collaborative: 2.0,
+        }
+    }
+}
+
+/// Parses weights like `distance=3,collaborative=0`. Signals left out keep
+/// their default weight.
+impl FromStr for Weights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Weights::default();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (signal, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected signal=weight, found `{}`", part))?;
+            let weight: f64 = match weight.trim().parse() {
+                Ok(weight) if weight >= 0.0 && f64::is_finite(weight) => weight,
+                _ => return Err(format!("weight for {} must be a number >= 0", signal)),
+            };
+            match signal.trim() {
+                "distance" => weights.distance = weight,
+                "preference" => weights.preference = weight,
+                "rating" => weights.rating = weight,
+                "activity" => weights.activity = weight,
+                "collaborative" => weights.collaborative = weight,
+                signal => return Err(format!("unknown signal `{}`", signal)),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+impl Weights {
+    /// The weights set by `RECOMMENDATION_WEIGHTS`, or the defaults.
+    pub fn from_env() -> Result<Self, String> {
+        match dotenv::var("RECOMMENDATION_WEIGHTS") {
+            Ok(weights) => weights.parse(),
+            Err(_) => Ok(Weights::default()),
+        }
+    }
+}
+
+/// Why a candidate ranked where they did: each signal's score and the
+/// weighted total they were ordered by.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct Score {
+    /// Higher the closer the candidate is, 0 if either location is unknown.
+    distance: f64,
+    /// 1 if both have the same discipline, 0 if they differ, 0.5 if either
+    /// hasn't set one.
+    preference: f64,
+    /// How close the share of right swipes the candidate gets is to the
+    /// caller's.
+    rating: f64,
+    /// Higher the more recently the candidate was active.
+    activity: f64,
+    /// How much users with similar taste to the caller like the candidate,
+    /// relative to the best liked candidate.
+    collaborative: f64,
+    total: f64,
+}
+
+/// What `rank` needs beyond the users themselves, from the swipes table.
+pub struct Signals {
+    /// Swipes received by the caller and each candidate.
+    pub received: HashMap<String, Received>,
+    /// From `SwipeRepository::liked_by_similar`.
+    pub liked_by_similar: HashMap<String, i64>,
+}
+
+/// The share of right swipes someone gets, pulled towards a half while they
+/// have few swipes so a single swipe doesn't make them a 0 or a 1.
+fn rating(received: Option<&Received>) -> f64 {
+    let received = received.copied().unwrap_or_default();
+    (received.right as f64 + 1.0) / (received.total as f64 + 2.0)
+}
+
+/// Scores `candidates` for `user` and sorts them best first.
+pub fn rank(
+    user: &DBUser,
+    candidates: Vec<DBUser>,
+    signals: &Signals,
+    weights: &Weights,
+    now: NaiveDateTime,
+) -> Vec<(DBUser, Score)> {
+    let user_rating = rating(signals.received.get(&user.username));
+    let most_liked = signals
+        .liked_by_similar
+        .values()
+        .copied()
+        .max()
+        .unwrap_or(0);
+    let weight_sum = weights.distance
+        + weights.preference
+        + weights.rating
+        + weights.activity
+        + weights.collaborative;
+
+    let mut ranked: Vec<(DBUser, Score)> = candidates
+        .into_iter()
+        .map(|candidate| {
+            let distance = match (user.lat, user.long, candidate.lat, candidate.long) {
+                (Some(lat), Some(long), Some(c_lat), Some(c_long)) => {
+                    let km = geo::haversine_km((lat, long), (c_lat, c_long));
+                    1.0 / (1.0 + km / DISTANCE_SCALE_KM)
+                }
+                _ => 0.0,
+            };
+            let preference = match (&user.discipline, &candidate.discipline) {
+                (Some(mine), Some(theirs)) if mine == theirs => 1.0,
+                (Some(_), Some(_)) => 0.0,
+                _ => 0.5,
+            };
+            let rating =
+                1.0 - (user_rating - rating(signals.received.get(&candidate.username))).abs();
+            let activity = match candidate.last_active_at {
+                Some(at) => {
+                    let days = (now - at).num_seconds().max(0) as f64 / 86_400.0;
+                    0.5f64.powf(days / ACTIVITY_HALF_LIFE_DAYS)
+                }
+                None => 0.0,
+            };
+            let collaborative = match signals.liked_by_similar.get(&candidate.username) {
+                Some(liked) if most_liked > 0 => *liked as f64 / most_liked as f64,
+                _ => 0.0,
+            };
+
+            let total = if weight_sum > 0.0 {
+                (weights.distance * distance
+                    + weights.preference * preference
+                    + weights.rating * rating
+                    + weights.activity * activity
+                    + weights.collaborative * collaborative)
+                    / weight_sum
+            } else {
+                0.0
+            };
+            let score = Score {
+                distance,
+                preference,
+                rating,
+                activity,
+                collaborative,
+                total,
+            };
+            (candidate, score)
+        })
+        .collect();
+
+    ranked.sort_by(|(a, a_score), (b, b_score)| {
+        b_score
+            .total
+            .total_cmp(&a_score.total)
+            .then_with(|| a.username.cmp(&b.username))
+    });
+    ranked
+}
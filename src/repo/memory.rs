@@ -0,0 +1,809 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{
+    ordered_pair, AdminRepository, Audit, MatchRepository, Received, RepoError, RepoResult,
+    SwipeRepository, UserRepository, UserSearch, WebhookRepository, SIMILAR_USERS,
+};
+use chrono::NaiveDateTime;
+use serde_json::Value;
+
+use crate::db::{
+    DBAdminAction, DBEmailVerification, DBMatch, DBMatchHistory, DBPasswordReset, DBUser,
+    DBWebhook, DBWebhookDelivery, DBWebhookMember, SwipeKind,
+};
+use crate::geo;
+use crate::webhooks::{WebhookEvent, DELIVERED, PENDING};
+
+#[derive(Default)]
+struct State {
+    users: BTreeMap<String, DBUser>,
+    /// (swiper, swiped) to how they swiped.
+    swipes: BTreeMap<(String, String), SwipeKind>,
+    matches: BTreeMap<(String, String), DBMatch>,
+    match_history: Vec<DBMatchHistory>,
+    webhooks: BTreeMap<i32, DBWebhook>,
+    webhook_deliveries: BTreeMap<i64, DBWebhookDelivery>,
+    /// (webhook id, username) to when they were added.
+    webhook_members: BTreeMap<(i32, String), NaiveDateTime>,
+    /// By the hash of their token, as are password resets.
+    email_verifications: BTreeMap<String, DBEmailVerification>,
+    password_resets: BTreeMap<String, DBPasswordReset>,
+    admin_actions: Vec<DBAdminAction>,
+}
+
+impl State {
+    fn record_action(&mut self, target: &str, audit: &Audit) {
+        let id = self.admin_actions.len() as i32 + 1;
+        self.admin_actions.push(DBAdminAction {
+            id,
+            admin: audit.admin.to_string(),
+            action: audit.action.to_string(),
+            target: target.to_string(),
+            details: audit.details.clone(),
+            created_at: chrono::Utc::now().naive_utc(),
+        });
+    }
+}
+
+/// The repositories kept in memory, following the same rules as the Postgres
+/// schema, for tests and local experiments. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryRepository {
+    state: Mutex<State>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("memory repository lock poisoned")
+    }
+}
+
+/// Changes a user and audits it, returning whether there was such a user.
+fn audit_user(
+    repo: &MemoryRepository,
+    username: &str,
+    audit: &Audit,
+    f: impl FnOnce(&mut DBUser),
+) -> bool {
+    let mut state = repo.state();
+    match state.users.get_mut(username) {
+        Some(user) => f(user),
+        None => return false,
+    }
+    state.record_action(username, audit);
+    true
+}
+
+fn update_user(
+    repo: &MemoryRepository,
+    username: &str,
+    f: impl FnOnce(&mut DBUser),
+) -> RepoResult<()> {
+    match repo.state().users.get_mut(username) {
+        Some(user) => {
+            f(user);
+            Ok(())
+        }
+        None => Err(RepoError::Backend(format!("no user named {}", username))),
+    }
+}
+
+impl UserRepository for MemoryRepository {
+    fn find(&self, username: &str) -> RepoResult<Option<DBUser>> {
+        Ok(self.state().users.get(username).cloned())
+    }
+
+    fn find_ignoring_case(&self, username: &str) -> RepoResult<Option<DBUser>> {
+        let lowered = username.to_lowercase();
+        Ok(self
+            .state()
+            .users
+            .values()
+            .find(|user| user.username.to_lowercase() == lowered)
+            .cloned())
+    }
+
+    fn create(&self, user: DBUser) -> RepoResult<DBUser> {
+        let mut state = self.state();
+        let username = user.username.to_lowercase();
+        let email = user.email.as_ref().map(|email| email.to_lowercase());
+        for existing in state.users.values() {
+            if existing.username.to_lowercase() == username {
+                return Err(RepoError::Conflict("username"));
+            }
+            if email.is_some() && existing.email.as_ref().map(|e| e.to_lowercase()) == email {
+                return Err(RepoError::Conflict("email"));
+            }
+        }
+        state.users.insert(user.username.clone(), user.clone());
+        Ok(user)
+    }
+
+    fn search(&self, search: &UserSearch) -> RepoResult<Vec<DBUser>> {
+        let state = self.state();
+        let found = state
+            .users
+            .values()
+            .filter(|user| user.restriction().is_none())
+            .filter(|user| match search.prefix {
+                Some(prefix) => user.username.starts_with(prefix),
+                None => true,
+            })
+            .filter(|user| match search.discipline {
+                Some(discipline) => user.discipline.as_deref() == Some(discipline),
+                None => true,
+            })
+            .filter(|user| match search.after {
+                Some(after) => user.username.as_str() > after,
+                None => true,
+            })
+            .filter(|user| match (search.within_km, user.lat, user.long) {
+                (Some((from, km)), Some(lat), Some(long)) => {
+                    geo::haversine_km(from, (lat, long)) <= km
+                }
+                (Some(_), _, _) => false,
+                (None, _, _) => true,
+            })
+            .take(search.limit.max(0) as usize)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+
+    fn set_location(&self, username: &str, lat: f64, long: f64) -> RepoResult<()> {
+        update_user(self, username, |user| {
+            user.lat = Some(lat);
+            user.long = Some(long);
+            user.geohash = Some(geo::geohash((lat, long)));
+        })
+    }
+
+    fn set_bio(&self, username: &str, bio: String) -> RepoResult<()> {
+        update_user(self, username, |user| user.bio = Some(bio))
+    }
+
+    fn set_discipline(&self, username: &str, discipline: Option<String>) -> RepoResult<()> {
+        update_user(self, username, |user| user.discipline = discipline)
+    }
+
+    fn set_profile_pic(&self, username: &str, path: &str) -> RepoResult<()> {
+        update_user(self, username, |user| {
+            user.profile_pic = Some(path.to_string())
+        })
+    }
+
+    fn touch(&self, username: &str) -> RepoResult<()> {
+        update_user(self, username, |user| {
+            user.last_active_at = Some(chrono::Utc::now().naive_utc())
+        })
+    }
+
+    fn set_email(&self, username: &str, email: &str) -> RepoResult<()> {
+        let taken = self.state().users.values().any(|user| {
+            user.username != username
+                && user.email.as_ref().map(|e| e.to_lowercase()) == Some(email.to_lowercase())
+        });
+        if taken {
+            return Err(RepoError::Conflict("email"));
+        }
+        update_user(self, username, |user| {
+            user.email = Some(email.to_string());
+            user.email_verified = false;
+        })?;
+        self.state()
+            .email_verifications
+            .retain(|_, verification| verification.username != username);
+        Ok(())
+    }
+
+    fn find_by_verified_email(&self, email: &str) -> RepoResult<Option<DBUser>> {
+        let lowered = Some(email.to_lowercase());
+        Ok(self
+            .state()
+            .users
+            .values()
+            .find(|user| {
+                user.email_verified && user.email.as_ref().map(|e| e.to_lowercase()) == lowered
+            })
+            .cloned())
+    }
+
+    fn add_email_verification(&self, verification: DBEmailVerification) -> RepoResult<()> {
+        self.state()
+            .email_verifications
+            .insert(verification.token_hash.clone(), verification);
+        Ok(())
+    }
+
+    fn verify_email(&self, token_hash: &str) -> RepoResult<bool> {
+        let mut state = self.state();
+        let now = chrono::Utc::now().naive_utc();
+        let (username, email) = match state.email_verifications.get(token_hash) {
+            Some(v) if v.expires_at > now => (v.username.clone(), v.email.clone()),
+            _ => return Ok(false),
+        };
+        let verified = match state.users.get_mut(&username) {
+            Some(user) if user.email.as_ref() == Some(&email) => {
+                user.email_verified = true;
+                true
+            }
+            _ => false,
+        };
+        state
+            .email_verifications
+            .retain(|_, verification| verification.username != username);
+        Ok(verified)
+    }
+
+    fn add_password_reset(&self, reset: DBPasswordReset) -> RepoResult<()> {
+        self.state()
+            .password_resets
+            .insert(reset.token_hash.clone(), reset);
+        Ok(())
+    }
+
+    fn reset_password(&self, token_hash: &str, password: &str) -> RepoResult<Option<String>> {
+        let mut state = self.state();
+        let now = chrono::Utc::now().naive_utc();
+        let username = match state.password_resets.get_mut(token_hash) {
+            Some(reset) if reset.used_at.is_none() && reset.expires_at > now => {
+                reset.used_at = Some(now);
+                reset.username.clone()
+            }
+            _ => return Ok(None),
+        };
+        if let Some(user) = state.users.get_mut(&username) {
+            user.password = password.to_string();
+        }
+        state
+            .password_resets
+            .retain(|_, reset| reset.username != username || reset.used_at.is_some());
+        Ok(Some(username))
+    }
+
+    fn ping(&self) -> RepoResult<()> {
+        Ok(())
+    }
+}
+
+impl SwipeRepository for MemoryRepository {
+    fn unswiped(
+        &self,
+        username: &str,
+        near: Option<(f64, f64)>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBUser>> {
+        let state = self.state();
+        let mut found: Vec<DBUser> = state
+            .users
+            .values()
+            .filter(|user| user.username != username)
+            .filter(|user| {
+                !state
+                    .swipes
+                    .contains_key(&(username.to_string(), user.username.clone()))
+            })
+            .filter(|user| user.email_verified && user.lat.is_some() && user.long.is_some())
+            .filter(|user| user.restriction().is_none())
+            .cloned()
+            .collect();
+        if let Some(from) = near {
+            let distance = |user: &DBUser| {
+                geo::haversine_km(from, (user.lat.unwrap_or(0.0), user.long.unwrap_or(0.0)))
+            };
+            found.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        }
+        found.truncate(limit.max(0) as usize);
+        Ok(found)
+    }
+
+    fn super_likers(&self, username: &str, limit: i64) -> RepoResult<Vec<DBUser>> {
+        let state = self.state();
+        let found = state
+            .users
+            .values()
+            .filter(|user| {
+                let liked = (user.username.clone(), username.to_string());
+                let swiped = (username.to_string(), user.username.clone());
+                state.swipes.get(&liked) == Some(&SwipeKind::Super)
+                    && !state.swipes.contains_key(&swiped)
+            })
+            .filter(|user| user.email_verified && user.lat.is_some() && user.long.is_some())
+            .filter(|user| user.restriction().is_none())
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+
+    fn record(&self, swiper: &str, swiped: &str, kind: SwipeKind) -> RepoResult<()> {
+        let mut state = self.state();
+        if swiper == swiped {
+            return Err(RepoError::Backend("users can't swipe on themselves".into()));
+        }
+        for username in [swiper, swiped].iter() {
+            if !state.users.contains_key(*username) {
+                return Err(RepoError::Backend(format!("no such user {}", username)));
+            }
+        }
+        let key = (swiper.to_string(), swiped.to_string());
+        if state.swipes.contains_key(&key) {
+            return Err(RepoError::Conflict("swipe"));
+        }
+        state.swipes.insert(key, kind);
+        Ok(())
+    }
+
+    fn swiped_right(&self, swiper: &str, swiped: &str) -> RepoResult<bool> {
+        let key = (swiper.to_string(), swiped.to_string());
+        Ok(self
+            .state()
+            .swipes
+            .get(&key)
+            .is_some_and(|kind| kind.is_right()))
+    }
+
+    fn likes_received(
+        &self,
+        username: &str,
+        after: Option<&str>,
+        limit: i64,
+    ) -> RepoResult<Vec<(DBUser, SwipeKind)>> {
+        let state = self.state();
+        let found = state
+            .users
+            .values()
+            .filter(|user| user.restriction().is_none())
+            .filter(|user| match after {
+                Some(after) => user.username.as_str() > after,
+                None => true,
+            })
+            .filter_map(|user| {
+                let liked = (user.username.clone(), username.to_string());
+                let swiped = (username.to_string(), user.username.clone());
+                match state.swipes.get(&liked) {
+                    Some(kind) if kind.is_right() && !state.swipes.contains_key(&swiped) => {
+                        Some((user.clone(), *kind))
+                    }
+                    _ => None,
+                }
+            })
+            .take(limit.max(0) as usize)
+            .collect();
+        Ok(found)
+    }
+
+    fn received(&self, usernames: &[String]) -> RepoResult<HashMap<String, Received>> {
+        let mut received: HashMap<String, Received> = HashMap::new();
+        for ((_, swiped), kind) in self.state().swipes.iter() {
+            if usernames.contains(swiped) {
+                let counts = received.entry(swiped.clone()).or_default();
+                counts.total += 1;
+                if kind.is_right() {
+                    counts.right += 1;
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    fn liked_by_similar(
+        &self,
+        username: &str,
+        candidates: &[String],
+    ) -> RepoResult<HashMap<String, i64>> {
+        let state = self.state();
+        let right_swipes = || {
+            state
+                .swipes
+                .iter()
+                .filter(|(_, kind)| kind.is_right())
+                .map(|(k, _)| k)
+        };
+        let liked: BTreeSet<&String> = right_swipes()
+            .filter(|(swiper, _)| swiper == username)
+            .map(|(_, swiped)| swiped)
+            .collect();
+
+        let mut shared: HashMap<&String, i64> = HashMap::new();
+        for (swiper, swiped) in right_swipes() {
+            if swiper != username && liked.contains(swiped) {
+                *shared.entry(swiper).or_default() += 1;
+            }
+        }
+        let mut similar: Vec<(&String, i64)> = shared.into_iter().collect();
+        similar.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        similar.truncate(SIMILAR_USERS as usize);
+        let similar: HashMap<&String, i64> = similar.into_iter().collect();
+
+        let mut scores = HashMap::new();
+        for (swiper, swiped) in right_swipes() {
+            if let Some(shared) = similar.get(swiper) {
+                if candidates.contains(swiped) {
+                    *scores.entry(swiped.clone()).or_default() += shared;
+                }
+            }
+        }
+        Ok(scores)
+    }
+}
+
+impl MatchRepository for MemoryRepository {
+    fn create(&self, username1: &str, username2: &str) -> RepoResult<()> {
+        let pair = ordered_pair(username1, username2);
+        let key = (pair.username1.clone(), pair.username2.clone());
+        let mut state = self.state();
+        if state.matches.contains_key(&key) {
+            return Err(RepoError::Conflict("match"));
+        }
+        state.matches.insert(key, pair);
+        Ok(())
+    }
+
+    fn for_user(&self, username: &str) -> RepoResult<Vec<DBMatch>> {
+        let found = self
+            .state()
+            .matches
+            .values()
+            .filter(|m| m.username1 == username || m.username2 == username)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+
+    fn open(&self, username: &str, other: &str) -> RepoResult<Option<DBMatch>> {
+        let pair = ordered_pair(username, other);
+        let mut state = self.state();
+        Ok(state
+            .matches
+            .get_mut(&(pair.username1, pair.username2))
+            .map(|found| {
+                found.last_activity_at = chrono::Utc::now().naive_utc();
+                found.clone()
+            }))
+    }
+
+    fn delete(&self, username1: &str, username2: &str) -> RepoResult<bool> {
+        let pair = ordered_pair(username1, username2);
+        Ok(self
+            .state()
+            .matches
+            .remove(&(pair.username1, pair.username2))
+            .is_some())
+    }
+
+    fn extend(&self, username: &str, other: &str) -> RepoResult<bool> {
+        let pair = ordered_pair(username, other);
+        let mut state = self.state();
+        let found = match state.matches.get_mut(&(pair.username1, pair.username2)) {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        if found.extended_by.is_some() {
+            return Err(RepoError::Conflict("extension"));
+        }
+        found.last_activity_at = chrono::Utc::now().naive_utc();
+        found.extended_by = Some(username.to_string());
+        Ok(true)
+    }
+
+    fn expire(&self, inactive_since: NaiveDateTime) -> RepoResult<usize> {
+        let mut state = self.state();
+        let now = chrono::Utc::now().naive_utc();
+        let (expired, kept) = std::mem::take(&mut state.matches)
+            .into_iter()
+            .partition::<BTreeMap<_, _>, _>(|(_, m)| m.last_activity_at < inactive_since);
+        state.matches = kept;
+        let count = expired.len();
+        for m in expired.into_values() {
+            let id = state.match_history.len() as i32 + 1;
+            state.match_history.push(DBMatchHistory {
+                id,
+                username1: m.username1,
+                username2: m.username2,
+                created_at: m.created_at,
+                last_activity_at: m.last_activity_at,
+                extended_by: m.extended_by,
+                expired_at: now,
+            });
+        }
+        Ok(count)
+    }
+
+    fn expired_for(&self, username: &str) -> RepoResult<Vec<DBMatchHistory>> {
+        let found = self
+            .state()
+            .match_history
+            .iter()
+            .rev()
+            .filter(|m| m.username1 == username || m.username2 == username)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+}
+
+impl AdminRepository for MemoryRepository {
+    fn users(
+        &self,
+        prefix: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBUser>> {
+        let found = self
+            .state()
+            .users
+            .values()
+            .filter(|user| prefix.is_none_or(|prefix| user.username.starts_with(prefix)))
+            .filter(|user| after.is_none_or(|after| user.username.as_str() > after))
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+
+    fn clear_bio(&self, username: &str, audit: &Audit) -> RepoResult<bool> {
+        Ok(audit_user(self, username, audit, |user| user.bio = None))
+    }
+
+    fn clear_profile_pic(&self, username: &str, audit: &Audit) -> RepoResult<Option<DBUser>> {
+        let mut before = None;
+        audit_user(self, username, audit, |user| {
+            before = Some(user.clone());
+            user.profile_pic = None;
+        });
+        Ok(before)
+    }
+
+    fn suspend(&self, username: &str, until: NaiveDateTime, audit: &Audit) -> RepoResult<bool> {
+        Ok(audit_user(self, username, audit, |user| {
+            user.suspended_until = Some(until)
+        }))
+    }
+
+    fn ban(&self, username: &str, audit: &Audit) -> RepoResult<bool> {
+        Ok(audit_user(self, username, audit, |user| user.banned = true))
+    }
+
+    fn reinstate(&self, username: &str, audit: &Audit) -> RepoResult<bool> {
+        Ok(audit_user(self, username, audit, |user| {
+            user.banned = false;
+            user.suspended_until = None;
+        }))
+    }
+
+    fn delete_match(&self, username1: &str, username2: &str, audit: &Audit) -> RepoResult<bool> {
+        let pair = ordered_pair(username1, username2);
+        let mut state = self.state();
+        let target = format!("{}/{}", pair.username1, pair.username2);
+        if state
+            .matches
+            .remove(&(pair.username1, pair.username2))
+            .is_none()
+        {
+            return Ok(false);
+        }
+        state.record_action(&target, audit);
+        Ok(true)
+    }
+
+    fn record(&self, target: &str, audit: &Audit) -> RepoResult<()> {
+        self.state().record_action(target, audit);
+        Ok(())
+    }
+
+    fn actions(
+        &self,
+        who: Option<&str>,
+        before: Option<i32>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBAdminAction>> {
+        let found = self
+            .state()
+            .admin_actions
+            .iter()
+            .rev()
+            .filter(|a| who.is_none_or(|who| a.target == who || a.admin == who))
+            .filter(|a| before.is_none_or(|before| a.id < before))
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect();
+        Ok(found)
+    }
+}
+
+impl WebhookRepository for MemoryRepository {
+    fn register(
+        &self,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        audit: &Audit,
+    ) -> RepoResult<DBWebhook> {
+        let mut state = self.state();
+        let id = state.webhooks.keys().next_back().map_or(1, |id| id + 1);
+        let webhook = DBWebhook {
+            id,
+            url: url.to_string(),
+            secret: secret.to_string(),
+            events: events.iter().map(|e| e.as_str().to_string()).collect(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        state.webhooks.insert(id, webhook.clone());
+        state.record_action(&id.to_string(), audit);
+        Ok(webhook)
+    }
+
+    fn unregister(&self, id: i32, audit: &Audit) -> RepoResult<bool> {
+        let mut state = self.state();
+        if state.webhooks.remove(&id).is_none() {
+            return Ok(false);
+        }
+        state
+            .webhook_members
+            .retain(|(webhook_id, _), _| *webhook_id != id);
+        state.webhook_deliveries.retain(|_, d| d.webhook_id != id);
+        state.record_action(&id.to_string(), audit);
+        Ok(true)
+    }
+
+    fn webhooks(&self) -> RepoResult<Vec<DBWebhook>> {
+        Ok(self.state().webhooks.values().cloned().collect())
+    }
+
+    fn webhook(&self, id: i32) -> RepoResult<Option<DBWebhook>> {
+        Ok(self.state().webhooks.get(&id).cloned())
+    }
+
+    fn add_member(&self, webhook_id: i32, username: &str, audit: &Audit) -> RepoResult<bool> {
+        let mut state = self.state();
+        if !state.webhooks.contains_key(&webhook_id) || !state.users.contains_key(username) {
+            return Ok(false);
+        }
+        let key = (webhook_id, username.to_string());
+        if let Entry::Vacant(member) = state.webhook_members.entry(key) {
+            member.insert(chrono::Utc::now().naive_utc());
+            state.record_action(&webhook_id.to_string(), audit);
+        }
+        Ok(true)
+    }
+
+    fn remove_member(&self, webhook_id: i32, username: &str, audit: &Audit) -> RepoResult<bool> {
+        let mut state = self.state();
+        if state
+            .webhook_members
+            .remove(&(webhook_id, username.to_string()))
+            .is_none()
+        {
+            return Ok(false);
+        }
+        state.record_action(&webhook_id.to_string(), audit);
+        Ok(true)
+    }
+
+    fn members(&self, webhook_id: i32) -> RepoResult<Vec<DBWebhookMember>> {
+        Ok(self
+            .state()
+            .webhook_members
+            .iter()
+            .filter(|((id, _), _)| *id == webhook_id)
+            .map(|((webhook_id, username), created_at)| DBWebhookMember {
+                webhook_id: *webhook_id,
+                username: username.clone(),
+                created_at: *created_at,
+            })
+            .collect())
+    }
+
+    fn subscribed_to(
+        &self,
+        event: WebhookEvent,
+        usernames: &[String],
+    ) -> RepoResult<Vec<DBWebhook>> {
+        let state = self.state();
+        Ok(state
+            .webhooks
+            .values()
+            .filter(|w| w.events.iter().any(|e| e == event.as_str()))
+            .filter(|w| {
+                usernames.iter().any(|username| {
+                    state
+                        .webhook_members
+                        .contains_key(&(w.id, username.clone()))
+                })
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn add_delivery(
+        &self,
+        job_id: i64,
+        webhook_id: i32,
+        event: WebhookEvent,
+        data: &Value,
+    ) -> RepoResult<Option<i64>> {
+        let mut state = self.state();
+        let made = state
+            .webhook_deliveries
+            .values()
+            .any(|d| d.job_id == Some(job_id) && d.webhook_id == webhook_id);
+        if made {
+            return Ok(None);
+        }
+        let id = state
+            .webhook_deliveries
+            .keys()
+            .next_back()
+            .map_or(1, |id| id + 1);
+        state.webhook_deliveries.insert(
+            id,
+            DBWebhookDelivery {
+                id,
+                webhook_id,
+                event: event.as_str().to_string(),
+                payload: data.clone(),
+                status: PENDING.to_string(),
+                attempts: 0,
+                response_status: None,
+                last_error: None,
+                created_at: chrono::Utc::now().naive_utc(),
+                delivered_at: None,
+                job_id: Some(job_id),
+            },
+        );
+        Ok(Some(id))
+    }
+
+    fn remove_delivery(&self, id: i64) -> RepoResult<()> {
+        self.state().webhook_deliveries.remove(&id);
+        Ok(())
+    }
+
+    fn delivery(&self, id: i64) -> RepoResult<Option<DBWebhookDelivery>> {
+        Ok(self.state().webhook_deliveries.get(&id).cloned())
+    }
+
+    fn record_attempt(
+        &self,
+        id: i64,
+        status: &str,
+        response_status: Option<i32>,
+        error: Option<&str>,
+    ) -> RepoResult<()> {
+        if let Some(delivery) = self.state().webhook_deliveries.get_mut(&id) {
+            delivery.status = status.to_string();
+            delivery.attempts += 1;
+            delivery.response_status = response_status;
+            delivery.last_error = error.map(str::to_string);
+            if status == DELIVERED {
+                delivery.delivered_at = Some(chrono::Utc::now().naive_utc());
+            }
+        }
+        Ok(())
+    }
+
+    fn deliveries(
+        &self,
+        webhook_id: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBWebhookDelivery>> {
+        Ok(self
+            .state()
+            .webhook_deliveries
+            .values()
+            .rev()
+            .filter(|d| d.webhook_id == webhook_id)
+            .filter(|d| before.is_none_or(|before| d.id < before))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
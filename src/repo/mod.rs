@@ -0,0 +1,341 @@
+//! Storage for users, swipes and matches behind traits, so handlers don't
+//! care whether they're talking to Postgres or, in tests, to memory.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use chrono::NaiveDateTime;
+use serde_json::Value;
+
+use crate::db::{
+    DBAdminAction, DBEmailVerification, DBMatch, DBMatchHistory, DBPasswordReset, DBUser,
+    DBWebhook, DBWebhookDelivery, DBWebhookMember, SwipeKind,
+};
+use crate::webhooks::WebhookEvent;
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::MemoryRepository;
+pub use postgres::PgRepository;
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// A uniqueness rule was broken, naming what was already taken: one of
+    /// `"username"`, `"email"`, `"swipe"`, `"match"` or `"extension"`.
+    Conflict(&'static str),
+    /// The store couldn't be reached or the query failed.
+    Backend(String),
+}
+
+impl RepoError {
+    pub(crate) fn backend(err: impl Display) -> Self {
+        RepoError::Backend(err.to_string())
+    }
+}
+
+impl Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::Conflict(what) => write!(f, "{} already exists", what),
+            RepoError::Backend(err) => f.write_str(err),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
+
+pub type RepoResult<T> = Result<T, RepoError>;
+
+/// How many users with similar taste `SwipeRepository::liked_by_similar`
+/// looks at.
+pub const SIMILAR_USERS: i64 = 100;
+
+/// Filters for `UserRepository::search`. Results are ordered by username and
+/// never include banned users.
+pub struct UserSearch<'a> {
+    /// Only usernames starting with this, matched case sensitively.
+    pub prefix: Option<&'a str>,
+    pub discipline: Option<&'a str>,
+    /// Only users within this many km of the given (lat, long).
+    pub within_km: Option<((f64, f64), f64)>,
+    /// Only usernames after this one, for paging.
+    pub after: Option<&'a str>,
+    pub limit: i64,
+}
+
+pub trait UserRepository: Send + Sync {
+    fn find(&self, username: &str) -> RepoResult<Option<DBUser>>;
+
+    /// Usernames are unique ignoring case, so this finds at most one user.
+    fn find_ignoring_case(&self, username: &str) -> RepoResult<Option<DBUser>>;
+
+    /// Fails with a conflict if the username or email is taken, ignoring case.
+    fn create(&self, user: DBUser) -> RepoResult<DBUser>;
+
+    /// Leaves out banned and suspended users.
+    fn search(&self, search: &UserSearch) -> RepoResult<Vec<DBUser>>;
+
+    /// Like the other setters, fails if there's no such user.
+    fn set_location(&self, username: &str, lat: f64, long: f64) -> RepoResult<()>;
+
+    fn set_bio(&self, username: &str, bio: String) -> RepoResult<()>;
+
+    fn set_discipline(&self, username: &str, discipline: Option<String>) -> RepoResult<()>;
+
+    fn set_profile_pic(&self, username: &str, path: &str) -> RepoResult<()>;
+
+    /// Records that the user did something just now.
+    fn touch(&self, username: &str) -> RepoResult<()>;
+
+    /// Changes a user's email, which then needs verifying again, and drops
+    /// the links sent to verify the old one. Fails with a conflict if someone
+    /// else has the address, ignoring case.
+    fn set_email(&self, username: &str, email: &str) -> RepoResult<()>;
+
+    /// The user with this verified email, ignoring case.
+    fn find_by_verified_email(&self, email: &str) -> RepoResult<Option<DBUser>>;
+
+    fn add_email_verification(&self, verification: DBEmailVerification) -> RepoResult<()>;
+
+    /// Marks the address an unexpired verification link was sent to as
+    /// verified, and drops the user's other links. Returns false if the link
+    /// is unknown or expired, or the user has changed their email since.
+    fn verify_email(&self, token_hash: &str) -> RepoResult<bool>;
+
+    fn add_password_reset(&self, reset: DBPasswordReset) -> RepoResult<()>;
+
+    /// Uses up an unexpired reset link to set the user's password hash, and
+    /// drops their other unused links, returning whose password it was.
+    /// `None` if the link is unknown, used or expired. Two requests racing
+    /// with the same link can't both succeed.
+    fn reset_password(&self, token_hash: &str, password: &str) -> RepoResult<Option<String>>;
+
+    /// Checks the store can be reached.
+    fn ping(&self) -> RepoResult<()>;
+}
+
+pub trait SwipeRepository: Send + Sync {
+    /// Up to `limit` users that `username` could swipe on: ones they haven't
+    /// swiped on yet, with a verified email and a location set, and who
+    /// aren't banned or suspended. They're nearest first if `near` is given,
+    /// otherwise in no particular order.
+    fn unswiped(
+        &self,
+        username: &str,
+        near: Option<(f64, f64)>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBUser>>;
+
+    /// Up to `limit` users who super-liked `username` and whom `username`
+    /// hasn't swiped on yet, with the same conditions as `unswiped`, ordered
+    /// by username.
+    fn super_likers(&self, username: &str, limit: i64) -> RepoResult<Vec<DBUser>>;
+
+    /// Records a swipe. Fails with a conflict if the swiper has already
+    /// swiped on that user.
+    fn record(&self, swiper: &str, swiped: &str, kind: SwipeKind) -> RepoResult<()>;
+
+    /// Whether `swiper` liked or super-liked `swiped`.
+    fn swiped_right(&self, swiper: &str, swiped: &str) -> RepoResult<bool>;
+
+    /// Up to `limit` users who swiped right on `username` and whom `username`
+    /// hasn't swiped on yet, with how they swiped, ordered by username and
+    /// starting after `after`. Banned and suspended users are left out.
+    fn likes_received(
+        &self,
+        username: &str,
+        after: Option<&str>,
+        limit: i64,
+    ) -> RepoResult<Vec<(DBUser, SwipeKind)>>;
+
+    /// How many swipes each of `usernames` has received, and how many of
+    /// those were likes or super-likes. Users nobody has swiped on are left out.
+    fn received(&self, usernames: &[String]) -> RepoResult<HashMap<String, Received>>;
+
+    /// Scores each of `candidates` by how much users with similar taste to
+    /// `username` like them: every right swipe a candidate got from someone
+    /// who also swiped right on people `username` swiped right on counts once
+    /// for each person they both liked. Only the `SIMILAR_USERS` users who
+    /// share the most right swipes with `username` are considered. Candidates
+    /// scoring 0 are left out.
+    fn liked_by_similar(
+        &self,
+        username: &str,
+        candidates: &[String],
+    ) -> RepoResult<HashMap<String, i64>>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Received {
+    pub right: i64,
+    pub total: i64,
+}
+
+pub trait MatchRepository: Send + Sync {
+    /// Matches two users, in whichever order they're given. Fails with a
+    /// conflict if they're already matched.
+    fn create(&self, username1: &str, username2: &str) -> RepoResult<()>;
+
+    fn for_user(&self, username: &str) -> RepoResult<Vec<DBMatch>>;
+
+    /// The users' match, counting as activity on it and putting off its
+    /// expiry. `None` if they aren't matched.
+    fn open(&self, username: &str, other: &str) -> RepoResult<Option<DBMatch>>;
+
+    /// Unmatches two users, returning whether they were matched.
+    fn delete(&self, username1: &str, username2: &str) -> RepoResult<bool>;
+
+    /// Counts as activity on a match, putting off its expiry. Each match can
+    /// be extended once, by either user; after that this fails with a
+    /// conflict. Returns whether the users were matched.
+    fn extend(&self, username: &str, other: &str) -> RepoResult<bool>;
+
+    /// Moves every match with no activity since `inactive_since` to the match
+    /// history, returning how many there were.
+    fn expire(&self, inactive_since: NaiveDateTime) -> RepoResult<usize>;
+
+    /// The user's expired matches, most recently expired first.
+    fn expired_for(&self, username: &str) -> RepoResult<Vec<DBMatchHistory>>;
+}
+
+/// An admin making a change, recorded in the audit log along with the change
+/// and in the same transaction.
+pub struct Audit<'a> {
+    pub admin: &'a str,
+    /// What was done, such as `"ban"`.
+    pub action: &'a str,
+    /// Anything else worth keeping, such as the reason for a ban.
+    pub details: Option<String>,
+}
+
+/// What admins can do beyond the other repositories. Changes are audited
+/// with what they changed as the target, and nothing is recorded if there was
+/// nothing to change.
+pub trait AdminRepository: Send + Sync {
+    /// Users whose username starts with `prefix`, banned and suspended ones
+    /// included, ordered by username and starting after `after`.
+    fn users(
+        &self,
+        prefix: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBUser>>;
+
+    /// Returns whether there was such a user, like the other changes to one.
+    fn clear_bio(&self, username: &str, audit: &Audit) -> RepoResult<bool>;
+
+    /// Returns the user as they were, so their picture can be removed.
+    fn clear_profile_pic(&self, username: &str, audit: &Audit) -> RepoResult<Option<DBUser>>;
+
+    fn suspend(&self, username: &str, until: NaiveDateTime, audit: &Audit) -> RepoResult<bool>;
+
+    fn ban(&self, username: &str, audit: &Audit) -> RepoResult<bool>;
+
+    /// Lifts any ban or suspension.
+    fn reinstate(&self, username: &str, audit: &Audit) -> RepoResult<bool>;
+
+    /// Unmatches two users, in whichever order they're given, returning
+    /// whether they were matched. The target is `username1/username2` in
+    /// the order matches are stored.
+    fn delete_match(&self, username1: &str, username2: &str, audit: &Audit) -> RepoResult<bool>;
+
+    /// Records a change made elsewhere, such as to a job.
+    fn record(&self, target: &str, audit: &Audit) -> RepoResult<()>;
+
+    /// Actions by or on `who`, newest first, with ids below `before`.
+    fn actions(
+        &self,
+        who: Option<&str>,
+        before: Option<i32>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBAdminAction>>;
+}
+
+pub trait WebhookRepository: Send + Sync {
+    /// Adds a webhook, recording `audit` against its id.
+    fn register(
+        &self,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        audit: &Audit,
+    ) -> RepoResult<DBWebhook>;
+
+    /// Removes a webhook along with its members and deliveries, returning
+    /// false if there's no webhook with that id.
+    fn unregister(&self, id: i32, audit: &Audit) -> RepoResult<bool>;
+
+    /// Every webhook, oldest first.
+    fn webhooks(&self) -> RepoResult<Vec<DBWebhook>>;
+
+    fn webhook(&self, id: i32) -> RepoResult<Option<DBWebhook>>;
+
+    /// Adds `username` to the members whose events a webhook is sent,
+    /// returning false if there's no such webhook or user. Adding someone
+    /// twice does nothing, and is only audited the first time.
+    fn add_member(&self, webhook_id: i32, username: &str, audit: &Audit) -> RepoResult<bool>;
+
+    /// Returns false if `username` wasn't a member of the webhook.
+    fn remove_member(&self, webhook_id: i32, username: &str, audit: &Audit) -> RepoResult<bool>;
+
+    /// A webhook's members, by username.
+    fn members(&self, webhook_id: i32) -> RepoResult<Vec<DBWebhookMember>>;
+
+    /// The webhooks subscribed to `event` with any of `usernames` as a member.
+    fn subscribed_to(
+        &self,
+        event: WebhookEvent,
+        usernames: &[String],
+    ) -> RepoResult<Vec<DBWebhook>>;
+
+    /// Records a pending delivery of an event to a webhook, made by the job
+    /// `job_id`, returning its id. Returns `None` if that job already made
+    /// one for the webhook.
+    fn add_delivery(
+        &self,
+        job_id: i64,
+        webhook_id: i32,
+        event: WebhookEvent,
+        data: &Value,
+    ) -> RepoResult<Option<i64>>;
+
+    fn remove_delivery(&self, id: i64) -> RepoResult<()>;
+
+    fn delivery(&self, id: i64) -> RepoResult<Option<DBWebhookDelivery>>;
+
+    /// Counts an attempt at a delivery, leaving it in `status`.
+    fn record_attempt(
+        &self,
+        id: i64,
+        status: &str,
+        response_status: Option<i32>,
+        error: Option<&str>,
+    ) -> RepoResult<()>;
+
+    /// A webhook's deliveries newest first, with ids below `before` for paging.
+    fn deliveries(
+        &self,
+        webhook_id: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBWebhookDelivery>>;
+}
+
+/// Matches are stored with the usernames in order so each pair has one row.
+/// This is a match made just now.
+pub(crate) fn ordered_pair(username1: &str, username2: &str) -> DBMatch {
+    let (username1, username2) = if username1 < username2 {
+        (username1, username2)
+    } else {
+        (username2, username1)
+    };
+    let now = chrono::Utc::now().naive_utc();
+    DBMatch {
+        username1: username1.to_string(),
+        username2: username2.to_string(),
+        created_at: now,
+        last_activity_at: now,
+        extended_by: None,
+    }
+}
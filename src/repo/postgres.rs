@@ -0,0 +1,961 @@
+use std::collections::HashMap;
+use std::{sync::Arc, time::Duration};
+
+use chrono::NaiveDateTime;
+use diesel::dsl::{exists, not, sql, And, Eq, IsNull, LtEq, Or};
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::sql_types::{Array, BigInt, Bool, Double, Text, Timestamp, Varchar};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    PgArrayExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    TextExpressionMethods,
+};
+use serde_json::Value;
+
+use super::{
+    ordered_pair, AdminRepository, Audit, MatchRepository, Received, RepoError, RepoResult,
+    SwipeRepository, UserRepository, UserSearch, WebhookRepository, SIMILAR_USERS,
+};
+use crate::db::{
+    lower, lower_nullable, DBAdminAction, DBEmailVerification, DBMatch, DBMatchHistory,
+    DBPasswordReset, DBSwipe, DBUser, DBWebhook, DBWebhookDelivery, DBWebhookMember,
+    NewAdminAction, NewWebhook, NewWebhookDelivery, NewWebhookMember, SwipeKind,
+};
+use crate::geo;
+use crate::paths::users::escape_like;
+use crate::schema::{
+    admin_actions, email_verifications, match_history, matches, password_resets, swipes, users,
+    webhook_deliveries, webhook_members, webhooks,
+};
+use crate::webhooks::{WebhookEvent, DELIVERED};
+
+/// The repositories backed by Postgres through Diesel.
+pub struct PgRepository {
+    pool: Arc<Pool<ConnectionManager<PgConnection>>>,
+}
+
+impl PgRepository {
+    pub fn new(pool: Arc<Pool<ConnectionManager<PgConnection>>>) -> Self {
+        PgRepository { pool }
+    }
+
+    fn conn(&self) -> RepoResult<PooledConnection<ConnectionManager<PgConnection>>> {
+        self.pool
+            .get_timeout(Duration::from_millis(500))
+            .map_err(RepoError::backend)
+    }
+
+    /// Makes a change, auditing it if it changed any rows, and returns
+    /// whether it did.
+    fn audited(
+        &self,
+        target: &str,
+        audit: &Audit,
+        change: impl FnOnce(&PgConnection) -> QueryResult<usize>,
+    ) -> RepoResult<bool> {
+        let conn = self.conn()?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let changed = change(&conn)?;
+            if changed > 0 {
+                record_action(&conn, target, audit)?;
+            }
+            Ok(changed > 0)
+        })
+        .map_err(RepoError::backend)
+    }
+}
+
+fn record_action(conn: &PgConnection, target: &str, audit: &Audit) -> QueryResult<()> {
+    diesel::insert_into(admin_actions::table)
+        .values(NewAdminAction {
+            admin: audit.admin,
+            action: audit.action,
+            target,
+            details: audit.details.clone(),
+        })
+        .execute(conn)
+        .map(|_| ())
+}
+
+pub(crate) fn conflict_or_backend(err: diesel::result::Error) -> RepoError {
+    match err {
+        DatabaseError(DatabaseErrorKind::UniqueViolation, info) => match info.constraint_name() {
+            Some("users_pkey") | Some("users_username_lower_idx") => {
+                RepoError::Conflict("username")
+            }
+            Some("users_email_lower_idx") => RepoError::Conflict("email"),
+            Some("swipe_pk") => RepoError::Conflict("swipe"),
+            Some("match_pk") => RepoError::Conflict("match"),
+            _ => RepoError::backend(DatabaseError(DatabaseErrorKind::UniqueViolation, info)),
+        },
+        err => RepoError::backend(err),
+    }
+}
+
+/// Users who are neither banned nor suspended right now.
+fn not_restricted() -> And<
+    Eq<users::banned, bool>,
+    Or<IsNull<users::suspended_until>, LtEq<users::suspended_until, NaiveDateTime>>,
+> {
+    users::banned.eq(false).and(
+        users::suspended_until
+            .is_null()
+            .or(users::suspended_until.le(chrono::Utc::now().naive_utc())),
+    )
+}
+
+/// Fails if `username` didn't exist to be updated.
+fn updated_user(updated: QueryResult<usize>, username: &str) -> RepoResult<()> {
+    match updated {
+        Ok(0) => Err(RepoError::Backend(format!("no user named {}", username))),
+        Ok(_) => Ok(()),
+        Err(err) => Err(conflict_or_backend(err)),
+    }
+}
+
+impl UserRepository for PgRepository {
+    fn find(&self, username: &str) -> RepoResult<Option<DBUser>> {
+        users::table
+            .find(username)
+            .first::<DBUser>(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn find_ignoring_case(&self, username: &str) -> RepoResult<Option<DBUser>> {
+        users::table
+            .filter(lower(users::username).eq(username.to_lowercase()))
+            .first::<DBUser>(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn create(&self, user: DBUser) -> RepoResult<DBUser> {
+        diesel::insert_into(users::table)
+            .values(&user)
+            .get_result::<DBUser>(&self.conn()?)
+            .map_err(conflict_or_backend)
+    }
+
+    fn search(&self, search: &UserSearch) -> RepoResult<Vec<DBUser>> {
+        let mut select = users::table
+            .filter(not_restricted())
+            .order(users::username)
+            .limit(search.limit)
+            .into_boxed();
+
+        if let Some(prefix) = search.prefix {
+            select = select.filter(users::username.like(format!("{}%", escape_like(prefix))));
+        }
+        if let Some(discipline) = search.discipline {
+            select = select.filter(users::discipline.eq(discipline));
+        }
+        if let Some(after) = search.after {
+            select = select.filter(users::username.gt(after));
+        }
+        if let Some((from, km)) = search.within_km {
+            match geo::covering(from, km) {
+                Some(cells) => select = select.filter(sql::<Bool>(&geo::in_cells_sql(&cells))),
+                None => {
+                    let (min_lat, max_lat, min_long, max_long) = geo::bounding_box(from, km);
+                    select = select.filter(users::lat.between(min_lat, max_lat));
+                    if min_long >= -180.0 && max_long <= 180.0 {
+                        select = select.filter(users::long.between(min_long, max_long));
+                    }
+                }
+            }
+            select = select.filter(sql::<Bool>(&format!(
+                "{} <= {}",
+                geo::haversine_km_sql(from),
+                km
+            )));
+        }
+
+        select
+            .load::<DBUser>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn set_location(&self, username: &str, lat: f64, long: f64) -> RepoResult<()> {
+        let updated = diesel::update(users::table.find(username))
+            .set((
+                users::lat.eq(lat),
+                users::long.eq(long),
+                users::geohash.eq(geo::geohash((lat, long))),
+            ))
+            .execute(&self.conn()?);
+        updated_user(updated, username)
+    }
+
+    fn set_bio(&self, username: &str, bio: String) -> RepoResult<()> {
+        let updated = diesel::update(users::table.find(username))
+            .set(users::bio.eq(bio))
+            .execute(&self.conn()?);
+        updated_user(updated, username)
+    }
+
+    fn set_discipline(&self, username: &str, discipline: Option<String>) -> RepoResult<()> {
+        let updated = diesel::update(users::table.find(username))
+            .set(users::discipline.eq(discipline))
+            .execute(&self.conn()?);
+        updated_user(updated, username)
+    }
+
+    fn set_profile_pic(&self, username: &str, path: &str) -> RepoResult<()> {
+        let updated = diesel::update(users::table.find(username))
+            .set(users::profile_pic.eq(path))
+            .execute(&self.conn()?);
+        updated_user(updated, username)
+    }
+
+    fn touch(&self, username: &str) -> RepoResult<()> {
+        let updated = diesel::update(users::table.find(username))
+            .set(users::last_active_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(&self.conn()?);
+        updated_user(updated, username)
+    }
+
+    fn set_email(&self, username: &str, email: &str) -> RepoResult<()> {
+        let conn = self.conn()?;
+        let updated = conn.transaction::<_, diesel::result::Error, _>(|| {
+            let updated = diesel::update(users::table.find(username))
+                .set((users::email.eq(email), users::email_verified.eq(false)))
+                .execute(&conn)?;
+            diesel::delete(
+                email_verifications::table.filter(email_verifications::username.eq(username)),
+            )
+            .execute(&conn)?;
+            Ok(updated)
+        });
+        updated_user(updated, username)
+    }
+
+    fn find_by_verified_email(&self, email: &str) -> RepoResult<Option<DBUser>> {
+        users::table
+            .filter(lower_nullable(users::email).eq(email.to_lowercase()))
+            .filter(users::email_verified.eq(true))
+            .first::<DBUser>(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn add_email_verification(&self, verification: DBEmailVerification) -> RepoResult<()> {
+        diesel::insert_into(email_verifications::table)
+            .values(&verification)
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(RepoError::backend)
+    }
+
+    fn verify_email(&self, token_hash: &str) -> RepoResult<bool> {
+        let conn = self.conn()?;
+        let checked_at = chrono::Utc::now().naive_utc();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let verification = email_verifications::table
+                .find(token_hash)
+                .filter(email_verifications::expires_at.gt(checked_at))
+                .first::<DBEmailVerification>(&conn)
+                .optional()?;
+            let verification = match verification {
+                Some(verification) => verification,
+                None => return Ok(false),
+            };
+            // the user may have changed their email since this link was sent
+            let updated = diesel::update(
+                users::table
+                    .filter(users::username.eq(&verification.username))
+                    .filter(users::email.eq(&verification.email)),
+            )
+            .set(users::email_verified.eq(true))
+            .execute(&conn)?;
+            diesel::delete(
+                email_verifications::table
+                    .filter(email_verifications::username.eq(&verification.username)),
+            )
+            .execute(&conn)?;
+            Ok(updated > 0)
+        })
+        .map_err(RepoError::backend)
+    }
+
+    fn add_password_reset(&self, reset: DBPasswordReset) -> RepoResult<()> {
+        diesel::insert_into(password_resets::table)
+            .values(&reset)
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(RepoError::backend)
+    }
+
+    fn reset_password(&self, token_hash: &str, password: &str) -> RepoResult<Option<String>> {
+        let conn = self.conn()?;
+        let used_at = chrono::Utc::now().naive_utc();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            // claiming the token and using it happen together so that two
+            // requests racing with the same token can't both succeed
+            let claimed = diesel::update(
+                password_resets::table
+                    .find(token_hash)
+                    .filter(password_resets::used_at.is_null())
+                    .filter(password_resets::expires_at.gt(used_at)),
+            )
+            .set(password_resets::used_at.eq(used_at))
+            .get_result::<DBPasswordReset>(&conn)
+            .optional()?;
+            let claimed = match claimed {
+                Some(claimed) => claimed,
+                None => return Ok(None),
+            };
+
+            diesel::update(users::table.find(&claimed.username))
+                .set(users::password.eq(password))
+                .execute(&conn)?;
+            diesel::delete(
+                password_resets::table
+                    .filter(password_resets::username.eq(&claimed.username))
+                    .filter(password_resets::used_at.is_null()),
+            )
+            .execute(&conn)?;
+            Ok(Some(claimed.username))
+        })
+        .map_err(RepoError::backend)
+    }
+
+    fn ping(&self) -> RepoResult<()> {
+        diesel::sql_query("SELECT 1")
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(RepoError::backend)
+    }
+}
+
+#[derive(QueryableByName)]
+struct ReceivedRow {
+    #[sql_type = "Varchar"]
+    username: String,
+    #[sql_type = "BigInt"]
+    rights: i64,
+    #[sql_type = "BigInt"]
+    total: i64,
+}
+
+#[derive(QueryableByName)]
+struct ScoreRow {
+    #[sql_type = "Varchar"]
+    username: String,
+    #[sql_type = "BigInt"]
+    score: i64,
+}
+
+impl SwipeRepository for PgRepository {
+    fn unswiped(
+        &self,
+        username: &str,
+        near: Option<(f64, f64)>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBUser>> {
+        let conn = self.conn()?;
+        let candidates = || {
+            users::table
+                .filter(
+                    not(exists(
+                        swipes::table
+                            .filter(swipes::swiper.eq(username))
+                            .filter(swipes::swiped.eq(users::username)),
+                    ))
+                    .and(users::username.ne(username)),
+                )
+                .filter(users::email_verified.eq(true))
+                .filter(not_restricted())
+                .filter(not(users::lat.is_null()))
+                .filter(not(users::long.is_null()))
+                .limit(limit)
+                .into_boxed()
+        };
+        let from = match near {
+            Some(from) => from,
+            None => {
+                return candidates()
+                    .load::<DBUser>(&conn)
+                    .map_err(RepoError::backend)
+            }
+        };
+
+        // look in ever larger blocks of geohash cells until one has enough
+        // candidates that none outside it could be nearer, so only a
+        // neighbourhood is sorted rather than everyone
+        let distance = geo::haversine_km_sql(from);
+        for (cells, reach_km) in geo::neighbourhoods(from) {
+            let found = candidates()
+                .filter(sql::<Bool>(&geo::in_cells_sql(&cells)))
+                .order(sql::<Double>(&distance))
+                .load::<DBUser>(&conn)
+                .map_err(RepoError::backend)?;
+            let nearby = match found.last() {
+                Some(DBUser {
+                    lat: Some(lat),
+                    long: Some(long),
+                    ..
+                }) => geo::haversine_km(from, (*lat, *long)) <= reach_km,
+                _ => false,
+            };
+            if found.len() as i64 == limit && nearby {
+                return Ok(found);
+            }
+        }
+        candidates()
+            .order(sql::<Double>(&distance))
+            .load::<DBUser>(&conn)
+            .map_err(RepoError::backend)
+    }
+
+    fn super_likers(&self, username: &str, limit: i64) -> RepoResult<Vec<DBUser>> {
+        users::table
+            .filter(exists(
+                swipes::table
+                    .filter(swipes::swiper.eq(users::username))
+                    .filter(swipes::swiped.eq(username))
+                    .filter(swipes::kind.eq(SwipeKind::Super)),
+            ))
+            .filter(not(exists(
+                swipes::table
+                    .filter(swipes::swiper.eq(username))
+                    .filter(swipes::swiped.eq(users::username)),
+            )))
+            .filter(users::email_verified.eq(true))
+            .filter(not_restricted())
+            .filter(not(users::lat.is_null()))
+            .filter(not(users::long.is_null()))
+            .order(users::username)
+            .limit(limit)
+            .load::<DBUser>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn record(&self, swiper: &str, swiped: &str, kind: SwipeKind) -> RepoResult<()> {
+        let swipe = DBSwipe {
+            swiper: swiper.to_string(),
+            swiped: swiped.to_string(),
+            kind,
+        };
+        diesel::insert_into(swipes::table)
+            .values(&swipe)
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(conflict_or_backend)
+    }
+
+    fn swiped_right(&self, swiper: &str, swiped: &str) -> RepoResult<bool> {
+        diesel::select(exists(
+            swipes::table
+                .filter(swipes::swiper.eq(swiper))
+                .filter(swipes::swiped.eq(swiped))
+                .filter(swipes::kind.ne(SwipeKind::Pass)),
+        ))
+        .get_result(&self.conn()?)
+        .map_err(RepoError::backend)
+    }
+
+    fn likes_received(
+        &self,
+        username: &str,
+        after: Option<&str>,
+        limit: i64,
+    ) -> RepoResult<Vec<(DBUser, SwipeKind)>> {
+        let conn = self.conn()?;
+        let mut select = users::table
+            .filter(exists(
+                swipes::table
+                    .filter(swipes::swiper.eq(users::username))
+                    .filter(swipes::swiped.eq(username))
+                    .filter(swipes::kind.ne(SwipeKind::Pass)),
+            ))
+            .filter(not(exists(
+                swipes::table
+                    .filter(swipes::swiper.eq(username))
+                    .filter(swipes::swiped.eq(users::username)),
+            )))
+            .filter(not_restricted())
+            .order(users::username)
+            .limit(limit)
+            .into_boxed();
+        if let Some(after) = after {
+            select = select.filter(users::username.gt(after));
+        }
+        let found = select.load::<DBUser>(&conn).map_err(RepoError::backend)?;
+
+        let names: Vec<&String> = found.iter().map(|user| &user.username).collect();
+        let kinds: HashMap<String, SwipeKind> = swipes::table
+            .select((swipes::swiper, swipes::kind))
+            .filter(swipes::swiped.eq(username))
+            .filter(swipes::swiper.eq_any(names))
+            .load(&conn)
+            .map_err(RepoError::backend)?
+            .into_iter()
+            .collect();
+        Ok(found
+            .into_iter()
+            .map(|user| {
+                let kind = kinds
+                    .get(&user.username)
+                    .copied()
+                    .unwrap_or(SwipeKind::Like);
+                (user, kind)
+            })
+            .collect())
+    }
+
+    fn received(&self, usernames: &[String]) -> RepoResult<HashMap<String, Received>> {
+        let rows = diesel::sql_query(
+            "SELECT swiped AS username, \
+                 COUNT(*) FILTER (WHERE kind <> 'pass') AS rights, COUNT(*) AS total \
+             FROM swipes WHERE swiped = ANY($1) GROUP BY swiped",
+        )
+        .bind::<Array<Text>, _>(usernames)
+        .load::<ReceivedRow>(&self.conn()?)
+        .map_err(RepoError::backend)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let received = Received {
+                    right: row.rights,
+                    total: row.total,
+                };
+                (row.username, received)
+            })
+            .collect())
+    }
+
+    fn liked_by_similar(
+        &self,
+        username: &str,
+        candidates: &[String],
+    ) -> RepoResult<HashMap<String, i64>> {
+        let rows = diesel::sql_query(
+            "WITH liked AS ( \
+                 SELECT swiped FROM swipes WHERE swiper = $1 AND kind <> 'pass' \
+             ), alike AS ( \
+                 SELECT s.swiper, COUNT(*) AS shared \
+                 FROM swipes s JOIN liked ON s.swiped = liked.swiped \
+                 WHERE s.kind <> 'pass' AND s.swiper <> $1 \
+                 GROUP BY s.swiper ORDER BY shared DESC LIMIT $3 \
+             ) \
+             SELECT s.swiped AS username, SUM(alike.shared)::BIGINT AS score \
+             FROM swipes s JOIN alike ON s.swiper = alike.swiper \
+             WHERE s.kind <> 'pass' AND s.swiped = ANY($2) \
+             GROUP BY s.swiped",
+        )
+        .bind::<Text, _>(username)
+        .bind::<Array<Text>, _>(candidates)
+        .bind::<BigInt, _>(SIMILAR_USERS)
+        .load::<ScoreRow>(&self.conn()?)
+        .map_err(RepoError::backend)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.username, row.score))
+            .collect())
+    }
+}
+
+impl MatchRepository for PgRepository {
+    fn create(&self, username1: &str, username2: &str) -> RepoResult<()> {
+        diesel::insert_into(matches::table)
+            .values(ordered_pair(username1, username2))
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(conflict_or_backend)
+    }
+
+    fn for_user(&self, username: &str) -> RepoResult<Vec<DBMatch>> {
+        matches::table
+            .filter(matches::username1.eq(username))
+            .or_filter(matches::username2.eq(username))
+            .load::<DBMatch>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn open(&self, username: &str, other: &str) -> RepoResult<Option<DBMatch>> {
+        let pair = ordered_pair(username, other);
+        diesel::update(
+            matches::table
+                .filter(matches::username1.eq(&pair.username1))
+                .filter(matches::username2.eq(&pair.username2)),
+        )
+        .set(matches::last_activity_at.eq(chrono::Utc::now().naive_utc()))
+        .get_result::<DBMatch>(&self.conn()?)
+        .optional()
+        .map_err(RepoError::backend)
+    }
+
+    fn delete(&self, username1: &str, username2: &str) -> RepoResult<bool> {
+        diesel::delete(
+            matches::table
+                .filter(
+                    matches::username1
+                        .eq(username1)
+                        .and(matches::username2.eq(username2)),
+                )
+                .or_filter(
+                    matches::username1
+                        .eq(username2)
+                        .and(matches::username2.eq(username1)),
+                ),
+        )
+        .execute(&self.conn()?)
+        .map(|deleted| deleted > 0)
+        .map_err(RepoError::backend)
+    }
+
+    fn extend(&self, username: &str, other: &str) -> RepoResult<bool> {
+        let pair = ordered_pair(username, other);
+        let conn = self.conn()?;
+        let this_match = matches::table
+            .filter(matches::username1.eq(&pair.username1))
+            .filter(matches::username2.eq(&pair.username2));
+        let extended = diesel::update(this_match.filter(matches::extended_by.is_null()))
+            .set((
+                matches::last_activity_at.eq(chrono::Utc::now().naive_utc()),
+                matches::extended_by.eq(username),
+            ))
+            .execute(&conn)
+            .map_err(RepoError::backend)?;
+        if extended > 0 {
+            return Ok(true);
+        }
+        let matched: bool = diesel::select(exists(this_match))
+            .get_result(&conn)
+            .map_err(RepoError::backend)?;
+        if matched {
+            Err(RepoError::Conflict("extension"))
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expire(&self, inactive_since: NaiveDateTime) -> RepoResult<usize> {
+        diesel::sql_query(
+            "WITH expired AS ( \
+                 DELETE FROM matches WHERE last_activity_at < $1 \
+                 RETURNING username1, username2, created_at, last_activity_at, extended_by \
+             ) \
+             INSERT INTO match_history \
+                 (username1, username2, created_at, last_activity_at, extended_by, expired_at) \
+             SELECT *, $2 FROM expired",
+        )
+        .bind::<Timestamp, _>(inactive_since)
+        .bind::<Timestamp, _>(chrono::Utc::now().naive_utc())
+        .execute(&self.conn()?)
+        .map_err(RepoError::backend)
+    }
+
+    fn expired_for(&self, username: &str) -> RepoResult<Vec<DBMatchHistory>> {
+        match_history::table
+            .filter(match_history::username1.eq(username))
+            .or_filter(match_history::username2.eq(username))
+            .order(match_history::expired_at.desc())
+            .load::<DBMatchHistory>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+}
+
+impl AdminRepository for PgRepository {
+    fn users(
+        &self,
+        prefix: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBUser>> {
+        let mut select = users::table
+            .order(users::username)
+            .limit(limit)
+            .into_boxed();
+        if let Some(prefix) = prefix {
+            select = select.filter(users::username.like(format!("{}%", escape_like(prefix))));
+        }
+        if let Some(after) = after {
+            select = select.filter(users::username.gt(after));
+        }
+        select
+            .load::<DBUser>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn clear_bio(&self, username: &str, audit: &Audit) -> RepoResult<bool> {
+        self.audited(username, audit, |conn| {
+            diesel::update(users::table.find(username))
+                .set(users::bio.eq(None::<String>))
+                .execute(conn)
+        })
+    }
+
+    fn clear_profile_pic(&self, username: &str, audit: &Audit) -> RepoResult<Option<DBUser>> {
+        let conn = self.conn()?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let user = match users::table
+                .find(username)
+                .first::<DBUser>(&conn)
+                .optional()?
+            {
+                Some(user) => user,
+                None => return Ok(None),
+            };
+            diesel::update(users::table.find(username))
+                .set(users::profile_pic.eq(None::<String>))
+                .execute(&conn)?;
+            record_action(&conn, username, audit)?;
+            Ok(Some(user))
+        })
+        .map_err(RepoError::backend)
+    }
+
+    fn suspend(&self, username: &str, until: NaiveDateTime, audit: &Audit) -> RepoResult<bool> {
+        self.audited(username, audit, |conn| {
+            diesel::update(users::table.find(username))
+                .set(users::suspended_until.eq(until))
+                .execute(conn)
+        })
+    }
+
+    fn ban(&self, username: &str, audit: &Audit) -> RepoResult<bool> {
+        self.audited(username, audit, |conn| {
+            diesel::update(users::table.find(username))
+                .set(users::banned.eq(true))
+                .execute(conn)
+        })
+    }
+
+    fn reinstate(&self, username: &str, audit: &Audit) -> RepoResult<bool> {
+        self.audited(username, audit, |conn| {
+            diesel::update(users::table.find(username))
+                .set((
+                    users::banned.eq(false),
+                    users::suspended_until.eq(None::<NaiveDateTime>),
+                ))
+                .execute(conn)
+        })
+    }
+
+    fn delete_match(&self, username1: &str, username2: &str, audit: &Audit) -> RepoResult<bool> {
+        let pair = ordered_pair(username1, username2);
+        let target = format!("{}/{}", pair.username1, pair.username2);
+        self.audited(&target, audit, |conn| {
+            diesel::delete(
+                matches::table
+                    .filter(matches::username1.eq(&pair.username1))
+                    .filter(matches::username2.eq(&pair.username2)),
+            )
+            .execute(conn)
+        })
+    }
+
+    fn record(&self, target: &str, audit: &Audit) -> RepoResult<()> {
+        record_action(&*self.conn()?, target, audit).map_err(RepoError::backend)
+    }
+
+    fn actions(
+        &self,
+        who: Option<&str>,
+        before: Option<i32>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBAdminAction>> {
+        let mut select = admin_actions::table
+            .order(admin_actions::id.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(who) = who {
+            select = select.filter(
+                admin_actions::target
+                    .eq(who)
+                    .or(admin_actions::admin.eq(who)),
+            );
+        }
+        if let Some(before) = before {
+            select = select.filter(admin_actions::id.lt(before));
+        }
+        select
+            .load::<DBAdminAction>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+}
+
+impl WebhookRepository for PgRepository {
+    fn register(
+        &self,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        audit: &Audit,
+    ) -> RepoResult<DBWebhook> {
+        let conn = self.conn()?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let registered = diesel::insert_into(webhooks::table)
+                .values(NewWebhook {
+                    url,
+                    secret,
+                    events: events.iter().map(|e| e.as_str().to_string()).collect(),
+                })
+                .get_result::<DBWebhook>(&conn)?;
+            record_action(&conn, &registered.id.to_string(), audit)?;
+            Ok(registered)
+        })
+        .map_err(RepoError::backend)
+    }
+
+    fn unregister(&self, id: i32, audit: &Audit) -> RepoResult<bool> {
+        self.audited(&id.to_string(), audit, |conn| {
+            diesel::delete(webhooks::table.find(id)).execute(conn)
+        })
+    }
+
+    fn webhooks(&self) -> RepoResult<Vec<DBWebhook>> {
+        webhooks::table
+            .order(webhooks::id)
+            .load::<DBWebhook>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn webhook(&self, id: i32) -> RepoResult<Option<DBWebhook>> {
+        webhooks::table
+            .find(id)
+            .first::<DBWebhook>(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn add_member(&self, webhook_id: i32, username: &str, audit: &Audit) -> RepoResult<bool> {
+        let conn = self.conn()?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let webhook_found: bool =
+                diesel::select(exists(webhooks::table.find(webhook_id))).get_result(&conn)?;
+            let user_found: bool =
+                diesel::select(exists(users::table.find(username))).get_result(&conn)?;
+            if !webhook_found || !user_found {
+                return Ok(false);
+            }
+            let added = diesel::insert_into(webhook_members::table)
+                .values(NewWebhookMember {
+                    webhook_id,
+                    username,
+                })
+                .on_conflict_do_nothing()
+                .execute(&conn)?;
+            if added > 0 {
+                record_action(&conn, &webhook_id.to_string(), audit)?;
+            }
+            Ok(true)
+        })
+        .map_err(RepoError::backend)
+    }
+
+    fn remove_member(&self, webhook_id: i32, username: &str, audit: &Audit) -> RepoResult<bool> {
+        self.audited(&webhook_id.to_string(), audit, |conn| {
+            diesel::delete(webhook_members::table.find((webhook_id, username))).execute(conn)
+        })
+    }
+
+    fn members(&self, webhook_id: i32) -> RepoResult<Vec<DBWebhookMember>> {
+        webhook_members::table
+            .filter(webhook_members::webhook_id.eq(webhook_id))
+            .order(webhook_members::username)
+            .load::<DBWebhookMember>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn subscribed_to(
+        &self,
+        event: WebhookEvent,
+        usernames: &[String],
+    ) -> RepoResult<Vec<DBWebhook>> {
+        webhooks::table
+            .filter(webhooks::events.contains(vec![event.as_str()]))
+            .filter(exists(
+                webhook_members::table
+                    .filter(webhook_members::webhook_id.eq(webhooks::id))
+                    .filter(webhook_members::username.eq_any(usernames)),
+            ))
+            .order(webhooks::id)
+            .load::<DBWebhook>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+
+    fn add_delivery(
+        &self,
+        job_id: i64,
+        webhook_id: i32,
+        event: WebhookEvent,
+        data: &Value,
+    ) -> RepoResult<Option<i64>> {
+        diesel::insert_into(webhook_deliveries::table)
+            .values(NewWebhookDelivery {
+                webhook_id,
+                event: event.as_str(),
+                payload: data,
+                job_id,
+            })
+            .on_conflict((webhook_deliveries::job_id, webhook_deliveries::webhook_id))
+            .do_nothing()
+            .returning(webhook_deliveries::id)
+            .get_result(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn remove_delivery(&self, id: i64) -> RepoResult<()> {
+        diesel::delete(webhook_deliveries::table.find(id))
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(RepoError::backend)
+    }
+
+    fn delivery(&self, id: i64) -> RepoResult<Option<DBWebhookDelivery>> {
+        webhook_deliveries::table
+            .find(id)
+            .first::<DBWebhookDelivery>(&self.conn()?)
+            .optional()
+            .map_err(RepoError::backend)
+    }
+
+    fn record_attempt(
+        &self,
+        id: i64,
+        status: &str,
+        response_status: Option<i32>,
+        error: Option<&str>,
+    ) -> RepoResult<()> {
+        let delivered_at = if status == DELIVERED {
+            Some(chrono::Utc::now().naive_utc())
+        } else {
+            None
+        };
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(status),
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::response_status.eq(response_status),
+                webhook_deliveries::last_error.eq(error),
+                webhook_deliveries::delivered_at.eq(delivered_at),
+            ))
+            .execute(&self.conn()?)
+            .map(|_| ())
+            .map_err(RepoError::backend)
+    }
+
+    fn deliveries(
+        &self,
+        webhook_id: i32,
+        before: Option<i64>,
+        limit: i64,
+    ) -> RepoResult<Vec<DBWebhookDelivery>> {
+        let mut select = webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+            .order(webhook_deliveries::id.desc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(before) = before {
+            select = select.filter(webhook_deliveries::id.lt(before));
+        }
+        select
+            .load::<DBWebhookDelivery>(&self.conn()?)
+            .map_err(RepoError::backend)
+    }
+}
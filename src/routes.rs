@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use actix_web::{guard, web};
+use diesel::r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
+use web::{get, post, scope};
+
+use crate::auth::{AdminChecker, SessionChecker};
+use crate::paths::{admin, email, matches, password, sessions, swipe, tokens, users};
+use crate::repo::UserRepository;
+use crate::sessions::SessionStore;
+use crate::tokens::TokenIssuer;
+
+/// The current version of the API, which `configure` is mounted under.
+pub const API_PREFIX: &str = "/api/v1";
+
+/// Registers every API route. `main.rs` mounts these under `API_PREFIX`, and
+/// again at the root for clients that haven't moved to the versioned paths.
+/// The arguments are what `SessionChecker` needs; handlers take everything
+/// else from the app data.
+pub fn configure(
+    cfg: &mut web::ServiceConfig,
+    user_repo: &Arc<dyn UserRepository>,
+    session_store: &Arc<dyn SessionStore>,
+    rd_pool: &Arc<Pool<RedisConnectionManager>>,
+    token_issuer: &Arc<TokenIssuer>,
+) {
+    let session_checker = || {
+        SessionChecker::new(
+            Arc::clone(user_repo),
+            Arc::clone(session_store),
+            Arc::clone(rd_pool),
+            Arc::clone(token_issuer),
+        )
+    };
+
+    cfg.service(
+        scope("/user")
+            .service(
+                web::resource("")
+                    .guard(guard::Get())
+                    .wrap(session_checker())
+                    .to(users::search_users),
+            )
+            .route("", post().to(users::create_user))
+            .route("/u/{username}", get().to(users::get_user_pic))
+            .route("/login", post().to(users::login))
+            .route("/logout", get().to(users::logout))
+            .route("/verify", get().to(email::verify_email))
+            .route(
+                "/password-reset/request",
+                post().to(password::request_reset),
+            )
+            .route(
+                "/password-reset/confirm",
+                post().to(password::confirm_reset),
+            )
+            .route("/token", post().to(tokens::issue_token))
+            .route("/token/refresh", post().to(tokens::refresh_token))
+            .route("/token/revoke", post().to(tokens::revoke_token))
+            .service(
+                scope("/manage")
+                    .wrap(session_checker())
+                    .route("/li", get().to(users::check_login))
+                    .route("/location", post().to(users::set_location))
+                    .route("/bio", post().to(users::set_bio))
+                    .route("/discipline", post().to(users::set_discipline))
+                    .route("/email", post().to(email::set_email))
+                    .route("/email/resend", post().to(email::resend_verification))
+                    .route("/profile_pic", post().to(users::upload_profile_pic))
+                    .route("/sessions", get().to(sessions::list_sessions))
+                    .route("/sessions", web::delete().to(sessions::revoke_all_sessions))
+                    .route("/sessions/{id}", web::delete().to(sessions::revoke_session)),
+            ),
+    )
+    .service(
+        scope("/swipe")
+            .wrap(session_checker())
+            .route("", post().to(swipe::do_swipe))
+            .route("/available", get().to(swipe::available))
+            .route("/likes-received", get().to(swipe::likes_received)),
+    )
+    .service(
+        scope("/match")
+            .wrap(session_checker())
+            .route("", get().to(matches::matches))
+            .route("/expired", get().to(matches::expired_matches))
+            .route("/{username}", get().to(matches::get_match))
+            .route("/{username}", web::delete().to(matches::delete_match))
+            .route("/{username}/extend", post().to(matches::extend_match)),
+    )
+    .service(
+        scope("/admin")
+            .wrap(AdminChecker)
+            .wrap(session_checker())
+            .route("/users", get().to(admin::search_users))
+            .route("/users/{username}", get().to(admin::get_user))
+            .route("/users/{username}/bio", web::delete().to(admin::clear_bio))
+            .route(
+                "/users/{username}/profile_pic",
+                web::delete().to(admin::clear_profile_pic),
+            )
+            .route("/users/{username}/suspend", post().to(admin::suspend_user))
+            .route("/users/{username}/ban", post().to(admin::ban_user))
+            .route(
+                "/users/{username}/reinstate",
+                post().to(admin::reinstate_user),
+            )
+            .route(
+                "/matches/{username1}/{username2}",
+                web::delete().to(admin::delete_match),
+            )
+            .route("/audit", get().to(admin::audit_log))
+            .route("/jobs", get().to(admin::list_jobs))
+            .route("/jobs/{id}", get().to(admin::get_job))
+            .route("/jobs/{id}/retry", post().to(admin::retry_job))
+            .route("/webhooks", get().to(admin::list_webhooks))
+            .route("/webhooks", post().to(admin::register_webhook))
+            .route(
+                "/webhooks/{id}",
+                web::delete().to(admin::unregister_webhook),
+            )
+            .route(
+                "/webhooks/{id}/deliveries",
+                get().to(admin::webhook_deliveries),
+            )
+            .route("/webhooks/{id}/members", get().to(admin::webhook_members))
+            .route(
+                "/webhooks/{id}/members",
+                post().to(admin::add_webhook_member),
+            )
+            .route(
+                "/webhooks/{id}/members/{username}",
+                web::delete().to(admin::remove_webhook_member),
+            ),
+    );
+}
@@ -0,0 +1,68 @@
+//! Runs periodic jobs on background threads inside the server process. Jobs
+//! run once at startup, to catch up on anything missed while the server was
+//! down, and then every period after that.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, info_span};
+
+struct Job {
+    name: &'static str,
+    every: Duration,
+    run: Box<dyn FnMut() -> Result<(), String> + Send>,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a job to run every `every`. Runs of the same job never overlap,
+    /// and a failed run is logged and then retried at the next period.
+    pub fn every(
+        mut self,
+        name: &'static str,
+        every: Duration,
+        run: impl FnMut() -> Result<(), String> + Send + 'static,
+    ) -> Self {
+        self.jobs.push(Job {
+            name,
+            every,
+            run: Box::new(run),
+        });
+        self
+    }
+
+    /// Starts a thread for each job. They run until the process exits.
+    pub fn start(self) {
+        for mut job in self.jobs {
+            info!(
+                job = job.name,
+                every_secs = job.every.as_secs(),
+                "scheduling job"
+            );
+            thread::Builder::new()
+                .name(format!("job-{}", job.name))
+                .spawn(move || loop {
+                    let started = Instant::now();
+                    let result = info_span!("job", job = job.name).in_scope(|| (job.run)());
+                    if let Err(err) = result {
+                        error!(
+                            job = job.name,
+                            error = %err,
+                            elapsed_ms = started.elapsed().as_millis() as u64,
+                            "job failed"
+                        );
+                    }
+                    thread::sleep(job.every);
+                })
+                .expect("unable to start job thread");
+        }
+    }
+}
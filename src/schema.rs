@@ -1,15 +1,78 @@
+table! {
+    admin_actions (id) {
+        id -> Int4,
+        admin -> Varchar,
+        action -> Varchar,
+        target -> Varchar,
+        details -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    email_verifications (token_hash) {
+        token_hash -> Varchar,
+        username -> Varchar,
+        email -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    jobs (id) {
+        id -> Int8,
+        kind -> Varchar,
+        payload -> Jsonb,
+        state -> Varchar,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_at -> Timestamp,
+        locked_at -> Nullable<Timestamp>,
+        last_error -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    match_history (id) {
+        id -> Int4,
+        username1 -> Varchar,
+        username2 -> Varchar,
+        created_at -> Timestamp,
+        last_activity_at -> Timestamp,
+        extended_by -> Nullable<Varchar>,
+        expired_at -> Timestamp,
+    }
+}
+
 table! {
     matches (username1, username2) {
         username1 -> Varchar,
         username2 -> Varchar,
+        created_at -> Timestamp,
+        last_activity_at -> Timestamp,
+        extended_by -> Nullable<Varchar>,
     }
 }
 
 table! {
+    password_resets (token_hash) {
+        token_hash -> Varchar,
+        username -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use crate::db::SwipeKindType;
+
     swipes (swiper, swiped) {
         swiper -> Varchar,
         swiped -> Varchar,
-        status -> Bool,
+        kind -> SwipeKindType,
     }
 }
 
@@ -21,7 +84,67 @@ table! {
         long -> Nullable<Float8>,
         bio -> Nullable<Varchar>,
         profile_pic -> Nullable<Varchar>,
+        role -> Varchar,
+        suspended_until -> Nullable<Timestamp>,
+        banned -> Bool,
+        discipline -> Nullable<Varchar>,
+        email -> Nullable<Varchar>,
+        email_verified -> Bool,
+        last_active_at -> Nullable<Timestamp>,
+        geohash -> Nullable<Varchar>,
     }
 }
 
-allow_tables_to_appear_in_same_query!(matches, swipes, users,);
+table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Int4,
+        event -> Varchar,
+        payload -> Jsonb,
+        status -> Varchar,
+        attempts -> Int4,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+        job_id -> Nullable<Int8>,
+    }
+}
+
+table! {
+    webhook_members (webhook_id, username) {
+        webhook_id -> Int4,
+        username -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Varchar,
+        secret -> Varchar,
+        events -> Array<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+joinable!(email_verifications -> users (username));
+joinable!(password_resets -> users (username));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+joinable!(webhook_members -> users (username));
+joinable!(webhook_members -> webhooks (webhook_id));
+
+allow_tables_to_appear_in_same_query!(
+    admin_actions,
+    email_verifications,
+    jobs,
+    match_history,
+    matches,
+    password_resets,
+    swipes,
+    users,
+    webhook_deliveries,
+    webhook_members,
+    webhooks,
+);
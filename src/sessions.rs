@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{ops::DerefMut, time::Duration};
+
+use diesel::r2d2::Pool;
+use r2d2_redis::redis::{self, Connection, RedisResult};
+use r2d2_redis::RedisConnectionManager;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::repo::{RepoError, RepoResult};
+use crate::tokens::{hash_token, now, random_token};
+
+/// How long a session survives without being used.
+pub const SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How stale `last_seen` is allowed to get before a request updates it.
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A logged in device. The cookie holds the session secret; everywhere else
+/// the session is referred to by `id`, a hash of that secret, so listing
+/// sessions doesn't hand out anything that could be used to hijack them.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SessionRecord {
+    pub id: String,
+    pub username: String,
+    pub created_at: u64,
+    pub last_seen: u64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// The id of the session a request was authenticated with, stored in the
+/// request extensions by `SessionChecker` for cookie authenticated requests.
+pub struct CurrentSession(pub String);
+
+/// Where logged in devices are kept track of.
+pub trait SessionStore: Send + Sync {
+    /// Starts a new session for a user, returning the secret to put in the
+    /// cookie.
+    fn create(
+        &self,
+        username: &str,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> RepoResult<String>;
+
+    /// Looks up the live session for a cookie secret, extending its lifetime.
+    fn resume(&self, secret: &str) -> RepoResult<Option<SessionRecord>>;
+
+    /// Lists a user's live sessions, most recently used first.
+    fn list(&self, username: &str) -> RepoResult<Vec<SessionRecord>>;
+
+    /// Ends one of a user's sessions, returning whether it existed.
+    fn revoke(&self, username: &str, id: &str) -> RepoResult<bool>;
+
+    /// Ends the session a cookie secret belongs to.
+    fn revoke_secret(&self, secret: &str) -> RepoResult<()>;
+
+    /// Ends every session a user has.
+    fn revoke_all(&self, username: &str) -> RepoResult<()>;
+}
+
+fn new_record(
+    username: &str,
+    user_agent: Option<String>,
+    ip: Option<String>,
+) -> (String, SessionRecord) {
+    let secret = random_token();
+    let now = now();
+    let record = SessionRecord {
+        id: session_id(&secret),
+        username: username.to_string(),
+        created_at: now,
+        last_seen: now,
+        user_agent,
+        ip,
+    };
+    (secret, record)
+}
+
+/// Sessions kept in redis, expiring `SESSION_TTL` after they were last used.
+pub struct RedisSessionStore {
+    pool: Arc<Pool<RedisConnectionManager>>,
+}
+
+impl RedisSessionStore {
+    pub fn new(pool: Arc<Pool<RedisConnectionManager>>) -> Self {
+        RedisSessionStore { pool }
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&mut Connection) -> RedisResult<T>) -> RepoResult<T> {
+        let mut conn = self
+            .pool
+            .get_timeout(Duration::from_millis(500))
+            .map_err(RepoError::backend)?;
+        f(conn.deref_mut()).map_err(RepoError::backend)
+    }
+}
+
+impl SessionStore for RedisSessionStore {
+    fn create(
+        &self,
+        username: &str,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> RepoResult<String> {
+        self.with_conn(|conn| create(conn, username, user_agent, ip))
+    }
+
+    fn resume(&self, secret: &str) -> RepoResult<Option<SessionRecord>> {
+        self.with_conn(|conn| resume(conn, secret))
+    }
+
+    fn list(&self, username: &str) -> RepoResult<Vec<SessionRecord>> {
+        self.with_conn(|conn| list(conn, username))
+    }
+
+    fn revoke(&self, username: &str, id: &str) -> RepoResult<bool> {
+        self.with_conn(|conn| revoke(conn, username, id))
+    }
+
+    fn revoke_secret(&self, secret: &str) -> RepoResult<()> {
+        self.with_conn(|conn| revoke_secret(conn, secret))
+    }
+
+    fn revoke_all(&self, username: &str) -> RepoResult<()> {
+        self.with_conn(|conn| revoke_all(conn, username))
+    }
+}
+
+/// Sessions kept in memory, for tests.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks the sessions, dropping any that have gone unused for too long.
+    fn live(&self) -> MutexGuard<'_, HashMap<String, SessionRecord>> {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        let now = now();
+        sessions.retain(|_, record| now.saturating_sub(record.last_seen) < SESSION_TTL.as_secs());
+        sessions
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn create(
+        &self,
+        username: &str,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> RepoResult<String> {
+        let (secret, record) = new_record(username, user_agent, ip);
+        self.live().insert(record.id.clone(), record);
+        Ok(secret)
+    }
+
+    fn resume(&self, secret: &str) -> RepoResult<Option<SessionRecord>> {
+        let mut sessions = self.live();
+        Ok(sessions.get_mut(&session_id(secret)).map(|record| {
+            record.last_seen = now();
+            record.clone()
+        }))
+    }
+
+    fn list(&self, username: &str) -> RepoResult<Vec<SessionRecord>> {
+        let mut records: Vec<SessionRecord> = self
+            .live()
+            .values()
+            .filter(|record| record.username == username)
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.last_seen));
+        Ok(records)
+    }
+
+    fn revoke(&self, username: &str, id: &str) -> RepoResult<bool> {
+        let mut sessions = self.live();
+        match sessions.get(id) {
+            Some(record) if record.username == username => {
+                sessions.remove(id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn revoke_secret(&self, secret: &str) -> RepoResult<()> {
+        self.live().remove(&session_id(secret));
+        Ok(())
+    }
+
+    fn revoke_all(&self, username: &str) -> RepoResult<()> {
+        self.live().retain(|_, record| record.username != username);
+        Ok(())
+    }
+}
+
+fn session_id(secret: &str) -> String {
+    hash_token(secret)
+}
+
+fn session_key(id: &str) -> String {
+    format!("session:{}", id)
+}
+
+fn user_sessions_key(username: &str) -> String {
+    format!("user_sessions:{}", username)
+}
+
+fn store(conn: &mut Connection, record: &SessionRecord) -> RedisResult<()> {
+    let as_string = serde_json::to_string(record).expect("failed to jsonify SessionRecord");
+    redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(session_key(&record.id))
+        .arg(as_string)
+        .arg("EX")
+        .arg(SESSION_TTL.as_secs())
+        .ignore()
+        .cmd("SADD")
+        .arg(user_sessions_key(&record.username))
+        .arg(&record.id)
+        .ignore()
+        .cmd("EXPIRE")
+        .arg(user_sessions_key(&record.username))
+        .arg(SESSION_TTL.as_secs())
+        .ignore()
+        .query(conn)
+}
+
+fn load(conn: &mut Connection, id: &str) -> RedisResult<Option<SessionRecord>> {
+    let raw: Option<String> = redis::cmd("GET").arg(session_key(id)).query(conn)?;
+    Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+}
+
+fn create(
+    conn: &mut Connection,
+    username: &str,
+    user_agent: Option<String>,
+    ip: Option<String>,
+) -> RedisResult<String> {
+    let (secret, record) = new_record(username, user_agent, ip);
+    store(conn, &record)?;
+    Ok(secret)
+}
+
+fn resume(conn: &mut Connection, secret: &str) -> RedisResult<Option<SessionRecord>> {
+    let mut record = match load(conn, &session_id(secret))? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let now = now();
+    if now.saturating_sub(record.last_seen) >= TOUCH_INTERVAL.as_secs() {
+        record.last_seen = now;
+        store(conn, &record)?;
+    }
+
+    Ok(Some(record))
+}
+
+fn list(conn: &mut Connection, username: &str) -> RedisResult<Vec<SessionRecord>> {
+    let ids: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(user_sessions_key(username))
+        .query(conn)?;
+
+    let mut records = Vec::with_capacity(ids.len());
+    for id in ids {
+        match load(conn, &id)? {
+            Some(record) => records.push(record),
+            None => redis::cmd("SREM")
+                .arg(user_sessions_key(username))
+                .arg(&id)
+                .query(conn)?,
+        }
+    }
+    records.sort_by_key(|record| std::cmp::Reverse(record.last_seen));
+
+    Ok(records)
+}
+
+fn revoke(conn: &mut Connection, username: &str, id: &str) -> RedisResult<bool> {
+    let removed: u32 = redis::cmd("SREM")
+        .arg(user_sessions_key(username))
+        .arg(id)
+        .query(conn)?;
+    if removed == 0 {
+        return Ok(false);
+    }
+    redis::cmd("DEL").arg(session_key(id)).query::<()>(conn)?;
+    Ok(true)
+}
+
+fn revoke_secret(conn: &mut Connection, secret: &str) -> RedisResult<()> {
+    let id = session_id(secret);
+    if let Some(record) = load(conn, &id)? {
+        revoke(conn, &record.username, &id)?;
+    }
+    Ok(())
+}
+
+fn revoke_all(conn: &mut Connection, username: &str) -> RedisResult<()> {
+    let ids: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(user_sessions_key(username))
+        .query(conn)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for id in ids {
+        pipe.cmd("DEL").arg(session_key(&id)).ignore();
+    }
+    pipe.cmd("DEL").arg(user_sessions_key(username)).ignore();
+    pipe.query(conn)
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use diesel::r2d2::Pool;
+use r2d2_redis::redis::{self, Connection, RedisResult};
+use r2d2_redis::RedisConnectionManager;
+use tracing::{info, warn};
+
+use crate::repo::{RepoError, RepoResult};
+
+/// How long a failure keeps counting towards a lockout after it happens.
+const FAILURE_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Delay after the first failure, doubled for every failure after it.
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay before a subject reaches its failure limit, kept well
+/// under `LOCKOUT` so the limit is what locks it out.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long a subject is locked out once it reaches its failure limit.
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+struct Subject {
+    kind: &'static str,
+    max_failures: u32,
+}
+
+const USERNAME: Subject = Subject {
+    kind: "user",
+    max_failures: 5,
+};
+
+// a single address can be shared by many legitimate users behind a NAT, so
+// it's allowed more failures than a single username before being locked
+const IP: Subject = Subject {
+    kind: "ip",
+    max_failures: 20,
+};
+
+impl Subject {
+    fn failures_key(&self, id: &str) -> String {
+        format!("login:failures:{}:{}", self.kind, id)
+    }
+
+    fn lock_key(&self, id: &str) -> String {
+        format!("login:lock:{}:{}", self.kind, id)
+    }
+
+    fn retry_after(&self, conn: &mut Connection, id: &str) -> RedisResult<Option<Duration>> {
+        let remaining: i64 = redis::cmd("PTTL").arg(self.lock_key(id)).query(conn)?;
+        if remaining > 0 {
+            return Ok(Some(Duration::from_millis(remaining as u64)));
+        }
+
+        let failures: Option<u32> = redis::cmd("GET").arg(self.failures_key(id)).query(conn)?;
+        if failures.unwrap_or(0) >= self.max_failures {
+            info!(kind = self.kind, %id, "login lockout expired");
+            redis::cmd("DEL")
+                .arg(self.failures_key(id))
+                .query::<()>(conn)?;
+        }
+
+        Ok(None)
+    }
+
+    fn record_failure(&self, conn: &mut Connection, id: &str) -> RedisResult<()> {
+        let key = self.failures_key(id);
+        let (failures,): (u32,) = redis::pipe()
+            .atomic()
+            .cmd("INCR")
+            .arg(&key)
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(FAILURE_WINDOW.as_secs())
+            .ignore()
+            .query(conn)?;
+
+        let wait = self.wait_after(id, failures);
+        redis::cmd("SET")
+            .arg(self.lock_key(id))
+            .arg(1)
+            .arg("PX")
+            .arg(wait.as_millis() as u64)
+            .query(conn)
+    }
+
+    /// How long to lock `id` out for after its `failures`th failure in a row.
+    fn wait_after(&self, id: &str, failures: u32) -> Duration {
+        if failures >= self.max_failures {
+            warn!(
+                kind = self.kind,
+                %id,
+                lockout_secs = LOCKOUT.as_secs(),
+                failures,
+                "locking out after too many failed logins"
+            );
+            LOCKOUT
+        } else {
+            backoff(failures)
+        }
+    }
+
+    fn clear(&self, conn: &mut Connection, id: &str) -> RedisResult<()> {
+        redis::cmd("DEL")
+            .arg(self.failures_key(id))
+            .arg(self.lock_key(id))
+            .query(conn)
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    (BASE_BACKOFF * 2u32.saturating_pow(failures.saturating_sub(1).min(16))).min(MAX_BACKOFF)
+}
+
+/// Slows down password guessing by locking out usernames and addresses with
+/// a growing delay after each failed login.
+pub trait LoginThrottle: Send + Sync {
+    /// Returns how long the caller has to wait before another login attempt
+    /// for this username from this address will be considered, if at all.
+    fn check(&self, username: &str, ip: &str) -> RepoResult<Option<Duration>>;
+
+    fn record_failure(&self, username: &str, ip: &str) -> RepoResult<()>;
+
+    /// Clears the failure history of a username after it logs in. The address
+    /// is left alone so one known account can't be used to reset its counter.
+    fn record_success(&self, username: &str) -> RepoResult<()>;
+}
+
+/// Login failures counted in redis, so that every instance sees them.
+pub struct RedisThrottle {
+    pool: Arc<Pool<RedisConnectionManager>>,
+}
+
+impl RedisThrottle {
+    pub fn new(pool: Arc<Pool<RedisConnectionManager>>) -> Self {
+        RedisThrottle { pool }
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&mut Connection) -> RedisResult<T>) -> RepoResult<T> {
+        let mut conn = self
+            .pool
+            .get_timeout(Duration::from_millis(500))
+            .map_err(RepoError::backend)?;
+        f(conn.deref_mut()).map_err(RepoError::backend)
+    }
+}
+
+impl LoginThrottle for RedisThrottle {
+    fn check(&self, username: &str, ip: &str) -> RepoResult<Option<Duration>> {
+        self.with_conn(|conn| {
+            let by_user = USERNAME.retry_after(conn, username)?;
+            let by_ip = IP.retry_after(conn, ip)?;
+            Ok(by_user.max(by_ip))
+        })
+    }
+
+    fn record_failure(&self, username: &str, ip: &str) -> RepoResult<()> {
+        self.with_conn(|conn| {
+            USERNAME.record_failure(conn, username)?;
+            IP.record_failure(conn, ip)
+        })
+    }
+
+    fn record_success(&self, username: &str) -> RepoResult<()> {
+        self.with_conn(|conn| USERNAME.clear(conn, username))
+    }
+}
+
+struct Failures {
+    count: u32,
+    forget_at: Instant,
+    locked_until: Instant,
+}
+
+/// Login failures counted in memory, for tests.
+#[derive(Default)]
+pub struct MemoryThrottle {
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl MemoryThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn retry_after(&self, subject: &Subject, id: &str) -> Option<Duration> {
+        let mut failures = self.failures.lock().expect("throttle lock poisoned");
+        let now = Instant::now();
+        let entry = failures.get_mut(&subject.failures_key(id))?;
+        if entry.locked_until > now {
+            return Some(entry.locked_until - now);
+        }
+        if entry.count >= subject.max_failures || entry.forget_at <= now {
+            failures.remove(&subject.failures_key(id));
+        }
+        None
+    }
+
+    fn record(&self, subject: &Subject, id: &str) {
+        let mut failures = self.failures.lock().expect("throttle lock poisoned");
+        let now = Instant::now();
+        let entry = failures
+            .entry(subject.failures_key(id))
+            .or_insert(Failures {
+                count: 0,
+                forget_at: now,
+                locked_until: now,
+            });
+        if entry.forget_at <= now {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.forget_at = now + FAILURE_WINDOW;
+        entry.locked_until = now + subject.wait_after(id, entry.count);
+    }
+}
+
+impl LoginThrottle for MemoryThrottle {
+    fn check(&self, username: &str, ip: &str) -> RepoResult<Option<Duration>> {
+        let by_user = self.retry_after(&USERNAME, username);
+        let by_ip = self.retry_after(&IP, ip);
+        Ok(by_user.max(by_ip))
+    }
+
+    fn record_failure(&self, username: &str, ip: &str) -> RepoResult<()> {
+        self.record(&USERNAME, username);
+        self.record(&IP, ip);
+        Ok(())
+    }
+
+    fn record_success(&self, username: &str) -> RepoResult<()> {
+        self.failures
+            .lock()
+            .expect("throttle lock poisoned")
+            .remove(&USERNAME.failures_key(username));
+        Ok(())
+    }
+}
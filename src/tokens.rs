@@ -0,0 +1,171 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{errors::Error, DecodingKey, EncodingKey, Header, Validation};
+use r2d2_redis::redis::{self, Connection, RedisResult};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const ACCESS_TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+pub const REFRESH_TOKEN_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Claims {
+    pub sub: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+}
+
+/// Signs and verifies the short lived access tokens handed to clients that
+/// can't hold on to a session cookie.
+pub struct TokenIssuer {
+    encoding: EncodingKey,
+    decoding: DecodingKey<'static>,
+}
+
+impl TokenIssuer {
+    pub fn new(secret: &[u8]) -> Self {
+        TokenIssuer {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret).into_static(),
+        }
+    }
+
+    pub fn issue(&self, username: &str) -> Result<String, Error> {
+        let iat = now();
+        let claims = Claims {
+            sub: username.to_string(),
+            iat,
+            exp: iat + ACCESS_TOKEN_TTL.as_secs(),
+            jti: random_token(),
+        };
+        jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, Error> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::default())
+            .map(|data| data.claims)
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_secs()
+}
+
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes a secret token for storage, so that whoever can read the store
+/// can't replay what they find there.
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn refresh_key(token: &str) -> String {
+    format!("refresh:{}", hash_token(token))
+}
+
+fn user_refresh_key(username: &str) -> String {
+    format!("refresh_tokens:{}", username)
+}
+
+fn revoked_key(jti: &str) -> String {
+    format!("revoked:{}", jti)
+}
+
+pub fn issue_refresh(conn: &mut Connection, username: &str) -> RedisResult<String> {
+    let token = random_token();
+    let key = refresh_key(&token);
+    redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(&key)
+        .arg(username)
+        .arg("EX")
+        .arg(REFRESH_TOKEN_TTL.as_secs())
+        .ignore()
+        .cmd("SADD")
+        .arg(user_refresh_key(username))
+        .arg(&key)
+        .ignore()
+        .cmd("EXPIRE")
+        .arg(user_refresh_key(username))
+        .arg(REFRESH_TOKEN_TTL.as_secs())
+        .ignore()
+        .query::<()>(conn)?;
+    Ok(token)
+}
+
+/// Consumes a refresh token, returning the user it was issued to if it was
+/// still valid. Each refresh token can only be used once.
+pub fn take_refresh(conn: &mut Connection, token: &str) -> RedisResult<Option<String>> {
+    let key = refresh_key(token);
+    let (username,): (Option<String>,) = redis::pipe()
+        .atomic()
+        .cmd("GET")
+        .arg(&key)
+        .cmd("DEL")
+        .arg(&key)
+        .ignore()
+        .query(conn)?;
+    if let Some(username) = &username {
+        redis::cmd("SREM")
+            .arg(user_refresh_key(username))
+            .arg(&key)
+            .query::<()>(conn)?;
+    }
+    Ok(username)
+}
+
+fn not_before_key(username: &str) -> String {
+    format!("tokens_not_before:{}", username)
+}
+
+/// Revokes every refresh token issued to a user, along with every access
+/// token issued to them up to now.
+pub fn revoke_all(conn: &mut Connection, username: &str) -> RedisResult<()> {
+    let keys: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(user_refresh_key(username))
+        .query(conn)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for key in keys {
+        pipe.cmd("DEL").arg(key).ignore();
+    }
+    pipe.cmd("DEL").arg(user_refresh_key(username)).ignore();
+    pipe.cmd("SET")
+        .arg(not_before_key(username))
+        .arg(now())
+        .arg("EX")
+        .arg(ACCESS_TOKEN_TTL.as_secs())
+        .ignore();
+    pipe.query(conn)
+}
+
+/// Rejects an access token for the rest of its lifetime.
+pub fn revoke_access(conn: &mut Connection, claims: &Claims) -> RedisResult<()> {
+    let ttl = claims.exp.saturating_sub(now()).max(1);
+    redis::cmd("SET")
+        .arg(revoked_key(&claims.jti))
+        .arg(1)
+        .arg("EX")
+        .arg(ttl)
+        .query(conn)
+}
+
+pub fn is_revoked(conn: &mut Connection, claims: &Claims) -> RedisResult<bool> {
+    let (revoked, not_before): (bool, Option<u64>) = redis::pipe()
+        .cmd("EXISTS")
+        .arg(revoked_key(&claims.jti))
+        .cmd("GET")
+        .arg(not_before_key(&claims.sub))
+        .query(conn)?;
+    Ok(revoked || not_before.is_some_and(|nb| claims.iat < nb))
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use unicode_normalization::UnicodeNormalization;
+
+pub const MIN_LENGTH: usize = 3;
+pub const MAX_LENGTH: usize = 32;
+
+/// Names that would be confusing or misleading as usernames, or that collide
+/// with routes under `/user` or `/match`. Compared case insensitively.
+const RESERVED: &[&str] = &[
+    "admin",
+    "administrator",
+    "api",
+    "expired",
+    "fightingtinder",
+    "login",
+    "logout",
+    "manage",
+    "me",
+    "moderator",
+    "null",
+    "password-reset",
+    "root",
+    "staff",
+    "support",
+    "system",
+    "token",
+    "undefined",
+    "user",
+    "users",
+    "verify",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum UsernameError {
+    TooShort,
+    TooLong,
+    InvalidCharacter(char),
+    InvalidStart,
+    Reserved,
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsernameError::TooShort => {
+                write!(f, "username must be at least {} characters", MIN_LENGTH)
+            }
+            UsernameError::TooLong => {
+                write!(f, "username must be at most {} characters", MAX_LENGTH)
+            }
+            UsernameError::InvalidCharacter(c) => write!(
+                f,
+                "username may only contain letters, digits, `_` and `-`, found `{}`",
+                c
+            ),
+            UsernameError::InvalidStart => write!(f, "username must start with a letter or digit"),
+            UsernameError::Reserved => write!(f, "username is reserved"),
+        }
+    }
+}
+
+/// Normalises a requested username and checks it's one we're willing to
+/// store. Usernames end up in file paths and URLs, so only ASCII letters,
+/// digits, `_` and `-` are allowed. Compatibility normalisation runs first so
+/// that lookalikes such as fullwidth letters fold to their ASCII forms.
+pub fn normalize(raw: &str) -> Result<String, UsernameError> {
+    let name: String = raw.trim().nfkc().collect();
+
+    let len = name.chars().count();
+    if len < MIN_LENGTH {
+        return Err(UsernameError::TooShort);
+    }
+    if len > MAX_LENGTH {
+        return Err(UsernameError::TooLong);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+    {
+        return Err(UsernameError::InvalidCharacter(c));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(UsernameError::InvalidStart);
+    }
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(&name)) {
+        return Err(UsernameError::Reserved);
+    }
+
+    Ok(name)
+}
@@ -0,0 +1,284 @@
+//! Tells partners' servers about events as they happen. Events are queued as
+//! jobs, so handlers never wait on someone else's server, and each delivery
+//! is retried with back-off like any other job.
+//!
+//! Every delivery is a JSON POST of `{"id", "event", "created_at", "data"}`,
+//! signed with the webhook's secret: `X-Fightingtinder-Signature` is
+//! `sha256=` followed by the hex HMAC-SHA256 of the
+//! `X-Fightingtinder-Timestamp` header, a `.` and the body.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::db::DBWebhookDelivery;
+use crate::jobs::{Job, JobContext, JobQueue};
+
+pub const PENDING: &str = "pending";
+pub const DELIVERED: &str = "delivered";
+pub const FAILED: &str = "failed";
+
+pub const SIGNATURE_HEADER: &str = "X-Fightingtinder-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Fightingtinder-Timestamp";
+pub const EVENT_HEADER: &str = "X-Fightingtinder-Event";
+/// The delivery's id, the same on every attempt, so endpoints can ignore
+/// repeats.
+pub const DELIVERY_HEADER: &str = "X-Fightingtinder-Delivery";
+
+/// How long an endpoint has to respond before the attempt counts as failed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "match.created")]
+    MatchCreated,
+    #[serde(rename = "match.deleted")]
+    MatchDeleted,
+    #[serde(rename = "swipe.like")]
+    SwipeLike,
+    /// Nothing sends this yet, since fights aren't recorded anywhere, but
+    /// endpoints can subscribe to it ahead of time.
+    #[serde(rename = "fight.result_confirmed")]
+    FightResultConfirmed,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::MatchCreated => "match.created",
+            WebhookEvent::MatchDeleted => "match.deleted",
+            WebhookEvent::SwipeLike => "swipe.like",
+            WebhookEvent::FightResultConfirmed => "fight.result_confirmed",
+        }
+    }
+}
+
+/// The signature sent with a body at `timestamp`, as unix seconds.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// What's POSTed for a delivery. It's the same on every attempt.
+pub fn body(delivery: &DBWebhookDelivery) -> String {
+    json!({
+        "id": delivery.id,
+        "event": delivery.event,
+        "created_at": delivery.created_at,
+        "data": delivery.payload,
+    })
+    .to_string()
+}
+
+/// Somewhere webhooks are POSTed to. Sending blocks, so only call it from
+/// jobs.
+pub trait WebhookSender: Send + Sync {
+    /// Returns the response's status code, or an error if there wasn't one.
+    fn post(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+        body: String,
+    ) -> Result<u16, String>;
+}
+
+/// Sends webhooks over HTTP.
+pub struct HttpSender;
+
+impl WebhookSender for HttpSender {
+    fn post(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+        body: String,
+    ) -> Result<u16, String> {
+        let url = url.to_string();
+        let headers = headers.to_vec();
+        // jobs run on plain threads, so each request gets its own runtime,
+        // which the client has to be built inside
+        actix_web::rt::System::new("webhook").block_on(async move {
+            let mut request = Client::builder()
+                .timeout(TIMEOUT)
+                .finish()
+                .post(url)
+                .content_type("application/json");
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            request
+                .send_body(body)
+                .await
+                .map(|response| response.status().as_u16())
+                .map_err(|err| err.to_string())
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SentWebhook {
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+/// Keeps every webhook in memory so tests can check what would have been
+/// sent, answering each with the same status.
+pub struct MemorySender {
+    status: u16,
+    sent: Mutex<Vec<SentWebhook>>,
+}
+
+impl MemorySender {
+    pub fn responding(status: u16) -> Self {
+        MemorySender {
+            status,
+            sent: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn sent(&self) -> Vec<SentWebhook> {
+        self.sent.lock().expect("sender lock poisoned").clone()
+    }
+}
+
+impl WebhookSender for MemorySender {
+    fn post(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+        body: String,
+    ) -> Result<u16, String> {
+        self.sent
+            .lock()
+            .expect("sender lock poisoned")
+            .push(SentWebhook {
+                url: url.to_string(),
+                headers: headers.to_vec(),
+                body,
+            });
+        Ok(self.status)
+    }
+}
+
+/// Queues `event` to be sent to every webhook subscribed to it that has one
+/// of `usernames`, the users it's about, as a member. A failure is logged
+/// rather than failing whatever caused the event.
+pub fn emit(job_queue: &dyn JobQueue, event: WebhookEvent, usernames: &[&str], data: Value) {
+    let job = Job::EmitWebhook {
+        event,
+        usernames: usernames.iter().map(|u| u.to_string()).collect(),
+        data,
+    };
+    if let Err(err) = job_queue.enqueue(&job) {
+        warn!(event = event.as_str(), error = %err, "error queueing webhook event");
+    }
+}
+
+/// The users in a match event, in the order matches are stored.
+pub(crate) fn match_usernames<'a>(username1: &'a str, username2: &'a str) -> [&'a str; 2] {
+    if username1 < username2 {
+        [username1, username2]
+    } else {
+        [username2, username1]
+    }
+}
+
+/// Records a delivery of the event for every subscribed webhook with one of
+/// `usernames` as a member, and queues a job to send each one. Deliveries are
+/// keyed on `job_id`, so when the job is retried after failing part way, the
+/// webhooks it already got to aren't sent the event twice.
+pub(crate) fn fan_out(
+    ctx: &JobContext,
+    job_id: i64,
+    event: WebhookEvent,
+    usernames: &[String],
+    data: &Value,
+) -> Result<(), String> {
+    let repo = &ctx.webhook_repo;
+    let webhooks = repo
+        .subscribed_to(event, usernames)
+        .map_err(|err| err.to_string())?;
+    for webhook in webhooks {
+        let delivery_id = match repo
+            .add_delivery(job_id, webhook.id, event, data)
+            .map_err(|err| err.to_string())?
+        {
+            Some(delivery_id) => delivery_id,
+            None => continue,
+        };
+        if let Err(err) = ctx.job_queue.enqueue(&Job::DeliverWebhook { delivery_id }) {
+            // so the retry records it again rather than skipping a delivery
+            // nothing will send
+            if let Err(err) = repo.remove_delivery(delivery_id) {
+                warn!(delivery_id, error = %err, "error removing unqueued webhook delivery");
+            }
+            return Err(err.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Makes one attempt at a delivery, failing if the endpoint didn't answer
+/// with a 2xx status. Once `last_attempt` fails the delivery is marked failed.
+pub(crate) fn deliver(
+    ctx: &JobContext,
+    delivery_id: i64,
+    last_attempt: bool,
+) -> Result<(), String> {
+    let repo = &ctx.webhook_repo;
+    let delivery = repo.delivery(delivery_id).map_err(|err| err.to_string())?;
+    // deliveries go with their webhook when it's removed
+    let delivery = match delivery {
+        Some(delivery) => delivery,
+        None => return Ok(()),
+    };
+    let webhook = match repo
+        .webhook(delivery.webhook_id)
+        .map_err(|err| err.to_string())?
+    {
+        Some(webhook) => webhook,
+        None => return Ok(()),
+    };
+
+    let body = body(&delivery);
+    let timestamp = chrono::Utc::now().timestamp();
+    let headers = [
+        (SIGNATURE_HEADER, sign(&webhook.secret, timestamp, &body)),
+        (TIMESTAMP_HEADER, timestamp.to_string()),
+        (EVENT_HEADER, delivery.event.clone()),
+        (DELIVERY_HEADER, delivery.id.to_string()),
+    ];
+    let (response_status, error) = match ctx.webhook_sender.post(&webhook.url, &headers, body) {
+        Ok(status) if (200..300).contains(&status) => (Some(status), None),
+        Ok(status) => (Some(status), Some(format!("endpoint responded {}", status))),
+        Err(err) => (None, Some(err)),
+    };
+    let status = match (&error, last_attempt) {
+        (None, _) => DELIVERED,
+        (Some(_), false) => PENDING,
+        (Some(_), true) => FAILED,
+    };
+    repo.record_attempt(
+        delivery.id,
+        status,
+        response_status.map(i32::from),
+        error.as_deref(),
+    )
+    .map_err(|err| err.to_string())?;
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
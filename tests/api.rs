@@ -0,0 +1,1080 @@
+//! Drives the whole app, `SessionChecker` included, against the in-memory
+//! repositories, so none of this needs Postgres or redis running.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix_session::CookieSession;
+use actix_web::cookie::Cookie;
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App};
+use diesel::r2d2::Pool;
+use r2d2_redis::RedisConnectionManager;
+use serde_json::{json, Value};
+
+use fightingtinder::db::DBUser;
+use fightingtinder::expiry::MatchExpiry;
+use fightingtinder::jobs::{self, Job, JobContext, JobQueue, MemoryJobQueue};
+use fightingtinder::mail::{FileMailer, Mailer, MemoryMailer, Outbox};
+use fightingtinder::metrics::Metrics;
+use fightingtinder::pic_cache::PicCache;
+use fightingtinder::quota::{MemoryQuota, SuperLikeQuota, SUPER_LIKES_PER_DAY};
+use fightingtinder::recommend::Weights;
+use fightingtinder::repo::{
+    AdminRepository, Audit, MatchRepository, MemoryRepository, SwipeRepository, UserRepository,
+    WebhookRepository,
+};
+use fightingtinder::routes::{self, API_PREFIX};
+use fightingtinder::sessions::{MemorySessionStore, SessionStore};
+use fightingtinder::throttle::{LoginThrottle, MemoryThrottle};
+use fightingtinder::tokens::TokenIssuer;
+use fightingtinder::webhooks::{self, MemorySender, WebhookEvent, WebhookSender};
+
+const PASSWORD: &str = "correct horse battery staple";
+
+struct Backends {
+    repo: Arc<MemoryRepository>,
+    user_repo: Arc<dyn UserRepository>,
+    swipe_repo: Arc<dyn SwipeRepository>,
+    match_repo: Arc<dyn MatchRepository>,
+    webhook_repo: Arc<dyn WebhookRepository>,
+    admin_repo: Arc<dyn AdminRepository>,
+    session_store: Arc<dyn SessionStore>,
+    throttle: Arc<dyn LoginThrottle>,
+    quota: Arc<dyn SuperLikeQuota>,
+    job_queue: Arc<dyn JobQueue>,
+    rd_pool: Arc<Pool<RedisConnectionManager>>,
+    token_issuer: Arc<TokenIssuer>,
+    metrics: Arc<Metrics>,
+    mailer: Arc<MemoryMailer>,
+    outbox: Arc<Outbox>,
+}
+
+impl Backends {
+    fn new() -> Self {
+        let repo = Arc::new(MemoryRepository::new());
+        // nothing under test talks to redis, so the pool is never connected
+        let rd_pool = Arc::new(
+            Pool::builder()
+                .min_idle(Some(0))
+                .build_unchecked(RedisConnectionManager::new("redis://127.0.0.1").unwrap()),
+        );
+        let mailer = Arc::new(MemoryMailer::default());
+        let outbox = Arc::new(Outbox::new(mailer.clone(), "http://localhost".to_string()));
+        Backends {
+            user_repo: repo.clone(),
+            swipe_repo: repo.clone(),
+            match_repo: repo.clone(),
+            webhook_repo: repo.clone(),
+            admin_repo: repo.clone(),
+            repo,
+            session_store: Arc::new(MemorySessionStore::new()),
+            throttle: Arc::new(MemoryThrottle::new()),
+            quota: Arc::new(MemoryQuota::new()),
+            job_queue: Arc::new(MemoryJobQueue::new()),
+            rd_pool,
+            token_issuer: Arc::new(TokenIssuer::new(b"test secret")),
+            metrics: Arc::new(Metrics::new().unwrap()),
+            mailer,
+            outbox,
+        }
+    }
+
+    fn add_user(&self, username: &str) {
+        let password = bcrypt::hash(PASSWORD, 4).unwrap();
+        let email = format!("{}@example.com", username);
+        let user = DBUser::new(username.to_string(), password, Some(email));
+        UserRepository::create(&*self.repo, user).unwrap();
+    }
+
+    /// A user who has confirmed their email address.
+    fn add_verified_user(&self, username: &str) {
+        let password = bcrypt::hash(PASSWORD, 4).unwrap();
+        let email = format!("{}@example.com", username);
+        let user = DBUser::new(username.to_string(), password, Some(email)).verified();
+        UserRepository::create(&*self.repo, user).unwrap();
+    }
+
+    fn add_admin(&self, username: &str) {
+        let password = bcrypt::hash(PASSWORD, 4).unwrap();
+        let user = DBUser::new(username.to_string(), password, None).admin();
+        UserRepository::create(&*self.repo, user).unwrap();
+    }
+
+    fn job_context(
+        &self,
+        mailer: Arc<dyn Mailer>,
+        webhook_sender: Arc<dyn WebhookSender>,
+    ) -> JobContext {
+        JobContext {
+            user_repo: Arc::clone(&self.user_repo),
+            match_repo: Arc::clone(&self.match_repo),
+            outbox: Arc::new(Outbox::new(mailer, "http://localhost".to_string())),
+            webhook_repo: Arc::clone(&self.webhook_repo),
+            webhook_sender,
+            job_queue: Arc::clone(&self.job_queue),
+            match_expiry: Arc::new(MatchExpiry::default()),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+}
+
+macro_rules! init_app {
+    ($backends:expr) => {{
+        let b = &$backends;
+        test::init_service(
+            App::new()
+                .wrap(CookieSession::signed(&[0; 32]).secure(false))
+                .data(Arc::clone(&b.user_repo))
+                .data(Arc::clone(&b.swipe_repo))
+                .data(Arc::clone(&b.match_repo))
+                .data(Arc::clone(&b.webhook_repo))
+                .data(Arc::clone(&b.admin_repo))
+                .data(Arc::clone(&b.session_store))
+                .data(Arc::clone(&b.throttle))
+                .data(Arc::clone(&b.quota))
+                .data(Arc::clone(&b.job_queue))
+                .data(Arc::clone(&b.rd_pool))
+                .data(Arc::new(PicCache::new(Arc::clone(&b.rd_pool))))
+                .data(Arc::clone(&b.token_issuer))
+                .data(Arc::new(Weights::default()))
+                .data(Arc::new(MatchExpiry::default()))
+                .data(Arc::clone(&b.metrics))
+                .data(Arc::clone(&b.outbox))
+                .service(web::scope(API_PREFIX).configure(|cfg| {
+                    routes::configure(
+                        cfg,
+                        &b.user_repo,
+                        &b.session_store,
+                        &b.rd_pool,
+                        &b.token_issuer,
+                    )
+                })),
+        )
+        .await
+    }};
+}
+
+fn request(path: &str) -> test::TestRequest {
+    test::TestRequest::with_uri(&format!("{}{}", API_PREFIX, path))
+        .peer_addr("127.0.0.1:40000".parse().unwrap())
+}
+
+macro_rules! login {
+    ($app:expr, $username:expr, $password:expr) => {{
+        let req = request("/user/login")
+            .method(actix_web::http::Method::POST)
+            .set_json(&json!({ "username": $username, "password": $password }))
+            .to_request();
+        test::call_service(&mut $app, req).await
+    }};
+}
+
+macro_rules! session_cookie {
+    ($app:expr, $username:expr) => {{
+        let resp = login!($app, $username, PASSWORD);
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.response()
+            .cookies()
+            .find(|c| c.name() == "actix-session")
+            .expect("login should set the session cookie")
+            .into_owned()
+    }};
+}
+
+fn get(path: &str, cookie: &Cookie<'static>) -> test::TestRequest {
+    request(path).cookie(cookie.clone())
+}
+
+fn post(path: &str, cookie: &Cookie<'static>, body: Value) -> test::TestRequest {
+    request(path)
+        .method(actix_web::http::Method::POST)
+        .cookie(cookie.clone())
+        .set_json(&body)
+}
+
+fn delete(path: &str, cookie: &Cookie<'static>) -> test::TestRequest {
+    request(path)
+        .method(actix_web::http::Method::DELETE)
+        .cookie(cookie.clone())
+}
+
+/// `field` of every item in a page.
+fn names<'a>(page: &'a Value, field: &str) -> Vec<&'a str> {
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item[field].as_str().unwrap())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn routes_behind_session_checker_need_a_session() {
+    let backends = Backends::new();
+    let mut app = init_app!(backends);
+
+    let resp = test::call_service(&mut app, request("/user/manage/li").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn login_session_lasts_until_logout() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    let mut app = init_app!(backends);
+
+    // logins ignore the case of the username
+    let cookie = session_cookie!(app, "ALICE");
+
+    let user: Value =
+        test::read_response_json(&mut app, get("/user/manage/li", &cookie).to_request()).await;
+    assert_eq!(user["username"], "alice");
+
+    let resp = test::call_service(
+        &mut app,
+        post("/user/manage/bio", &cookie, json!({ "bio": "southpaw" })).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let user: Value =
+        test::read_response_json(&mut app, get("/user/manage/li", &cookie).to_request()).await;
+    assert_eq!(user["bio"], "southpaw");
+
+    let resp = test::call_service(&mut app, get("/user/logout", &cookie).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // the old cookie still carries the secret, but the session behind it is gone
+    let resp = test::call_service(&mut app, get("/user/manage/li", &cookie).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn failed_logins_are_throttled() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    let mut app = init_app!(backends);
+
+    let resp = login!(app, "alice", "wrong password");
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = login!(app, "alice", PASSWORD);
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().contains_key("retry-after"));
+}
+
+#[actix_rt::test]
+async fn emails_are_verified_through_the_mailed_link() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    backends.add_user("bob");
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let resp = test::call_service(
+        &mut app,
+        post(
+            "/user/manage/email",
+            &cookie,
+            json!({ "email": "bob@example.com" }),
+        )
+        .to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = test::call_service(
+        &mut app,
+        post(
+            "/user/manage/email",
+            &cookie,
+            json!({ "email": "alice@fight.club" }),
+        )
+        .to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let sent = backends.mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "alice@fight.club");
+    let token = sent[0]
+        .body
+        .split("token=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .expect("the email should carry a verification link");
+
+    let resp = test::call_service(&mut app, request("/user/verify?token=wrong").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(
+        &mut app,
+        request(&format!("/user/verify?token={}", token)).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(
+        &mut app,
+        post("/user/manage/email/resend", &cookie, json!({})).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // a link only works once
+    let resp = test::call_service(
+        &mut app,
+        request(&format!("/user/verify?token={}", token)).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn right_swipes_both_ways_make_a_match() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    backends.add_user("bob");
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    let bob = session_cookie!(app, "bob");
+
+    let swipe = json!({ "swiped": "bob", "status": true });
+    let resp =
+        test::call_service(&mut app, post("/swipe", &alice, swipe.clone()).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(&mut app, post("/swipe", &alice, swipe).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    assert_eq!(matches["items"], json!([]));
+
+    let swipe = json!({ "swiped": "alice", "status": true });
+    let resp = test::call_service(&mut app, post("/swipe", &bob, swipe).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    assert_eq!(names(&matches, "name"), ["bob"]);
+
+    let resp = test::call_service(&mut app, delete("/match/alice", &bob).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    assert_eq!(matches["items"], json!([]));
+}
+
+#[actix_rt::test]
+async fn left_swipes_never_match() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    backends.add_user("bob");
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    let bob = session_cookie!(app, "bob");
+
+    let swipe = json!({ "swiped": "bob", "status": true });
+    test::call_service(&mut app, post("/swipe", &alice, swipe).to_request()).await;
+    let swipe = json!({ "swiped": "alice", "status": false });
+    test::call_service(&mut app, post("/swipe", &bob, swipe).to_request()).await;
+
+    let matches: Value = test::read_response_json(&mut app, get("/match", &bob).to_request()).await;
+    assert_eq!(matches["items"], json!([]));
+}
+
+#[actix_rt::test]
+async fn search_pages_through_users() {
+    let backends = Backends::new();
+    for username in &["alice", "bob", "carol", "dave"] {
+        backends.add_user(username);
+    }
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let page: Value =
+        test::read_response_json(&mut app, get("/user?limit=3", &cookie).to_request()).await;
+    assert_eq!(names(&page, "username"), ["alice", "bob", "carol"]);
+    assert_eq!(page["next_cursor"], "carol");
+
+    let page: Value = test::read_response_json(
+        &mut app,
+        get("/user?limit=3&cursor=carol", &cookie).to_request(),
+    )
+    .await;
+    assert_eq!(page["items"][0]["username"], "dave");
+    assert_eq!(page["next_cursor"], Value::Null);
+}
+
+#[actix_rt::test]
+async fn locations_must_be_on_the_map() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let location = json!({ "lat": 95.0, "long": 10.0 });
+    let resp = test::call_service(
+        &mut app,
+        post("/user/manage/location", &cookie, location).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let location = json!({ "lat": 57.64911, "long": 10.40744 });
+    let resp = test::call_service(
+        &mut app,
+        post("/user/manage/location", &cookie, location).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let alice = backends.user_repo.find("alice").unwrap().unwrap();
+    assert_eq!(serde_json::to_value(&alice).unwrap()["lat"], 57.64911);
+}
+
+#[actix_rt::test]
+async fn distances_are_given_in_whole_km() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    backends.add_user("bob");
+    backends.user_repo.set_location("alice", 51.5, 0.0).unwrap();
+    // about 2.2km north
+    backends.user_repo.set_location("bob", 51.52, 0.0).unwrap();
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let page: Value =
+        test::read_response_json(&mut app, get("/user?prefix=bob", &cookie).to_request()).await;
+    assert_eq!(page["items"][0]["distance_km"], 3.0);
+}
+
+#[actix_rt::test]
+async fn swipe_candidates_only_show_the_public_profile() {
+    let backends = Backends::new();
+    backends.add_verified_user("alice");
+    backends.add_verified_user("bob");
+    backends.user_repo.set_location("alice", 51.5, 0.0).unwrap();
+    backends.user_repo.set_location("bob", 51.52, 0.0).unwrap();
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let page: Value =
+        test::read_response_json(&mut app, get("/swipe/available", &cookie).to_request()).await;
+    let bob = &page["items"][0];
+    assert_eq!(bob["username"], "bob");
+    assert_eq!(bob["distance_km"], 3.0);
+    for field in &["lat", "long", "geohash", "role", "email", "banned"] {
+        assert!(bob.get(field).is_none(), "{} should not be shown", field);
+    }
+}
+
+#[actix_rt::test]
+async fn only_admins_see_recommendation_scores() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let resp = test::call_service(&mut app, get("/swipe/available", &cookie).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(
+        &mut app,
+        get("/swipe/available?debug=true", &cookie).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn admin_changes_are_audited() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    backends.add_admin("root");
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    let root = session_cookie!(app, "root");
+
+    let resp = test::call_service(
+        &mut app,
+        post("/user/manage/bio", &alice, json!({ "bio": "southpaw" })).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(
+        &mut app,
+        delete("/admin/users/alice/bio", &alice).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = test::call_service(
+        &mut app,
+        delete("/admin/users/alice/bio", &root).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(
+        &mut app,
+        delete("/admin/users/nobody/bio", &root).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(
+        &mut app,
+        post(
+            "/admin/users/alice/suspend",
+            &root,
+            json!({ "hours": 11 * 365 * 24 }),
+        )
+        .to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let user: Value = test::read_body_json(
+        test::call_service(&mut app, get("/admin/users/alice", &root).to_request()).await,
+    )
+    .await;
+    assert_eq!(user["bio"], Value::Null);
+    let log: Value = test::read_body_json(
+        test::call_service(&mut app, get("/admin/audit", &root).to_request()).await,
+    )
+    .await;
+    assert_eq!(names(&log, "action"), ["clear_bio"]);
+    assert_eq!(names(&log, "target"), ["alice"]);
+    assert_eq!(names(&log, "admin"), ["root"]);
+}
+
+#[actix_rt::test]
+async fn banned_and_suspended_users_are_not_offered_for_swiping() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    let now = chrono::Utc::now().naive_utc();
+    let candidate = |username: &str| {
+        DBUser::new(username.to_string(), "hash".to_string(), None)
+            .verified()
+            .located(51.5, -0.1)
+    };
+    let users = [
+        candidate("bob"),
+        candidate("carol").banned(),
+        candidate("dave").suspended_until(now + chrono::Duration::days(1)),
+        candidate("erin").suspended_until(now - chrono::Duration::days(1)),
+    ];
+    for user in users {
+        UserRepository::create(&*backends.repo, user).unwrap();
+    }
+    let mut app = init_app!(backends);
+    let cookie = session_cookie!(app, "alice");
+
+    let page: Value =
+        test::read_response_json(&mut app, get("/swipe/available", &cookie).to_request()).await;
+    let mut offered = names(&page, "username");
+    offered.sort_unstable();
+    assert_eq!(offered, ["bob", "erin"]);
+}
+
+#[actix_rt::test]
+async fn likes_received_lists_unanswered_right_swipes() {
+    let backends = Backends::new();
+    for username in &["alice", "bob", "carol", "dave"] {
+        backends.add_user(username);
+    }
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    for (username, status) in &[("bob", true), ("carol", true), ("dave", false)] {
+        let cookie = session_cookie!(app, username);
+        let swipe = json!({ "swiped": "alice", "status": status });
+        test::call_service(&mut app, post("/swipe", &cookie, swipe).to_request()).await;
+    }
+
+    let page: Value = test::read_response_json(
+        &mut app,
+        get("/swipe/likes-received?limit=1", &alice).to_request(),
+    )
+    .await;
+    assert_eq!(page["items"][0]["username"], "bob");
+    assert_eq!(page["next_cursor"], "bob");
+    let page: Value = test::read_response_json(
+        &mut app,
+        get("/swipe/likes-received?limit=1&cursor=bob", &alice).to_request(),
+    )
+    .await;
+    assert_eq!(page["items"][0]["username"], "carol");
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    let swipe = json!({ "swiped": "bob", "status": true });
+    test::call_service(&mut app, post("/swipe", &alice, swipe).to_request()).await;
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    assert_eq!(names(&matches, "name"), ["bob"]);
+
+    let page: Value =
+        test::read_response_json(&mut app, get("/swipe/likes-received", &alice).to_request()).await;
+    assert_eq!(names(&page, "username"), ["carol"]);
+}
+
+#[actix_rt::test]
+async fn likes_from_banned_and_suspended_users_are_hidden() {
+    let backends = Backends::new();
+    for username in &["alice", "bob", "carol", "dave", "erin"] {
+        backends.add_user(username);
+    }
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    for username in &["bob", "carol", "dave", "erin"] {
+        let cookie = session_cookie!(app, username);
+        let swipe = json!({ "swiped": "alice", "status": true });
+        test::call_service(&mut app, post("/swipe", &cookie, swipe).to_request()).await;
+    }
+
+    let audit = Audit {
+        admin: "root",
+        action: "test",
+        details: None,
+    };
+    let now = chrono::Utc::now().naive_utc();
+    backends.admin_repo.ban("carol", &audit).unwrap();
+    backends
+        .admin_repo
+        .suspend("dave", now + chrono::Duration::days(1), &audit)
+        .unwrap();
+    backends
+        .admin_repo
+        .suspend("erin", now - chrono::Duration::days(1), &audit)
+        .unwrap();
+
+    let page: Value =
+        test::read_response_json(&mut app, get("/swipe/likes-received", &alice).to_request()).await;
+    assert_eq!(names(&page, "username"), ["bob", "erin"]);
+}
+
+#[actix_rt::test]
+async fn super_likes_are_capped_per_day_and_flagged() {
+    let backends = Backends::new();
+    let others = ["bob", "carol", "dave", "erin"];
+    assert_eq!(SUPER_LIKES_PER_DAY as usize, others.len() - 1);
+    backends.add_user("alice");
+    for username in &others {
+        backends.add_user(username);
+    }
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+
+    let super_like = |username: &str| json!({ "swiped": username, "kind": "super" });
+    let resp = test::call_service(
+        &mut app,
+        post("/swipe", &alice, super_like("bob")).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    // a swipe that isn't recorded doesn't use up a super-like
+    let resp = test::call_service(
+        &mut app,
+        post("/swipe", &alice, super_like("bob")).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    for username in &["carol", "dave"] {
+        let resp = test::call_service(
+            &mut app,
+            post("/swipe", &alice, super_like(username)).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let resp = test::call_service(
+        &mut app,
+        post("/swipe", &alice, super_like("erin")).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let bob = session_cookie!(app, "bob");
+    let page: Value =
+        test::read_response_json(&mut app, get("/swipe/likes-received", &bob).to_request()).await;
+    assert_eq!(page["items"][0]["username"], "alice");
+    assert_eq!(page["items"][0]["super_liked"], true);
+
+    let like = json!({ "swiped": "alice", "kind": "like" });
+    test::call_service(&mut app, post("/swipe", &bob, like).to_request()).await;
+    let matches: Value = test::read_response_json(&mut app, get("/match", &bob).to_request()).await;
+    assert_eq!(names(&matches, "name"), ["alice"]);
+}
+
+#[actix_rt::test]
+async fn inactive_matches_expire_unless_extended() {
+    let backends = Backends::new();
+    for username in &["alice", "bob", "carol"] {
+        backends.add_user(username);
+    }
+    backends.match_repo.create("alice", "bob").unwrap();
+    backends.match_repo.create("alice", "carol").unwrap();
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    let bob = session_cookie!(app, "bob");
+
+    let resp = test::call_service(
+        &mut app,
+        post("/match/alice/extend", &bob, json!({})).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = test::call_service(
+        &mut app,
+        post("/match/bob/extend", &alice, json!({})).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = test::call_service(
+        &mut app,
+        post("/match/nobody/extend", &alice, json!({})).to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // opening a match counts as activity on it
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    let carol_match = |matches: &Value| {
+        matches["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["name"] == "carol")
+            .unwrap()["expires_at"]
+            .clone()
+    };
+    let listed_expiry = carol_match(&matches);
+    let opened: Value =
+        test::read_response_json(&mut app, get("/match/carol", &alice).to_request()).await;
+    assert_eq!(opened["name"], "carol");
+    assert!(opened["expires_at"].as_str() > listed_expiry.as_str());
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    assert_eq!(carol_match(&matches), opened["expires_at"]);
+    let resp = test::call_service(&mut app, get("/match/nobody", &alice).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let expiry = MatchExpiry::days(1);
+    let now = chrono::Utc::now().naive_utc();
+    let sweep = |at| {
+        expiry
+            .sweep(&*backends.match_repo, &backends.metrics, at)
+            .unwrap()
+    };
+    assert_eq!(sweep(now), 0);
+    assert_eq!(sweep(now + chrono::Duration::days(2)), 2);
+    let matches: Value =
+        test::read_response_json(&mut app, get("/match", &alice).to_request()).await;
+    assert_eq!(matches["items"], json!([]));
+
+    let expired: Value =
+        test::read_response_json(&mut app, get("/match/expired", &alice).to_request()).await;
+    let mut expired_with = names(&expired, "name");
+    expired_with.sort_unstable();
+    assert_eq!(expired_with, ["bob", "carol"]);
+}
+
+#[test]
+fn expiry_sweeps_are_only_queued_when_none_is_pending() {
+    let backends = Backends::new();
+    let queue = &*backends.job_queue;
+    let first = queue.enqueue_once(&Job::ExpireMatches).unwrap();
+    assert!(first.is_some());
+    assert_eq!(queue.enqueue_once(&Job::ExpireMatches).unwrap(), None);
+    let claimed = queue.claim().unwrap().unwrap();
+    assert_eq!(queue.enqueue_once(&Job::ExpireMatches).unwrap(), None);
+    assert!(queue.complete(&claimed).unwrap());
+    assert!(queue.enqueue_once(&Job::ExpireMatches).unwrap() > first);
+}
+
+#[test]
+fn only_jobs_that_succeeded_are_pruned() {
+    let backends = Backends::new();
+    let queue = &*backends.job_queue;
+    let done = queue.enqueue(&Job::ExpireMatches).unwrap();
+    let dead = queue.enqueue(&Job::PruneJobs).unwrap();
+    let queued = queue.enqueue(&Job::ExpireMatches).unwrap();
+    queue.complete(&queue.claim().unwrap().unwrap()).unwrap();
+    queue
+        .fail(&queue.claim().unwrap().unwrap(), "broken", None)
+        .unwrap();
+
+    let now = chrono::Utc::now().naive_utc();
+    assert_eq!(queue.prune(now - chrono::Duration::days(1)).unwrap(), 0);
+    assert_eq!(queue.prune(now + chrono::Duration::days(1)).unwrap(), 1);
+    assert!(queue.find(done).unwrap().is_none());
+    assert!(queue.find(dead).unwrap().is_some());
+    assert!(queue.find(queued).unwrap().is_some());
+}
+
+#[test]
+fn outcomes_from_an_old_claim_are_ignored() {
+    let backends = Backends::new();
+    let queue = &*backends.job_queue;
+    let id = queue.enqueue(&Job::ExpireMatches).unwrap();
+    let first = queue.claim().unwrap().unwrap();
+    let earlier = chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1);
+    assert!(queue.fail(&first, "timed out", Some(earlier)).unwrap());
+    let second = queue.claim().unwrap().unwrap();
+
+    // the first worker finishing late doesn't touch the second one's claim
+    assert!(!queue.complete(&first).unwrap());
+    assert!(!queue.fail(&first, "late", None).unwrap());
+    let job = serde_json::to_value(queue.find(id).unwrap().unwrap()).unwrap();
+    assert_eq!(job["state"], jobs::RUNNING);
+    assert_eq!(job["attempts"], 2);
+
+    assert!(queue.complete(&second).unwrap());
+    let job = serde_json::to_value(queue.find(id).unwrap().unwrap()).unwrap();
+    assert_eq!(job["state"], jobs::DONE);
+}
+
+#[actix_rt::test]
+async fn matches_queue_emails_that_are_retried_on_failure() {
+    let backends = Backends::new();
+    backends.add_verified_user("alice");
+    backends.add_verified_user("bob");
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    let bob = session_cookie!(app, "bob");
+    // swipes queue webhook events as well, so only match emails are counted
+    let emails = |state| {
+        let found = backends.job_queue.list(Some(state), None, 50).unwrap();
+        let found = serde_json::to_value(found).unwrap();
+        found
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|job| job["kind"] == "notify_match")
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let swipe = json!({ "swiped": "bob", "kind": "like" });
+    let resp = test::call_service(&mut app, post("/swipe", &alice, swipe).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(emails(jobs::QUEUED).is_empty());
+    let swipe = json!({ "swiped": "alice", "kind": "like" });
+    let resp = test::call_service(&mut app, post("/swipe", &bob, swipe).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(emails(jobs::QUEUED).len(), 2);
+
+    let mailer = Arc::new(MemoryMailer::default());
+    let ctx = backends.job_context(mailer.clone(), Arc::new(MemorySender::responding(200)));
+    while mailer.sent().is_empty() {
+        assert!(jobs::run_next(&*backends.job_queue, &ctx).unwrap());
+    }
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "bob@example.com");
+    assert_eq!(sent[0].subject, "You matched with alice");
+
+    // a directory under a file can never be created, so sending fails
+    let broken = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml/mail");
+    let ctx = backends.job_context(
+        Arc::new(FileMailer::new(broken)),
+        Arc::new(MemorySender::responding(200)),
+    );
+    while emails(jobs::QUEUED)[0]["attempts"] == 0 {
+        assert!(jobs::run_next(&*backends.job_queue, &ctx).unwrap());
+    }
+    // the failed job waits out its back-off before it's due again
+    assert!(!jobs::run_next(&*backends.job_queue, &ctx).unwrap());
+
+    assert_eq!(emails(jobs::DONE).len(), 1);
+    let queued = emails(jobs::QUEUED);
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0]["attempts"], 1);
+    assert!(queued[0]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("failed to send email"));
+    assert_eq!(jobs::backoff(1), chrono::Duration::seconds(30));
+    assert_eq!(jobs::backoff(3), chrono::Duration::seconds(120));
+    assert_eq!(jobs::backoff(20), chrono::Duration::hours(1));
+}
+
+#[actix_rt::test]
+async fn admins_manage_webhooks_and_their_members() {
+    let backends = Backends::new();
+    backends.add_user("alice");
+    backends.add_admin("root");
+    let mut app = init_app!(backends);
+    let root = session_cookie!(app, "root");
+
+    let hook = json!({ "url": "https://gym.example/hook", "events": ["match.created"] });
+    let registered: Value =
+        test::read_response_json(&mut app, post("/admin/webhooks", &root, hook).to_request()).await;
+    assert_eq!(registered["id"], 1);
+    assert!(registered["secret"].as_str().is_some());
+
+    let alice = json!({ "username": "alice" });
+    let nobody = json!({ "username": "nobody" });
+    for (path, member, status) in &[
+        ("/admin/webhooks/1/members", &alice, StatusCode::OK),
+        ("/admin/webhooks/1/members", &alice, StatusCode::OK),
+        ("/admin/webhooks/1/members", &nobody, StatusCode::NOT_FOUND),
+        ("/admin/webhooks/2/members", &alice, StatusCode::NOT_FOUND),
+    ] {
+        let req = post(path, &root, (*member).clone()).to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), *status);
+    }
+    let members: Value = test::read_response_json(
+        &mut app,
+        get("/admin/webhooks/1/members", &root).to_request(),
+    )
+    .await;
+    assert_eq!(names(&members, "username"), ["alice"]);
+
+    for (path, status) in &[
+        ("/admin/webhooks/1/members/alice", StatusCode::OK),
+        ("/admin/webhooks/1/members/alice", StatusCode::NOT_FOUND),
+        ("/admin/webhooks/1", StatusCode::OK),
+        ("/admin/webhooks/1", StatusCode::NOT_FOUND),
+    ] {
+        let req = delete(path, &root).to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), *status);
+    }
+
+    // adding alice twice is only audited once
+    let log: Value =
+        test::read_response_json(&mut app, get("/admin/audit", &root).to_request()).await;
+    assert_eq!(
+        names(&log, "action"),
+        [
+            "unregister_webhook",
+            "remove_webhook_member",
+            "add_webhook_member",
+            "register_webhook"
+        ]
+    );
+    assert!(names(&log, "target").iter().all(|target| *target == "1"));
+}
+
+#[actix_rt::test]
+async fn webhooks_get_signed_events_and_retry_failures() {
+    let backends = Backends::new();
+    for username in &["alice", "bob", "carol", "dave"] {
+        backends.add_user(username);
+    }
+    let audit = Audit {
+        admin: "root",
+        action: "test",
+        details: None,
+    };
+    // registered first, so these get ids 1 and 2
+    backends
+        .webhook_repo
+        .register(
+            "http://gym.example/hook",
+            "gym secret",
+            &[WebhookEvent::MatchCreated, WebhookEvent::SwipeLike],
+            &audit,
+        )
+        .unwrap();
+    backends
+        .webhook_repo
+        .register(
+            "http://other.example/hook",
+            "other secret",
+            &[WebhookEvent::MatchDeleted],
+            &audit,
+        )
+        .unwrap();
+    // each gym only hears about its own members
+    backends
+        .webhook_repo
+        .add_member(1, "alice", &audit)
+        .unwrap();
+    backends.webhook_repo.add_member(2, "bob", &audit).unwrap();
+    let mut app = init_app!(backends);
+    let alice = session_cookie!(app, "alice");
+    let bob = session_cookie!(app, "bob");
+    let carol = session_cookie!(app, "carol");
+
+    let swipe = json!({ "swiped": "dave", "kind": "like" });
+    test::call_service(&mut app, post("/swipe", &carol, swipe).to_request()).await;
+
+    let swipe = json!({ "swiped": "bob", "kind": "super" });
+    test::call_service(&mut app, post("/swipe", &alice, swipe).to_request()).await;
+    let swipe = json!({ "swiped": "alice", "kind": "like" });
+    test::call_service(&mut app, post("/swipe", &bob, swipe).to_request()).await;
+
+    let sender = Arc::new(MemorySender::responding(204));
+    let ctx = backends.job_context(Arc::new(MemoryMailer::default()), sender.clone());
+    while jobs::run_next(&*backends.job_queue, &ctx).unwrap() {}
+
+    let sent = sender.sent();
+    let events: Vec<Value> = sent
+        .iter()
+        .map(|s| serde_json::from_str(&s.body).unwrap())
+        .collect();
+    let names: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["swipe.like", "swipe.like", "match.created"]);
+    assert_eq!(
+        events[0]["data"],
+        json!({ "swiper": "alice", "swiped": "bob", "super_like": true })
+    );
+    assert_eq!(events[2]["data"], json!({ "usernames": ["alice", "bob"] }));
+    for s in &sent {
+        assert_eq!(s.url, "http://gym.example/hook");
+        let header = |name| &s.headers.iter().find(|(n, _)| *n == name).unwrap().1;
+        let timestamp = header(webhooks::TIMESTAMP_HEADER).parse().unwrap();
+        assert_eq!(
+            header(webhooks::SIGNATURE_HEADER),
+            &webhooks::sign("gym secret", timestamp, &s.body)
+        );
+    }
+    let delivered = backends.webhook_repo.deliveries(1, None, 10).unwrap();
+    let delivered = serde_json::to_value(delivered).unwrap();
+    assert_eq!(delivered.as_array().unwrap().len(), 3);
+    assert!(delivered
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|d| d["status"] == "delivered"));
+
+    // running an event's job again, as a retry after a failure part way
+    // through would, doesn't deliver it twice
+    let emitted = backends.job_queue.list(Some(jobs::DONE), None, 50).unwrap();
+    let emitted = serde_json::to_value(emitted).unwrap();
+    let emitted = emitted
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|job| job["kind"] == "emit_webhook" && job["payload"]["event"] == "match.created")
+        .unwrap();
+    let job: Job = serde_json::from_value(emitted["payload"].clone()).unwrap();
+    job.run(&ctx, emitted["id"].as_i64().unwrap(), false)
+        .unwrap();
+    while jobs::run_next(&*backends.job_queue, &ctx).unwrap() {}
+    assert_eq!(sender.sent().len(), 3);
+    assert_eq!(
+        backends.webhook_repo.deliveries(1, None, 10).unwrap().len(),
+        3
+    );
+
+    let resp = test::call_service(&mut app, delete("/match/alice", &bob).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let ctx = backends.job_context(
+        Arc::new(MemoryMailer::default()),
+        Arc::new(MemorySender::responding(500)),
+    );
+    while jobs::run_next(&*backends.job_queue, &ctx).unwrap() {}
+
+    let pending = backends.webhook_repo.deliveries(2, None, 10).unwrap();
+    let pending = serde_json::to_value(pending).unwrap();
+    assert_eq!(pending[0]["event"], "match.deleted");
+    assert_eq!(pending[0]["status"], "pending");
+    assert_eq!(pending[0]["attempts"], 1);
+    assert_eq!(pending[0]["response_status"], 500);
+    let retrying = backends
+        .job_queue
+        .list(Some(jobs::QUEUED), None, 10)
+        .unwrap();
+    let retrying = serde_json::to_value(retrying).unwrap();
+    assert_eq!(retrying[0]["kind"], "deliver_webhook");
+}
@@ -0,0 +1,85 @@
+use std::thread;
+use std::time::Duration;
+
+use fightingtinder::breaker::CircuitBreaker;
+
+const COOLDOWN: Duration = Duration::from_millis(50);
+
+fn fail(breaker: &CircuitBreaker) -> Option<()> {
+    breaker.call(|| Err::<(), _>("down"))
+}
+
+fn succeed(breaker: &CircuitBreaker) -> Option<()> {
+    breaker.call(|| Ok::<_, String>(()))
+}
+
+#[test]
+fn opens_after_enough_failures_in_a_row() {
+    let breaker = CircuitBreaker::new("test", 3, COOLDOWN);
+    fail(&breaker);
+    fail(&breaker);
+    assert_eq!(breaker.state(), "closed");
+    fail(&breaker);
+    assert_eq!(breaker.state(), "open");
+
+    let mut called = false;
+    let result = breaker.call(|| {
+        called = true;
+        Ok::<_, String>(())
+    });
+    assert_eq!(result, None);
+    assert!(!called);
+}
+
+#[test]
+fn a_success_resets_the_failure_count() {
+    let breaker = CircuitBreaker::new("test", 3, COOLDOWN);
+    fail(&breaker);
+    fail(&breaker);
+    assert_eq!(succeed(&breaker), Some(()));
+    fail(&breaker);
+    fail(&breaker);
+    assert_eq!(breaker.state(), "closed");
+}
+
+#[test]
+fn lets_one_call_through_after_the_cooldown() {
+    let breaker = CircuitBreaker::new("test", 1, COOLDOWN);
+    fail(&breaker);
+    assert!(!breaker.allow());
+
+    thread::sleep(COOLDOWN);
+    assert!(breaker.allow());
+    assert_eq!(breaker.state(), "half_open");
+    // only the one trial call until it reports back
+    assert!(!breaker.allow());
+
+    breaker.record_success();
+    assert_eq!(breaker.state(), "closed");
+    assert!(breaker.allow());
+}
+
+#[test]
+fn a_failed_trial_call_opens_it_again() {
+    let breaker = CircuitBreaker::new("test", 3, COOLDOWN);
+    for _ in 0..3 {
+        fail(&breaker);
+    }
+    thread::sleep(COOLDOWN);
+    assert_eq!(fail(&breaker), None);
+    assert_eq!(breaker.state(), "open");
+    assert!(!breaker.allow());
+}
+
+#[test]
+fn a_trial_call_that_never_reports_back_is_retried() {
+    let breaker = CircuitBreaker::new("test", 1, COOLDOWN);
+    fail(&breaker);
+    thread::sleep(COOLDOWN);
+    assert!(breaker.allow());
+    assert!(!breaker.allow());
+
+    thread::sleep(COOLDOWN);
+    assert!(breaker.allow());
+    assert_eq!(breaker.state(), "half_open");
+}
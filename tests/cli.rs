@@ -0,0 +1,111 @@
+use serde_json::json;
+
+use fightingtinder::admin_cli::{parse, Command};
+use fightingtinder::cli::{split_args, Error, Output};
+
+#[test]
+fn split_args_separates_positionals_options_and_switches() {
+    let (positional, options, switches) = split_args(
+        &["alice", "--email", "a@example.com", "--admin", "extra"],
+        &["--email"],
+        &["--admin"],
+    )
+    .unwrap();
+    assert_eq!(positional, ["alice", "extra"]);
+    assert_eq!(options.get("--email"), Some(&"a@example.com"));
+    assert_eq!(switches, ["--admin"]);
+}
+
+#[test]
+fn split_args_refuses_unknown_flags_and_missing_values() {
+    assert!(matches!(
+        split_args(&["alice", "--force"], &[], &[]),
+        Err(Error::Usage)
+    ));
+    assert!(matches!(
+        split_args(&["alice", "--email"], &["--email"], &[]),
+        Err(Error::Usage)
+    ));
+}
+
+#[test]
+fn parses_each_command() {
+    assert_eq!(
+        parse(&[
+            "create-user",
+            "alice",
+            "--admin",
+            "--email",
+            "a@example.com"
+        ]),
+        Ok(Command::CreateUser {
+            username: "alice",
+            email: Some("a@example.com"),
+            admin: true,
+        })
+    );
+    assert_eq!(
+        parse(&["ban", "alice", "--reason", "spam"]),
+        Ok(Command::Ban {
+            username: "alice",
+            reason: Some("spam"),
+        })
+    );
+    assert_eq!(parse(&["unban", "alice"]), Ok(Command::Unban("alice")));
+    assert_eq!(
+        parse(&["purge-pic-cache"]),
+        Ok(Command::PurgePicCache(vec![]))
+    );
+    assert_eq!(
+        parse(&["purge-pic-cache", "alice", "bob"]),
+        Ok(Command::PurgePicCache(vec!["alice", "bob"]))
+    );
+    assert_eq!(parse(&["rebuild"]), Ok(Command::Rebuild));
+}
+
+#[test]
+fn bad_command_lines_show_the_usage() {
+    assert_eq!(parse(&[]), Err(Error::Usage));
+    assert_eq!(parse(&["explode"]), Err(Error::Usage));
+    assert_eq!(parse(&["ban"]), Err(Error::Usage));
+    assert_eq!(parse(&["ban", "alice", "bob"]), Err(Error::Usage));
+    // options only belong to the commands that take them
+    assert_eq!(
+        parse(&["unban", "alice", "--reason", "x"]),
+        Err(Error::Usage)
+    );
+    assert_eq!(parse(&["rebuild", "now"]), Err(Error::Usage));
+}
+
+fn output() -> Output {
+    Output::new(&["username", "banned", "email"])
+        .row(vec![json!("alice"), json!(false), json!(null)])
+        .row(vec![
+            json!("bartholomew"),
+            json!(true),
+            json!("b@example.com"),
+        ])
+}
+
+#[test]
+fn tables_align_columns_and_show_nulls_as_dashes() {
+    assert_eq!(
+        output().to_table(),
+        "username     banned  email\n\
+         alice        false   -\n\
+         bartholomew  true    b@example.com\n"
+    );
+    assert_eq!(Output::new(&["username"]).to_table(), "username\n");
+}
+
+#[test]
+fn json_has_an_object_per_row() {
+    assert_eq!(
+        output().to_json(),
+        json!([
+            { "username": "alice", "banned": false, "email": null },
+            { "username": "bartholomew", "banned": true, "email": "b@example.com" },
+        ])
+    );
+    assert_eq!(Output::new(&["username"]).to_json(), json!([]));
+}
@@ -0,0 +1,63 @@
+//! Checks the geohash cells the Postgres repository narrows location queries
+//! with, since missing a cell there would silently hide nearby users.
+
+use fightingtinder::geo;
+
+#[test]
+fn geohashes_match_the_standard_encoding() {
+    assert_eq!(geo::geohash((57.64911, 10.40744)), "u4pruydqq");
+    assert_eq!(geo::geohash((-25.382708, -49.265506)), "6gkzwgjzn");
+    assert_eq!(geo::geohash((0.0, 0.0)), "s00000000");
+}
+
+#[test]
+fn neighbourhoods_hold_every_point_within_reach() {
+    let centres = [
+        (51.5074, -0.1278),
+        (-33.8688, 151.2093),
+        (64.1466, -21.9426),
+        (0.0, 179.99),
+        (-89.9, 45.0),
+    ];
+    for &centre in &centres {
+        for (cells, reach_km) in geo::neighbourhoods(centre) {
+            // points spread in every direction, just within the reach
+            for step in 0..72 {
+                let bearing = (step as f64 * 5.0).to_radians();
+                let point = destination(centre, bearing, reach_km * 0.999);
+                let hash = geo::geohash(point);
+                assert!(
+                    cells.iter().any(|cell| hash.starts_with(cell.as_str())),
+                    "{:?} is {:.1}km from {:?} but not in {:?}",
+                    point,
+                    geo::haversine_km(centre, point),
+                    centre,
+                    cells
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn covering_is_the_smallest_neighbourhood_reaching_far_enough() {
+    let london = (51.5074, -0.1278);
+    let near = geo::covering(london, 1.0).unwrap();
+    let far = geo::covering(london, 500.0).unwrap();
+    assert!(near[0].len() > far[0].len());
+    assert!(near
+        .iter()
+        .any(|cell| geo::geohash(london).starts_with(cell.as_str())));
+    assert_eq!(geo::covering(london, 20_000.0), None);
+}
+
+/// The point `km` from `from` along the great circle heading `bearing`.
+fn destination(from: (f64, f64), bearing: f64, km: f64) -> (f64, f64) {
+    let (lat, long) = (from.0.to_radians(), from.1.to_radians());
+    let angle = km / 6371.0;
+    let lat2 = (lat.sin() * angle.cos() + lat.cos() * angle.sin() * bearing.cos()).asin();
+    let long2 = long
+        + (bearing.sin() * angle.sin() * lat.cos()).atan2(angle.cos() - lat.sin() * lat2.sin());
+    let long2 = (long2.to_degrees() + 540.0).rem_euclid(360.0) - 180.0;
+    (lat2.to_degrees(), long2)
+}
@@ -0,0 +1,28 @@
+use std::fs;
+
+use fightingtinder::mail::{Email, FileMailer, Mailer};
+
+#[test]
+fn file_mailer_keeps_every_email_inside_its_directory() {
+    let root = std::env::temp_dir().join(format!("fightingtinder-mail-{}", std::process::id()));
+    let dir = root.join("mail");
+    let mailer = FileMailer::new(dir.clone());
+    let email = Email {
+        to: "../../escaped@example.com".to_string(),
+        subject: "Hello".to_string(),
+        body: "Hi there".to_string(),
+    };
+    mailer.send(&email).unwrap();
+
+    let written: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(written.len(), 1);
+    let contents = fs::read_to_string(&written[0]).unwrap();
+    assert!(contents.starts_with("To: ../../escaped@example.com\n"));
+    // nothing was written next to the directory either
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+    fs::remove_dir_all(&root).unwrap();
+}
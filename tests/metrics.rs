@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use actix_web::dev::Service;
+use actix_web::{error, test, web, App, HttpResponse};
+
+use fightingtinder::metrics::{Metrics, RequestMetrics};
+
+#[actix_rt::test]
+async fn requests_are_counted_whether_handlers_succeed_or_fail() {
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let mut app = test::init_service(
+        App::new()
+            // fails the way middleware such as the session checker does, with
+            // an error rather than an error response
+            .wrap_fn(|req, srv| {
+                let fails = req.path() == "/fails";
+                let res = srv.call(req);
+                async move {
+                    if fails {
+                        return Err(error::ErrorConflict("already done"));
+                    }
+                    res.await
+                }
+            })
+            .wrap(RequestMetrics::new(Arc::clone(&metrics)))
+            .route("/ok", web::get().to(HttpResponse::Ok))
+            .route("/fails", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    test::call_service(&mut app, test::TestRequest::with_uri("/ok").to_request()).await;
+    let failed = app
+        .call(test::TestRequest::with_uri("/fails").to_request())
+        .await;
+    assert!(failed.is_err());
+
+    let exported = metrics.encode().unwrap();
+    let counted = |route: &str, status: &str| {
+        exported.contains(&format!(
+            "fightingtinder_http_requests_total{{method=\"GET\",route=\"{}\",status=\"{}\"}} 1",
+            route, status
+        ))
+    };
+    assert!(counted("/ok", "200"), "{}", exported);
+    assert!(counted("/fails", "409"), "{}", exported);
+    assert!(exported.contains(
+        "fightingtinder_http_request_duration_seconds_count{method=\"GET\",route=\"/fails\"} 1"
+    ));
+}
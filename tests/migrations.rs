@@ -0,0 +1,42 @@
+//! Checks the list `build.rs` embeds from `migrations/`.
+
+use fightingtinder::migrations::MIGRATIONS;
+
+#[test]
+fn versions_are_the_timestamps_diesel_records() {
+    for migration in MIGRATIONS {
+        let (timestamp, _) = migration.name.split_once('_').unwrap();
+        assert_eq!(migration.version, timestamp.replace('-', ""));
+        assert!(migration.version.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(migration.version.len(), 14, "{}", migration.name);
+    }
+
+    let username_rules = MIGRATIONS
+        .iter()
+        .find(|m| m.name == "2026-10-18-110000_username_rules")
+        .unwrap();
+    assert_eq!(username_rules.version, "20261018110000");
+}
+
+#[test]
+fn migrations_are_oldest_first() {
+    assert_eq!(MIGRATIONS[0].name, "00000000000000_diesel_initial_setup");
+    for pair in MIGRATIONS.windows(2) {
+        assert!(
+            pair[0].version < pair[1].version,
+            "{} should come after {}",
+            pair[0].name,
+            pair[1].name
+        );
+    }
+}
+
+#[test]
+fn every_migration_directory_is_embedded() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+    let on_disk = std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().join("up.sql").is_file())
+        .count();
+    assert_eq!(MIGRATIONS.len(), on_disk);
+}
@@ -0,0 +1,25 @@
+use fightingtinder::openapi::ApiDoc;
+use utoipa::OpenApi;
+
+const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+/// Keeps the checked in `openapi.json` in step with the handlers. Run with
+/// `UPDATE_OPENAPI=1` to regenerate it after changing a route or DTO.
+#[test]
+fn openapi_json_is_up_to_date() {
+    let generated = ApiDoc::openapi()
+        .to_pretty_json()
+        .expect("failed to jsonify the OpenAPI document")
+        + "\n";
+
+    if std::env::var_os("UPDATE_OPENAPI").is_some() {
+        std::fs::write(SNAPSHOT, &generated).expect("unable to write openapi.json");
+        return;
+    }
+
+    let checked_in = std::fs::read_to_string(SNAPSHOT).unwrap_or_default();
+    assert!(
+        checked_in == generated,
+        "openapi.json is out of date, regenerate it with `UPDATE_OPENAPI=1 cargo test --test openapi`"
+    );
+}
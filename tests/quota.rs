@@ -0,0 +1,33 @@
+//! Checks the daily super-like allowance, using the in-memory quota that
+//! follows the same rules as the redis one.
+
+use chrono::Duration;
+
+use fightingtinder::quota::{self, MemoryQuota, SuperLikeQuota, SUPER_LIKES_PER_DAY};
+
+#[test]
+fn each_day_has_its_own_allowance() {
+    let quota = MemoryQuota::new();
+    let today = quota::today();
+    for _ in 0..SUPER_LIKES_PER_DAY {
+        assert!(quota.take("alice", today).unwrap());
+    }
+    assert!(!quota.take("alice", today).unwrap());
+    assert!(quota.take("bob", today).unwrap());
+    assert!(quota.take("alice", today + Duration::days(1)).unwrap());
+}
+
+#[test]
+fn giving_back_after_midnight_returns_it_to_the_day_it_was_taken() {
+    let quota = MemoryQuota::new();
+    let yesterday = quota::today() - Duration::days(1);
+    let today = quota::today();
+    for _ in 0..SUPER_LIKES_PER_DAY {
+        assert!(quota.take("alice", yesterday).unwrap());
+        assert!(quota.take("alice", today).unwrap());
+    }
+
+    quota.give_back("alice", yesterday).unwrap();
+    assert!(!quota.take("alice", today).unwrap());
+    assert!(quota.take("alice", yesterday).unwrap());
+}
@@ -0,0 +1,184 @@
+//! Checks how recommendations are scored and ordered, and how the weights
+//! set through `RECOMMENDATION_WEIGHTS` are parsed.
+
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde_json::Value;
+
+use fightingtinder::db::DBUser;
+use fightingtinder::recommend::{rank, Signals, Weights};
+use fightingtinder::repo::Received;
+
+const LONDON: (f64, f64) = (51.5074, -0.1278);
+
+fn now() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2026, 10, 18)
+        .and_then(|day| day.and_hms_opt(12, 0, 0))
+        .unwrap()
+}
+
+fn user(username: &str) -> DBUser {
+    DBUser::new(username.to_string(), "hash".to_string(), None)
+}
+
+fn no_signals() -> Signals {
+    Signals {
+        received: HashMap::new(),
+        liked_by_similar: HashMap::new(),
+    }
+}
+
+/// Each candidate's username and score, in the order they were ranked.
+fn ranked(
+    caller: &DBUser,
+    candidates: Vec<DBUser>,
+    signals: &Signals,
+    weights: &Weights,
+) -> Vec<(String, Value)> {
+    rank(caller, candidates, signals, weights, now())
+        .into_iter()
+        .map(|(candidate, score)| {
+            let score = serde_json::to_value(score).unwrap();
+            (candidate.username().to_string(), score)
+        })
+        .collect()
+}
+
+fn assert_close(actual: &Value, expected: f64) {
+    let actual = actual.as_f64().unwrap();
+    assert!(
+        (actual - expected).abs() < 1e-3,
+        "expected {}, got {}",
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn weights_left_out_keep_their_defaults() {
+    assert_eq!("".parse::<Weights>().unwrap(), Weights::default());
+    let weights: Weights = " distance=0.5 , collaborative=0,".parse().unwrap();
+    assert_eq!(
+        weights,
+        Weights {
+            distance: 0.5,
+            collaborative: 0.0,
+            ..Weights::default()
+        }
+    );
+}
+
+#[test]
+fn bad_weights_are_rejected() {
+    assert_eq!(
+        "speed=1".parse::<Weights>(),
+        Err("unknown signal `speed`".to_string())
+    );
+    assert_eq!(
+        "distance".parse::<Weights>(),
+        Err("expected signal=weight, found `distance`".to_string())
+    );
+    for weight in &["-1", "NaN", "inf", "far"] {
+        assert_eq!(
+            format!("rating={}", weight).parse::<Weights>(),
+            Err("weight for rating must be a number >= 0".to_string())
+        );
+    }
+}
+
+#[test]
+fn signals_score_between_0_and_1() {
+    let caller = user("caller")
+        .located(LONDON.0, LONDON.1)
+        .with_discipline("judo");
+    // about 10km due north
+    let candidate = user("candidate")
+        .located(LONDON.0 + 10.0 / 111.195, LONDON.1)
+        .with_discipline("judo")
+        .active_at(now() - Duration::days(3));
+    let mut signals = no_signals();
+    signals.received.insert(
+        "caller".to_string(),
+        Received {
+            right: 8,
+            total: 10,
+        },
+    );
+    signals.liked_by_similar.insert("candidate".to_string(), 2);
+    signals.liked_by_similar.insert("someone".to_string(), 4);
+
+    let ranked = ranked(&caller, vec![candidate], &signals, &Weights::default());
+    let score = &ranked[0].1;
+    assert_close(&score["distance"], 0.5);
+    assert_close(&score["preference"], 1.0);
+    // 9/12 for the caller against a half for a candidate with no swipes
+    assert_close(&score["rating"], 0.75);
+    assert_close(&score["activity"], 0.5);
+    assert_close(&score["collaborative"], 0.5);
+    assert_close(
+        &score["total"],
+        (3.0 * 0.5 + 1.0 + 0.75 + 0.5 + 2.0 * 0.5) / 8.0,
+    );
+}
+
+#[test]
+fn unknowns_score_as_neutral_or_nothing() {
+    let caller = user("caller").with_discipline("judo");
+    let differs = user("differs").with_discipline("boxing");
+    let unset = user("unset");
+
+    let ranked = ranked(
+        &caller,
+        vec![differs, unset],
+        &no_signals(),
+        &Weights::default(),
+    );
+    let scores: HashMap<_, _> = ranked.into_iter().collect();
+    assert_close(&scores["differs"]["preference"], 0.0);
+    assert_close(&scores["unset"]["preference"], 0.5);
+    for score in scores.values() {
+        assert_close(&score["distance"], 0.0);
+        assert_close(&score["rating"], 1.0);
+        assert_close(&score["activity"], 0.0);
+        assert_close(&score["collaborative"], 0.0);
+    }
+}
+
+#[test]
+fn candidates_are_ordered_by_total_then_username() {
+    let caller = user("caller").located(LONDON.0, LONDON.1);
+    let far = user("far").located(LONDON.0 + 1.0, LONDON.1);
+    let near_b = user("near_b").located(LONDON.0, LONDON.1);
+    let near_a = user("near_a").located(LONDON.0, LONDON.1);
+    let weights = Weights {
+        distance: 1.0,
+        preference: 0.0,
+        rating: 0.0,
+        activity: 0.0,
+        collaborative: 0.0,
+    };
+
+    let ranked = ranked(&caller, vec![far, near_b, near_a], &no_signals(), &weights);
+    let order: Vec<_> = ranked.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(order, ["near_a", "near_b", "far"]);
+    // with one signal weighted, the total is just that signal
+    assert_eq!(ranked[2].1["total"], ranked[2].1["distance"]);
+}
+
+#[test]
+fn zero_weights_give_every_candidate_a_zero_total() {
+    let caller = user("caller").located(LONDON.0, LONDON.1);
+    let near = user("b_near").located(LONDON.0, LONDON.1);
+    let far = user("a_far").located(LONDON.0 + 1.0, LONDON.1);
+    let weights: Weights = "distance=0,preference=0,rating=0,activity=0,collaborative=0"
+        .parse()
+        .unwrap();
+
+    let ranked = ranked(&caller, vec![near, far], &no_signals(), &weights);
+    let order: Vec<_> = ranked.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(order, ["a_far", "b_near"]);
+    for (_, score) in &ranked {
+        assert_close(&score["total"], 0.0);
+    }
+}
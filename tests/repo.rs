@@ -0,0 +1,80 @@
+//! Rules the in-memory repositories share with the Postgres ones, which the
+//! API tests rely on.
+
+use chrono::{Duration, Utc};
+
+use fightingtinder::db::{DBUser, SwipeKind};
+use fightingtinder::repo::{
+    MatchRepository, MemoryRepository, RepoError, SwipeRepository, UserRepository,
+};
+
+fn repo_with(usernames: &[&str]) -> MemoryRepository {
+    let repo = MemoryRepository::new();
+    for username in usernames {
+        let user = DBUser::new(username.to_string(), "hash".to_string(), None);
+        UserRepository::create(&repo, user).unwrap();
+    }
+    repo
+}
+
+#[test]
+fn usernames_are_taken_ignoring_case() {
+    let repo = repo_with(&["alice"]);
+    let user = DBUser::new("Alice".to_string(), "hash".to_string(), None);
+    assert!(matches!(
+        UserRepository::create(&repo, user),
+        Err(RepoError::Conflict("username"))
+    ));
+}
+
+#[test]
+fn matching_twice_is_a_conflict() {
+    let repo = repo_with(&["alice", "bob"]);
+    MatchRepository::create(&repo, "alice", "bob").unwrap();
+    assert!(matches!(
+        MatchRepository::create(&repo, "bob", "alice"),
+        Err(RepoError::Conflict("match"))
+    ));
+}
+
+#[test]
+fn updating_a_missing_user_fails() {
+    let repo = repo_with(&["alice"]);
+    assert!(repo.set_bio("alice", "hi".to_string()).is_ok());
+    assert!(matches!(
+        repo.set_bio("nobody", "hi".to_string()),
+        Err(RepoError::Backend(_))
+    ));
+    assert!(repo.set_location("nobody", 0.0, 0.0).is_err());
+    assert!(repo.touch("nobody").is_err());
+}
+
+#[test]
+fn banned_and_suspended_super_likers_are_left_out() {
+    let repo = repo_with(&["alice"]);
+    let now = Utc::now().naive_utc();
+    let liker = |username: &str| {
+        DBUser::new(username.to_string(), "hash".to_string(), None)
+            .verified()
+            .located(51.5, -0.1)
+    };
+    let users = [
+        liker("bob"),
+        liker("carol").banned(),
+        liker("dave").suspended_until(now + Duration::days(1)),
+        liker("erin").suspended_until(now - Duration::days(1)),
+    ];
+    for user in users {
+        let username = user.username().to_string();
+        UserRepository::create(&repo, user).unwrap();
+        repo.record(&username, "alice", SwipeKind::Super).unwrap();
+    }
+
+    let found: Vec<_> = repo
+        .super_likers("alice", 10)
+        .unwrap()
+        .iter()
+        .map(|user| user.username().to_string())
+        .collect();
+    assert_eq!(found, ["bob", "erin"]);
+}
@@ -0,0 +1,62 @@
+//! Checks the delays `MemoryThrottle` hands out, which follow the same rules
+//! as the redis throttle the server uses.
+
+use std::time::Duration;
+
+use fightingtinder::throttle::{LoginThrottle, MemoryThrottle};
+
+const IP: &str = "203.0.113.7";
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+fn wait(throttle: &MemoryThrottle, username: &str, ip: &str) -> Duration {
+    throttle
+        .check(username, ip)
+        .unwrap()
+        .unwrap_or(Duration::from_secs(0))
+}
+
+#[test]
+fn delays_double_after_each_failure() {
+    let throttle = MemoryThrottle::new();
+    assert_eq!(throttle.check("alice", IP).unwrap(), None);
+
+    for expected_secs in [1, 2, 4, 8] {
+        throttle.record_failure("alice", IP).unwrap();
+        let wait = wait(&throttle, "alice", IP);
+        assert!(wait <= Duration::from_secs(expected_secs));
+        assert!(wait > Duration::from_secs(expected_secs) / 2);
+    }
+}
+
+#[test]
+fn usernames_are_locked_out_at_their_limit() {
+    let throttle = MemoryThrottle::new();
+    for _ in 0..5 {
+        throttle.record_failure("alice", IP).unwrap();
+    }
+    assert!(wait(&throttle, "alice", "198.51.100.1") > LOCKOUT - Duration::from_secs(5));
+}
+
+#[test]
+fn addresses_are_allowed_more_failures_before_a_lockout() {
+    let throttle = MemoryThrottle::new();
+    // a different username each time, so only the address is counted up
+    for n in 0..19 {
+        throttle.record_failure(&format!("user{}", n), IP).unwrap();
+    }
+    let wait_before = wait(&throttle, "someone", IP);
+    assert!(wait_before > Duration::from_secs(0));
+    assert!(wait_before <= Duration::from_secs(60));
+
+    throttle.record_failure("user19", IP).unwrap();
+    assert!(wait(&throttle, "someone", IP) > LOCKOUT - Duration::from_secs(5));
+}
+
+#[test]
+fn logging_in_clears_the_username_but_not_the_address() {
+    let throttle = MemoryThrottle::new();
+    throttle.record_failure("alice", IP).unwrap();
+    throttle.record_success("alice").unwrap();
+    assert!(wait(&throttle, "alice", "198.51.100.1") == Duration::from_secs(0));
+    assert!(wait(&throttle, "bob", IP) > Duration::from_secs(0));
+}
@@ -0,0 +1,62 @@
+use fightingtinder::username::{normalize, UsernameError, MAX_LENGTH, MIN_LENGTH};
+
+#[test]
+fn plain_names_are_kept_as_typed() {
+    assert_eq!(normalize("Alice_99"), Ok("Alice_99".to_string()));
+    assert_eq!(normalize("  bob-smith  "), Ok("bob-smith".to_string()));
+}
+
+#[test]
+fn lookalikes_fold_to_ascii() {
+    // fullwidth letters and digits
+    assert_eq!(normalize("ａｌｉｃｅ１"), Ok("alice1".to_string()));
+    // the "fi" ligature
+    assert_eq!(normalize("ﬁghter"), Ok("fighter".to_string()));
+}
+
+#[test]
+fn lengths_are_counted_after_normalising() {
+    assert_eq!(
+        normalize(&"a".repeat(MIN_LENGTH - 1)),
+        Err(UsernameError::TooShort)
+    );
+    assert_eq!(
+        normalize(&"a".repeat(MIN_LENGTH)),
+        Ok("a".repeat(MIN_LENGTH))
+    );
+    assert_eq!(
+        normalize(&"a".repeat(MAX_LENGTH)),
+        Ok("a".repeat(MAX_LENGTH))
+    );
+    assert_eq!(
+        normalize(&"a".repeat(MAX_LENGTH + 1)),
+        Err(UsernameError::TooLong)
+    );
+    // surrounding whitespace doesn't count
+    assert_eq!(normalize("  ab  "), Err(UsernameError::TooShort));
+}
+
+#[test]
+fn only_letters_digits_underscores_and_dashes_are_allowed() {
+    assert_eq!(
+        normalize("al ice"),
+        Err(UsernameError::InvalidCharacter(' '))
+    );
+    assert_eq!(
+        normalize("../etc"),
+        Err(UsernameError::InvalidCharacter('.'))
+    );
+    assert_eq!(normalize("zoë"), Err(UsernameError::InvalidCharacter('ë')));
+    assert_eq!(normalize("_alice"), Err(UsernameError::InvalidStart));
+    assert_eq!(normalize("-alice"), Err(UsernameError::InvalidStart));
+}
+
+#[test]
+fn reserved_names_are_refused_in_any_case() {
+    assert_eq!(normalize("admin"), Err(UsernameError::Reserved));
+    assert_eq!(normalize("ADMIN"), Err(UsernameError::Reserved));
+    assert_eq!(normalize("ａｄｍｉｎ"), Err(UsernameError::Reserved));
+    assert_eq!(normalize("admin2"), Ok("admin2".to_string()));
+    // would be shadowed by `/match/expired`
+    assert_eq!(normalize("Expired"), Err(UsernameError::Reserved));
+}